@@ -0,0 +1,121 @@
+// 板上的TMP451温度传感器，只在打开board-sensors功能、而且设备树中有这个传感器时使用
+//
+// TMP451接在I2C0上，本地通道测量板子的温度，远端通道测量FU740芯片内的测温二极管。
+// 启动时读一次并打印，S层通过专有SBI调用读到的也是这次的结果：进入S层以后，
+// S层的驱动可能正在使用同一条I2C总线，M层不能再去访问它
+use crate::console::println;
+use crate::peripheral::{I2c, I2cError};
+use core::sync::atomic::{AtomicBool, AtomicIsize, Ordering};
+
+// 通道0是本地通道，1是远端通道
+const CHANNEL_NAMES: [&str; 2] = ["board", "FU740"];
+
+// TMP451的寄存器，温度的高字节是整数部分，低字节的高4位是1/16度
+const REG_LOCAL_HIGH: u8 = 0x00;
+const REG_REMOTE_HIGH: u8 = 0x01;
+const REG_CONFIG: u8 = 0x03;
+const REG_REMOTE_LOW: u8 = 0x10;
+const REG_LOCAL_LOW: u8 = 0x15;
+const REG_MANUFACTURER_ID: u8 = 0xFE;
+const MANUFACTURER_ID_TI: u8 = 0x55;
+// 扩展温度范围下读数要减去64度
+const CONFIG_EXTENDED_RANGE: u8 = 1 << 2;
+const EXTENDED_RANGE_OFFSET: isize = 64;
+
+// 没有读数时的值
+const NO_READING: isize = isize::MIN;
+
+static PRESENT: AtomicBool = AtomicBool::new(false);
+// 各个通道的温度，单位是千分之一摄氏度
+static TEMPERATURES: [AtomicIsize; 2] =
+    [AtomicIsize::new(NO_READING), AtomicIsize::new(NO_READING)];
+
+/// 读出两个通道的温度并打印；读不到的通道只打印警告，不影响启动
+pub fn init(i2c_base: usize, address: u8) {
+    PRESENT.store(true, Ordering::Relaxed);
+    let i2c = unsafe { I2c::new(i2c_base) };
+    let extended = match check_sensor(&i2c, address) {
+        Ok(extended) => extended,
+        Err(e) => {
+            println!(
+                "[rustsbi] warning: temperature sensor at i2c {:#x} not readable, {:?}",
+                address, e
+            );
+            return;
+        }
+    };
+    let registers = [
+        (REG_LOCAL_HIGH, REG_LOCAL_LOW),
+        (REG_REMOTE_HIGH, REG_REMOTE_LOW),
+    ];
+    for (channel, &(high, low)) in registers.iter().enumerate() {
+        match read_temperature(&i2c, address, high, low, extended) {
+            Ok(value) => {
+                TEMPERATURES[channel].store(value, Ordering::Relaxed);
+                let sign = if value < 0 { "-" } else { "" };
+                println!(
+                    "[rustsbi] {} temperature: {}{}.{:03} C",
+                    CHANNEL_NAMES[channel],
+                    sign,
+                    value.abs() / 1000,
+                    value.abs() % 1000
+                );
+            }
+            Err(e) => println!(
+                "[rustsbi] warning: reading {} temperature failed, {:?}",
+                CHANNEL_NAMES[channel], e
+            ),
+        }
+    }
+}
+
+/// 设备树中是否有温度传感器
+pub fn present() -> bool {
+    PRESENT.load(Ordering::Relaxed)
+}
+
+/// 启动时读到的温度，单位是千分之一摄氏度；通道不存在或者没有读到时返回None
+pub fn temperature(channel: usize) -> Option<isize> {
+    let value = TEMPERATURES.get(channel)?.load(Ordering::Relaxed);
+    if value == NO_READING {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+// 检查厂商编号，返回传感器是否工作在扩展温度范围
+fn check_sensor(i2c: &I2c, address: u8) -> Result<bool, I2cError> {
+    let mut id = [0];
+    i2c.read_registers(address, REG_MANUFACTURER_ID, &mut id)?;
+    if id[0] != MANUFACTURER_ID_TI {
+        println!(
+            "[rustsbi] warning: unexpected temperature sensor manufacturer id {:#x}",
+            id[0]
+        );
+    }
+    let mut config = [0];
+    i2c.read_registers(address, REG_CONFIG, &mut config)?;
+    Ok(config[0] & CONFIG_EXTENDED_RANGE != 0)
+}
+
+// 先读高字节：远端通道读高字节时会锁存低字节，两次读出的是同一次转换的结果
+fn read_temperature(
+    i2c: &I2c,
+    address: u8,
+    high: u8,
+    low: u8,
+    extended: bool,
+) -> Result<isize, I2cError> {
+    let mut high_byte = [0];
+    let mut low_byte = [0];
+    i2c.read_registers(address, high, &mut high_byte)?;
+    i2c.read_registers(address, low, &mut low_byte)?;
+    let mut degrees = high_byte[0] as isize;
+    if extended {
+        degrees -= EXTENDED_RANGE_OFFSET;
+    }
+    let sixteenths = degrees * 16 + (low_byte[0] >> 4) as isize;
+    // 1/16度是62.5千分之一度
+    Ok(sixteenths * 125 / 2)
+}
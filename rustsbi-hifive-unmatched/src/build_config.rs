@@ -0,0 +1,70 @@
+// 固件的编译配置，S层通过专有SBI调用按编号读出，用于按功能调整测试和排查问题
+//
+// | 编号 | 内容 |
+// |:---|:---|
+// | 0 | 打开的Cargo功能，位的编号见下面的FEATURE_* |
+// | 1 | 启动时堆的字节数，不包括heap-extension扩展的部分 |
+// | 2 | 每个核的M层栈的字节数 |
+// | 3 | 内置设备树的字节数，没有内置设备树时为0 |
+const FIELD_FEATURES: usize = 0;
+const FIELD_HEAP_SIZE: usize = 1;
+const FIELD_STACK_SIZE: usize = 2;
+const FIELD_EMBEDDED_DTB_SIZE: usize = 3;
+
+// 按Cargo.toml中的顺序编号，新的功能追加在最后
+const FEATURE_VECTORED_TRAP: usize = 1 << 0;
+const FEATURE_CONSOLE_MIRROR: usize = 1 << 1;
+const FEATURE_TRAP_PROFILE: usize = 1 << 2;
+const FEATURE_DUMP_DEVICE_TREE: usize = 1 << 3;
+const FEATURE_STACK_WATERMARK: usize = 1 << 4;
+const FEATURE_MEMORY_TEST: usize = 1 << 5;
+const FEATURE_BOARD_SENSORS: usize = 1 << 6;
+const FEATURE_TERMINAL_PROBE: usize = 1 << 7;
+const FEATURE_MONITOR: usize = 1 << 8;
+const FEATURE_HEAP_EXTENSION: usize = 1 << 9;
+const FEATURE_PANIC_INJECTION: usize = 1 << 10;
+const FEATURE_TIME_SCALE: usize = 1 << 11;
+const FEATURE_ORDERED_HARTS: usize = 1 << 12;
+const FEATURE_INIT_FAULTS: usize = 1 << 13;
+const FEATURE_UART_INIT_FAULTS: usize = 1 << 14;
+
+const FEATURES: [(usize, bool); 15] = [
+    (FEATURE_VECTORED_TRAP, cfg!(feature = "vectored-trap")),
+    (FEATURE_CONSOLE_MIRROR, cfg!(feature = "console-mirror")),
+    (FEATURE_TRAP_PROFILE, cfg!(feature = "trap-profile")),
+    (FEATURE_DUMP_DEVICE_TREE, cfg!(feature = "dump-device-tree")),
+    (FEATURE_STACK_WATERMARK, cfg!(feature = "stack-watermark")),
+    (FEATURE_MEMORY_TEST, cfg!(feature = "memory-test")),
+    (FEATURE_BOARD_SENSORS, cfg!(feature = "board-sensors")),
+    (FEATURE_TERMINAL_PROBE, cfg!(feature = "terminal-probe")),
+    (FEATURE_MONITOR, cfg!(feature = "monitor")),
+    (FEATURE_HEAP_EXTENSION, cfg!(feature = "heap-extension")),
+    (FEATURE_PANIC_INJECTION, cfg!(feature = "panic-injection")),
+    (FEATURE_TIME_SCALE, cfg!(feature = "time-scale")),
+    (FEATURE_ORDERED_HARTS, cfg!(feature = "ordered-harts")),
+    (FEATURE_INIT_FAULTS, cfg!(feature = "init-faults")),
+    (FEATURE_UART_INIT_FAULTS, cfg!(feature = "uart-init-faults")),
+];
+
+const fn feature_mask() -> usize {
+    let mut mask = 0;
+    let mut i = 0;
+    while i < FEATURES.len() {
+        if FEATURES[i].1 {
+            mask |= FEATURES[i].0;
+        }
+        i += 1;
+    }
+    mask
+}
+
+/// 读出一项配置，编号不存在时返回None
+pub fn read(field: usize) -> Option<usize> {
+    match field {
+        FIELD_FEATURES => Some(feature_mask()),
+        FIELD_HEAP_SIZE => Some(crate::SBI_HEAP_SIZE),
+        FIELD_STACK_SIZE => Some(crate::PER_HART_STACK_SIZE),
+        FIELD_EMBEDDED_DTB_SIZE => Some(crate::DEVICE_TREE.len()),
+        _ => None,
+    }
+}
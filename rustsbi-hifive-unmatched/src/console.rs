@@ -1,16 +1,81 @@
 use crate::peripheral::Uart;
-use crate::util::AmoMutex;
+use crate::util::{AmoMutex, AmoMutexGuard};
+use core::convert::Infallible;
 use core::fmt;
-use embedded_hal::serial::Write;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use embedded_hal::serial::{Read, Write};
 
-static STDOUT: AmoMutex<Option<Uart>> = AmoMutex::new(None);
+static STDOUT: AmoMutex<Option<MultiUart>> = AmoMutex::new(None);
+// panic时额外输出的调试串口，启动时就设置好，panic时不再需要初始化
+static PANIC_UART: AmoMutex<Option<Uart>> = AmoMutex::new(None);
+// 为true时固件自己的输出都被丢弃，panic和早期陷入的错误输出不受影响
+static QUIET: AtomicBool = AtomicBool::new(false);
 
-pub fn init_stdout(uart: Uart) {
+pub fn init_stdout(uart: MultiUart) {
     let mut lock = STDOUT.lock();
     *lock = Some(uart);
     drop(lock);
 }
 
+/// 控制台正在使用的串口
+pub fn stdout_uarts() -> Option<MultiUart> {
+    *STDOUT.lock()
+}
+
+/// 关闭固件的普通输出；S层通过SBI调用的输出不受影响
+#[allow(unused)]
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+/// 从主串口读一个字节，没有输入时返回None
+#[cfg(feature = "monitor")]
+pub fn getchar() -> Option<u8> {
+    let mut lock = STDOUT.lock();
+    let ans = lock.as_mut().and_then(|uarts| uarts.read().ok());
+    drop(lock);
+    ans
+}
+
+/// S层通过调试控制台扩展的输出，和legacy控制台一样不受quiet影响；返回写出的字节数
+pub fn write_bytes(bytes: &[u8]) -> usize {
+    let mut lock = STDOUT.lock();
+    let written = match lock.as_mut() {
+        Some(uarts) => {
+            for byte in bytes {
+                uarts.write_byte(*byte);
+            }
+            bytes.len()
+        }
+        None => 0,
+    };
+    drop(lock);
+    written
+}
+
+/// 从主串口读出已经收到的字节，不等待输入；返回读到的字节数
+pub fn read_bytes(buf: &mut [u8]) -> usize {
+    let mut lock = STDOUT.lock();
+    let mut len = 0;
+    if let Some(uarts) = lock.as_mut() {
+        while len < buf.len() {
+            match uarts.read() {
+                Ok(byte) => buf[len] = byte,
+                Err(_) => break,
+            }
+            len += 1;
+        }
+    }
+    drop(lock);
+    len
+}
+
+pub fn init_panic_uart(uart: Uart) {
+    let mut lock = PANIC_UART.lock();
+    *lock = Some(uart);
+    drop(lock);
+}
+
 impl fmt::Write for Uart {
     fn write_str(&mut self, s: &str) -> fmt::Result {
         for byte in s.as_bytes() {
@@ -21,9 +86,176 @@ impl fmt::Write for Uart {
     }
 }
 
+const MAX_CONSOLE_UARTS: usize = 2;
+// 发送队列满时最多等待的次数；超过后丢弃这个串口上的这个字节，以免一个慢速串口拖住其它串口
+const TX_SPIN_LIMIT: usize = 10_000;
+// 连续丢弃这么多个字节以后，认为这个串口的发送卡住了，尝试重新打开发送
+const TX_STUCK_DROPS: usize = 4;
+// 连续恢复失败这么多次以后不再尝试，之后发送队列满时直接丢弃字节，不再等待
+const TX_MAX_RECOVERIES: usize = 3;
+
+// 每个串口连续丢弃的字节数和连续恢复失败的次数。MultiUart每次输出时会被复制，状态不能放在它里面
+static TX_DROPPED: [AtomicUsize; MAX_CONSOLE_UARTS] = [AtomicUsize::new(0), AtomicUsize::new(0)];
+static TX_FAILED_RECOVERIES: [AtomicUsize; MAX_CONSOLE_UARTS] =
+    [AtomicUsize::new(0), AtomicUsize::new(0)];
+
+/// 同时向多个串口输出的控制台，第一个串口是主串口，输入只从主串口读取
+#[derive(Clone, Copy)]
+pub struct MultiUart {
+    uarts: [Option<Uart>; MAX_CONSOLE_UARTS],
+}
+
+impl MultiUart {
+    pub fn new(primary: Uart) -> Self {
+        let mut uarts = [None; MAX_CONSOLE_UARTS];
+        uarts[0] = Some(primary);
+        MultiUart { uarts }
+    }
+
+    pub fn contains(&self, uart: &Uart) -> bool {
+        self.uarts.iter().flatten().any(|u| u == uart)
+    }
+
+    /// 增加一个镜像输出的串口，没有空位时返回false
+    pub fn push(&mut self, uart: Uart) -> bool {
+        match self.uarts.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => {
+                *slot = Some(uart);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        let mut recovery = None;
+        for (index, slot) in self.uarts.iter_mut().enumerate() {
+            let uart = match slot {
+                Some(uart) => uart,
+                None => continue,
+            };
+            let failed = TX_FAILED_RECOVERIES[index].load(Ordering::Relaxed);
+            // 已经放弃恢复的串口只尝试一次，发送队列恢复以后输出自然继续
+            let spins = if failed >= TX_MAX_RECOVERIES {
+                1
+            } else {
+                TX_SPIN_LIMIT
+            };
+            if send(uart, byte, spins) {
+                TX_DROPPED[index].store(0, Ordering::Relaxed);
+                TX_FAILED_RECOVERIES[index].store(0, Ordering::Relaxed);
+                continue;
+            }
+            let dropped = TX_DROPPED[index].fetch_add(1, Ordering::Relaxed) + 1;
+            if dropped < TX_STUCK_DROPS || failed >= TX_MAX_RECOVERIES {
+                continue;
+            }
+            TX_DROPPED[index].store(0, Ordering::Relaxed);
+            let recovered = unsafe { uart.reset_transmitter() };
+            let failed = if recovered { 0 } else { failed + 1 };
+            TX_FAILED_RECOVERIES[index].store(failed, Ordering::Relaxed);
+            recovery = Some((index, recovered, failed, dropped));
+        }
+        if let Some((index, recovered, failed, dropped)) = recovery {
+            self.report_recovery(index, recovered, failed, dropped);
+        }
+    }
+
+    // 这时已经持有控制台的锁，不能使用println，直接写到各个串口；
+    // 恢复失败时这条信息只能从其它串口看到
+    fn report_recovery(&mut self, index: usize, recovered: bool, failed: usize, dropped: usize) {
+        use fmt::Write;
+        let mut out = RawUarts(&mut self.uarts);
+        let _ = if recovered {
+            write!(
+                out,
+                "\r\n[rustsbi] warning: console uart {} stuck, {} byte(s) dropped, transmitter re-enabled\r\n",
+                index, dropped
+            )
+        } else if failed < TX_MAX_RECOVERIES {
+            write!(
+                out,
+                "\r\n[rustsbi] warning: console uart {} stuck, recovery failed ({} of {})\r\n",
+                index, failed, TX_MAX_RECOVERIES
+            )
+        } else {
+            write!(
+                out,
+                "\r\n[rustsbi] warning: console uart {} stuck, giving up, output is dropped\r\n",
+                index
+            )
+        };
+    }
+}
+
+// 发送一个字节，发送队列满时最多等待spins次，返回是否发送成功
+fn send(uart: &mut Uart, byte: u8, spins: usize) -> bool {
+    for _ in 0..spins {
+        if uart.write(byte).is_ok() {
+            return true;
+        }
+        core::hint::spin_loop();
+    }
+    false
+}
+
+// 只做有限等待的输出，不检测卡住，用于报告恢复的情况
+struct RawUarts<'a>(&'a mut [Option<Uart>; MAX_CONSOLE_UARTS]);
+
+impl fmt::Write for RawUarts<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for uart in self.0.iter_mut().flatten() {
+            for byte in s.as_bytes() {
+                send(uart, *byte, TX_SPIN_LIMIT);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Write for MultiUart {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.as_bytes() {
+            self.write_byte(*byte);
+        }
+        Ok(())
+    }
+}
+
+// 供rustsbi的legacy控制台使用，S层的输出也会镜像到所有串口
+impl Write<u8> for MultiUart {
+    type Error = Infallible;
+
+    #[inline]
+    fn write(&mut self, byte: u8) -> nb::Result<(), Infallible> {
+        self.write_byte(byte);
+        Ok(())
+    }
+
+    #[inline]
+    fn flush(&mut self) -> nb::Result<(), Infallible> {
+        Ok(())
+    }
+}
+
+impl Read<u8> for MultiUart {
+    type Error = Infallible;
+
+    #[inline]
+    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+        match &mut self.uarts[0] {
+            Some(uart) => uart.read(),
+            None => Err(nb::Error::WouldBlock),
+        }
+    }
+}
+
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
     use fmt::Write;
+    if QUIET.load(Ordering::Relaxed) {
+        return;
+    }
     let lock = STDOUT.lock();
     if let Some(mut stdout) = *lock {
         stdout.write_fmt(args).unwrap();
@@ -31,14 +263,34 @@ pub fn _print(args: fmt::Arguments) {
     drop(lock);
 }
 
+// 错误输出用于panic和早期陷入，设置了调试串口时同时输出到调试串口
 #[doc(hidden)]
 pub fn _eprint(args: fmt::Arguments) {
     use fmt::Write;
-    let lock = STDOUT.lock();
+    let lock = lock_for_panic(&STDOUT);
     if let Some(mut stdout) = *lock {
         stdout.write_fmt(args).unwrap();
     }
     drop(lock);
+    let lock = lock_for_panic(&PANIC_UART);
+    if let Some(mut uart) = *lock {
+        uart.write_fmt(args).unwrap();
+    }
+    drop(lock);
+}
+
+// 获取锁时最多尝试的次数，足够其它核输出完一行
+const PANIC_LOCK_SPINS: usize = 1_000_000;
+
+// 只在panic和早期陷入的路径上使用：其它核可能在输出到一半时停住，
+// 或者当前核就是在持有锁时发生的panic。等待一段时间仍然拿不到锁时强行取得锁，
+// 输出可能和其它核交错，但诊断信息一定能输出。
+// 强行取得的锁在输出以后和普通的锁一样释放，热重启和监视器之后的输出仍然可以正常加锁
+fn lock_for_panic<T>(mutex: &AmoMutex<T>) -> AmoMutexGuard<T> {
+    match mutex.try_lock_for(PANIC_LOCK_SPINS) {
+        Some(guard) => guard,
+        None => unsafe { mutex.force_lock() },
+    }
 }
 
 #[allow(unused)]
@@ -0,0 +1,84 @@
+// 给S层用的看门狗：S层打开以后需要定期喂狗，超时没有喂狗就认为S层卡死了
+//
+// 看门狗和S层的set_timer共用CLINT的比较寄存器，见HartLocal::next_timer_deadline
+use crate::console::println;
+use crate::ecall;
+use crate::hart_local;
+use crate::peripheral::Clint;
+use crate::runtime::SupervisorContext;
+use core::sync::atomic::Ordering;
+use riscv::register::mie;
+
+// 到期时关闭系统，而不是只打印诊断信息
+pub const FLAG_RESET_ON_EXPIRE: usize = 1 << 0;
+
+// 看门狗到期时关闭系统使用的原因，对应SRST扩展的系统错误
+const RESET_REASON_SYSTEM_FAILURE: usize = 0x0000_0001;
+
+#[inline]
+fn clint() -> Clint {
+    Clint::new(0x2000000 as *mut u8)
+}
+
+// 打开当前核的看门狗；超时长度为0时关闭看门狗
+pub fn enable(hart_id: usize, timeout: u64, flags: usize) {
+    let hart = hart_local::get(hart_id);
+    let clint = clint();
+    hart.deadman_reset
+        .store(flags & FLAG_RESET_ON_EXPIRE != 0, Ordering::Relaxed);
+    hart.deadman_fired.store(false, Ordering::Relaxed);
+    hart.deadman_deadline
+        .store(clint.get_mtime().saturating_add(timeout), Ordering::Relaxed);
+    hart.deadman_timeout.store(timeout, Ordering::Relaxed);
+    rearm(hart_id);
+}
+
+// 喂狗，从现在开始重新计时。看门狗已经到期过时返回false
+pub fn pet(hart_id: usize) -> bool {
+    let hart = hart_local::get(hart_id);
+    if hart.deadman_fired.load(Ordering::Relaxed) {
+        return false;
+    }
+    let timeout = hart.deadman_timeout.load(Ordering::Relaxed);
+    if timeout != 0 {
+        hart.deadman_deadline.store(
+            clint().get_mtime().saturating_add(timeout),
+            Ordering::Relaxed,
+        );
+        rearm(hart_id);
+    }
+    true
+}
+
+// 按照S层时钟和看门狗中较早的一个设置比较寄存器；两个都没有设置时关闭M层时钟中断
+pub fn rearm(hart_id: usize) {
+    let next = hart_local::get(hart_id).next_timer_deadline();
+    clint().set_timer(hart_id, next);
+    unsafe {
+        if next == u64::MAX {
+            mie::clear_mtimer();
+        } else {
+            mie::set_mtimer();
+        }
+    }
+}
+
+// 在M层时钟中断中调用，看门狗到期时打印S层当时的上下文
+pub fn check(hart_id: usize, now: u64, ctx: &SupervisorContext) {
+    let hart = hart_local::get(hart_id);
+    let timeout = hart.deadman_timeout.load(Ordering::Relaxed);
+    if timeout == 0 || now < hart.deadman_deadline.load(Ordering::Relaxed) {
+        return;
+    }
+    // 只报告一次，S层需要重新打开看门狗
+    hart.deadman_timeout.store(0, Ordering::Relaxed);
+    hart.deadman_fired.store(true, Ordering::Relaxed);
+    println!(
+        "[rustsbi] supervisor appears hung on hart {}: no pet in {} ticks, mepc: {:#x}",
+        hart_id, timeout, ctx.mepc
+    );
+    println!("[rustsbi] supervisor context: {:x?}", ctx);
+    if hart.deadman_reset.load(Ordering::Relaxed) {
+        ecall::shutdown(RESET_REASON_SYSTEM_FAILURE)
+    }
+}
@@ -1,4 +1,6 @@
 // use alloc::collections::BTreeMap;
+use crate::execute::BootConvention;
+use crate::panic_action::PanicAction;
 use serde_derive::Deserialize;
 use serde_device_tree::{self, error::Result};
 
@@ -6,23 +8,378 @@ use serde_device_tree::{self, error::Result};
 struct Tree<'a> {
     // #[serde(borrow)]
     // aliases: BTreeMap<&'a str, &'a str>,
+    model: Option<&'a str>,
     #[serde(borrow)]
     chosen: Option<Chosen<'a>>,
+    #[serde(borrow, rename = "memory@80000000")]
+    memory: Option<Memory<'a>>,
+    #[serde(borrow)]
+    cpus: Option<Cpus<'a>>,
+    #[serde(borrow)]
+    soc: Option<Soc<'a>>,
+}
+
+// FU740的五个核，cpu@0是S7小核
+#[derive(Debug, Deserialize)]
+struct Cpus<'a> {
+    #[serde(borrow, rename = "cpu@0")]
+    cpu0: Option<Cpu<'a>>,
+    #[serde(borrow, rename = "cpu@1")]
+    cpu1: Option<Cpu<'a>>,
+    #[serde(borrow, rename = "cpu@2")]
+    cpu2: Option<Cpu<'a>>,
+    #[serde(borrow, rename = "cpu@3")]
+    cpu3: Option<Cpu<'a>>,
+    #[serde(borrow, rename = "cpu@4")]
+    cpu4: Option<Cpu<'a>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Cpu<'a> {
+    status: Option<&'a str>,
+}
+
+// 只解析板上传感器所在的I2C0
+#[derive(Debug, Deserialize)]
+struct Soc<'a> {
+    #[serde(borrow, rename = "i2c@10030000")]
+    i2c0: Option<I2cBus<'a>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct I2cBus<'a> {
+    status: Option<&'a str>,
+    reg: Option<&'a [u8]>,
+    #[serde(borrow, rename = "temperature-sensor@4c")]
+    temperature_sensor: Option<I2cDevice<'a>>,
+}
+
+// I2C总线的#address-cells是1，#size-cells是0
+#[derive(Debug, Deserialize)]
+struct I2cDevice<'a> {
+    compatible: Option<&'a str>,
+    reg: Option<&'a [u8]>,
 }
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 struct Chosen<'a> {
     stdout_path: Option<&'a str>,
+    #[serde(borrow, rename = "rustsbi,rescue-addr")]
+    rescue_addr: Option<&'a [u8]>,
+    // 主负载的长度，用来检查负载是否和固件重叠
+    #[serde(borrow, rename = "rustsbi,payload-size")]
+    payload_size: Option<&'a [u8]>,
+    // 打开memory-test功能时，相邻两个被测试的内存块的间隔
+    #[serde(borrow, rename = "rustsbi,memory-test-stride")]
+    memory_test_stride: Option<&'a [u8]>,
+    // panic时额外输出的串口，写法和stdout-path相同，只支持UART1
+    #[serde(borrow, rename = "rustsbi,panic-console")]
+    panic_console: Option<&'a str>,
+    // 打开monitor功能时，不能进入S层以后等待用户按键进入监视器的秒数
+    #[serde(borrow, rename = "rustsbi,monitor-timeout")]
+    monitor_timeout: Option<&'a [u8]>,
+    // 固件panic以后的处理方式，可以是halt、reboot或monitor
+    #[serde(borrow, rename = "rustsbi,panic-action")]
+    panic_action: Option<&'a str>,
+    // 选择reboot时，热重启之前等待的秒数
+    #[serde(borrow, rename = "rustsbi,panic-reboot-delay")]
+    panic_reboot_delay: Option<&'a [u8]>,
+    // 打开time-scale功能时，S层时间相对mtime的速率，两个cell分别是分子和分母
+    #[serde(borrow, rename = "rustsbi,time-scale")]
+    time_scale: Option<&'a [u8]>,
+    // 进入S层时a0和a1的约定，可以是linux或dtb-first
+    #[serde(borrow, rename = "rustsbi,boot-convention")]
+    boot_convention: Option<&'a str>,
+    // 调试控制台扩展一次调用最多处理的字节数
+    #[serde(borrow, rename = "rustsbi,dbcn-max-transfer")]
+    dbcn_max_transfer: Option<&'a [u8]>,
 }
 
-pub unsafe fn parse_device_tree(dtb_pa: usize) -> Result<()> {
-    let tree: Tree = serde_device_tree::from_raw(dtb_pa as *const u8)?;
+// 根节点的#address-cells和#size-cells都是2
+#[derive(Debug, Deserialize)]
+struct Memory<'a> {
+    reg: Option<&'a [u8]>,
+}
+
+/// 从设备树中读出的、固件后续需要使用的信息
+#[derive(Debug, Default)]
+pub struct BoardInfo {
+    /// 根节点的model属性，只在进入S层之前使用，这时设备树还没有被S层改动
+    pub model: Option<&'static str>,
+    pub rescue_addr: Option<usize>,
+    /// 主负载的长度
+    pub payload_size: Option<usize>,
+    /// 内存测试的抽样间隔
+    pub memory_test_stride: Option<usize>,
+    /// 等待进入监视器的秒数
+    pub monitor_timeout: Option<usize>,
+    /// 内存的起始和结束地址
+    pub memory: Option<(usize, usize)>,
+    /// 设备树中status不是okay的核，按核编号置位
+    pub disabled_harts: usize,
+    /// 设备树有/cpus节点，但其中没有描述的核，按核编号置位
+    pub missing_harts: usize,
+    /// panic时是否额外输出到UART1
+    pub panic_uart1: bool,
+    /// panic以后的处理方式
+    pub panic_action: Option<PanicAction>,
+    /// panic以后热重启之前等待的秒数
+    pub panic_reboot_delay: Option<usize>,
+    /// S层时间的缩放比例，分子和分母
+    pub time_scale: Option<(u64, u64)>,
+    /// 进入S层时a0和a1的约定
+    pub boot_convention: Option<BootConvention>,
+    /// 调试控制台扩展一次调用最多处理的字节数
+    pub dbcn_max_transfer: Option<usize>,
+    /// 温度传感器所在I2C控制器的基地址和传感器的I2C地址
+    pub temperature_sensor: Option<(usize, u8)>,
+}
+
+pub unsafe fn parse_device_tree(dtb_pa: usize) -> Result<BoardInfo> {
+    let tree: Tree<'static> = serde_device_tree::from_raw(dtb_pa as *const u8)?;
     use crate::console::println;
+    let mut info = BoardInfo {
+        model: tree.model,
+        ..BoardInfo::default()
+    };
     if let Some(chosen) = tree.chosen {
         if let Some(stdout_path) = chosen.stdout_path {
             println!("[rustsbi] stdout path: {}", stdout_path);
         }
+        if let Some(rescue_addr) = chosen.rescue_addr {
+            info.rescue_addr = read_cells(rescue_addr);
+            if let Some(rescue_addr) = info.rescue_addr {
+                println!("[rustsbi] rescue payload address: {:#x}", rescue_addr);
+            }
+        }
+        if let Some(payload_size) = chosen.payload_size {
+            info.payload_size = read_cells(payload_size);
+        }
+        if let Some(stride) = chosen.memory_test_stride {
+            info.memory_test_stride = read_cells(stride);
+        }
+        if let Some(timeout) = chosen.monitor_timeout {
+            info.monitor_timeout = read_cells(timeout);
+        }
+        if let Some(action) = chosen.panic_action {
+            info.panic_action = PanicAction::from_name(action);
+            if info.panic_action.is_none() {
+                println!(
+                    "[rustsbi] warning: unknown panic action {}, expected halt, reboot or monitor",
+                    action
+                );
+            }
+        }
+        if let Some(delay) = chosen.panic_reboot_delay {
+            info.panic_reboot_delay = read_cells(delay);
+        }
+        if let Some(scale) = chosen.time_scale {
+            info.time_scale = read_ratio(scale);
+        }
+        if let Some(max_transfer) = chosen.dbcn_max_transfer {
+            info.dbcn_max_transfer = read_cells(max_transfer);
+        }
+        if let Some(convention) = chosen.boot_convention {
+            info.boot_convention = BootConvention::from_name(convention);
+            if info.boot_convention.is_none() {
+                println!(
+                    "[rustsbi] warning: unknown boot convention {}, expected linux or dtb-first",
+                    convention
+                );
+            }
+        }
+        if let Some(panic_console) = chosen.panic_console {
+            info.panic_uart1 = is_uart1(panic_console);
+            if !info.panic_uart1 {
+                println!(
+                    "[rustsbi] warning: unsupported panic console {}, only uart1 is supported",
+                    panic_console
+                );
+            }
+        }
+    }
+    if let Some(reg) = tree.memory.and_then(|memory| memory.reg) {
+        info.memory = read_region(reg);
+        if let Some((start, end)) = info.memory {
+            println!("[rustsbi] memory: {:#x} ~ {:#x}", start, end);
+        }
+    }
+    if let Some(cpus) = tree.cpus {
+        let cpus = [cpus.cpu0, cpus.cpu1, cpus.cpu2, cpus.cpu3, cpus.cpu4];
+        for (hart_id, cpu) in cpus.iter().enumerate() {
+            if cpu.is_none() {
+                info.missing_harts |= 1 << hart_id;
+                continue;
+            }
+            // 没有status属性的节点按规范视为okay
+            let status = cpu.as_ref().and_then(|cpu| cpu.status);
+            if status.map_or(false, |status| status != "okay") {
+                info.disabled_harts |= 1 << hart_id;
+            }
+        }
+    }
+    if let Some(i2c0) = tree.soc.and_then(|soc| soc.i2c0) {
+        info.temperature_sensor = read_temperature_sensor(i2c0);
+    }
+    Ok(info)
+}
+
+// 只支持TMP451，控制器被禁用时视为没有传感器
+fn read_temperature_sensor(bus: I2cBus) -> Option<(usize, u8)> {
+    if bus.status.map_or(false, |status| status != "okay") {
+        return None;
+    }
+    let (base, _) = read_region(bus.reg?)?;
+    let sensor = bus.temperature_sensor?;
+    if sensor.compatible? != "ti,tmp451" {
+        return None;
+    }
+    match read_cells(sensor.reg?)? {
+        address @ 0..=0x7F => Some((base, address as u8)),
+        _ => None,
+    }
+}
+
+// 两个32位的cell，分别是分子和分母
+fn read_ratio(bytes: &[u8]) -> Option<(u64, u64)> {
+    if bytes.len() != 8 {
+        return None;
+    }
+    let cell = |i: usize| u32::from_be_bytes(bytes[i * 4..i * 4 + 4].try_into().unwrap()) as u64;
+    Some((cell(0), cell(1)))
+}
+
+// 接受别名或者节点路径，冒号以后的串口参数忽略
+fn is_uart1(path: &str) -> bool {
+    let path = path.split(':').next().unwrap_or(path);
+    path == "serial1" || path.ends_with("/serial@10011000")
+}
+
+// 只使用reg中的第一段区域
+fn read_region(reg: &[u8]) -> Option<(usize, usize)> {
+    if reg.len() < 16 {
+        return None;
+    }
+    let start = read_cells(&reg[0..8])?;
+    let size = read_cells(&reg[8..16])?;
+    Some((start, start.checked_add(size)?))
+}
+
+// 设备树的文件头，各个字段都是大端序的u32
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_HEADER_SIZE: usize = 40;
+// 版本17开始才有结构块的长度
+const FDT_MIN_VERSION: u32 = 17;
+const FDT_LAST_COMP_VERSION: u32 = 17;
+// 结构块中的标记
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_NOP: u32 = 0x4;
+
+/// 检查设备树的文件头，各个块都要位于设备树之内；成功时返回设备树的总长度
+pub fn check_header(blob: &[u8]) -> Option<usize> {
+    let field = |index: usize| {
+        let offset = index * 4;
+        Some(u32::from_be_bytes(blob.get(offset..offset + 4)?.try_into().ok()?) as usize)
+    };
+    if field(0)? != FDT_MAGIC as usize {
+        return None;
+    }
+    let total_size = field(1)?;
+    if total_size < FDT_HEADER_SIZE || total_size > blob.len() {
+        return None;
+    }
+    if field(5)? < FDT_MIN_VERSION as usize || field(6)? > FDT_LAST_COMP_VERSION as usize {
+        return None;
+    }
+    let (off_dt_struct, size_dt_struct) = (field(2)?, field(9)?);
+    let (off_dt_strings, size_dt_strings) = (field(3)?, field(8)?);
+    let off_mem_rsvmap = field(4)?;
+    let within = |offset: usize, size: usize| match offset.checked_add(size) {
+        Some(end) => end <= total_size,
+        None => false,
+    };
+    if !within(off_dt_struct, size_dt_struct)
+        || !within(off_dt_strings, size_dt_strings)
+        || off_mem_rsvmap % 8 != 0
+        || !within(off_mem_rsvmap, 16)
+    {
+        return None;
+    }
+    Some(total_size)
+}
+
+/// 文件头中记录的设备树总长度
+pub unsafe fn total_size(dtb_pa: usize) -> usize {
+    u32::from_be(core::ptr::read_volatile((dtb_pa as *const u32).add(1))) as usize
+}
+
+/// 检查设备树的结构能否被固件解析；调用前应当先用check_header检查文件头
+pub unsafe fn is_valid(dtb_pa: usize) -> bool {
+    serde_device_tree::from_raw::<Tree>(dtb_pa as *const u8).is_ok()
+}
+
+/// 不经过完整的解析，直接从设备树的结构块中读出/chosen节点的一个整数属性。
+/// 不需要堆，可以在堆和控制台初始化之前使用；设备树无效或者没有这个属性时返回None
+pub unsafe fn read_chosen_cells(dtb_pa: usize, name: &str) -> Option<usize> {
+    let header = core::slice::from_raw_parts(dtb_pa as *const u8, FDT_HEADER_SIZE);
+    if u32::from_be_bytes(header[..4].try_into().ok()?) != FDT_MAGIC {
+        return None;
+    }
+    let blob = core::slice::from_raw_parts(dtb_pa as *const u8, total_size(dtb_pa));
+    check_header(blob)?;
+    let word = |bytes: &[u8], offset: usize| {
+        Some(u32::from_be_bytes(
+            bytes.get(offset..offset + 4)?.try_into().ok()?,
+        ))
+    };
+    let cstr = |bytes: &'static [u8]| Some(&bytes[..bytes.iter().position(|&b| b == 0)?]);
+    let off_dt_struct = word(blob, 8)? as usize;
+    let structure = blob.get(off_dt_struct..off_dt_struct + word(blob, 36)? as usize)?;
+    let strings = blob.get(word(blob, 12)? as usize..)?;
+    // 根节点的深度是1，/chosen的属性都在深度2，并且排在它的子节点之前
+    let (mut pos, mut depth, mut in_chosen) = (0, 0usize, false);
+    loop {
+        let token = word(structure, pos)?;
+        pos += 4;
+        match token {
+            FDT_BEGIN_NODE => {
+                if in_chosen {
+                    return None;
+                }
+                let node_name = cstr(structure.get(pos..)?)?;
+                depth += 1;
+                in_chosen = depth == 2 && node_name == b"chosen";
+                pos += (node_name.len() + 1 + 3) & !3;
+            }
+            FDT_END_NODE => {
+                if in_chosen {
+                    return None;
+                }
+                depth = depth.checked_sub(1)?;
+            }
+            FDT_PROP => {
+                let len = word(structure, pos)? as usize;
+                let prop_name = cstr(strings.get(word(structure, pos + 4)? as usize..)?)?;
+                pos += 8;
+                if in_chosen && prop_name == name.as_bytes() {
+                    return read_cells(structure.get(pos..pos + len)?);
+                }
+                pos += (len + 3) & !3;
+            }
+            FDT_NOP => {}
+            _ => return None,
+        }
+    }
+}
+
+// 设备树中的整数以大端序存放，可能占一个或两个cell
+fn read_cells(bytes: &[u8]) -> Option<usize> {
+    match bytes.len() {
+        4 => Some(u32::from_be_bytes(bytes.try_into().ok()?) as usize),
+        8 => Some(u64::from_be_bytes(bytes.try_into().ok()?) as usize),
+        _ => None,
     }
-    Ok(())
 }
@@ -0,0 +1,129 @@
+// 以dts的格式打印设备树，只在打开dump-device-tree功能时编译
+use crate::console::{print, println};
+
+// 扁平设备树（FDT）的格式，见设备树规范第5章
+mod fdt {
+    pub const MAGIC: u32 = 0xd00d_feed;
+    pub const BEGIN_NODE: u32 = 0x1;
+    pub const END_NODE: u32 = 0x2;
+    pub const PROP: u32 = 0x3;
+    pub const NOP: u32 = 0x4;
+    pub const END: u32 = 0x9;
+}
+
+/// 以dts的格式打印设备树，用来确认交给S层的设备树内容
+pub unsafe fn dump_device_tree(dtb_pa: usize) {
+    let header = core::slice::from_raw_parts(dtb_pa as *const u8, 40);
+    if read_u32(header, 0) != Some(fdt::MAGIC) {
+        println!("[rustsbi] no device tree at {:#x} to dump", dtb_pa);
+        return;
+    }
+    let total_size = read_u32(header, 4).unwrap() as usize;
+    let blob = core::slice::from_raw_parts(dtb_pa as *const u8, total_size);
+    println!(
+        "[rustsbi] device tree at {:#x}, {} bytes:",
+        dtb_pa, total_size
+    );
+    if dump_structure(blob).is_none() {
+        println!("[rustsbi] device tree is truncated or malformed, dump stopped");
+    }
+}
+
+fn dump_structure(blob: &[u8]) -> Option<()> {
+    let struct_start = read_u32(blob, 8)? as usize;
+    let strings = blob.get(read_u32(blob, 12)? as usize..)?;
+    let struct_size = read_u32(blob, 36)? as usize;
+    let structure = blob.get(struct_start..struct_start.checked_add(struct_size)?)?;
+    let mut pos = 0;
+    let mut depth = 0;
+    loop {
+        let token = read_u32(structure, pos)?;
+        pos += 4;
+        match token {
+            fdt::BEGIN_NODE => {
+                let name = read_str(structure.get(pos..)?)?;
+                pos += align4(name.len() + 1);
+                let name = if depth == 0 { "/" } else { name };
+                println!("{:indent$}{} {{", "", name, indent = depth * 4);
+                depth += 1;
+            }
+            fdt::END_NODE => {
+                depth = depth.checked_sub(1)?;
+                println!("{:indent$}}};", "", indent = depth * 4);
+            }
+            fdt::PROP => {
+                let len = read_u32(structure, pos)? as usize;
+                let name = read_str(strings.get(read_u32(structure, pos + 4)? as usize..)?)?;
+                pos += 8;
+                let value = structure.get(pos..pos.checked_add(len)?)?;
+                pos += align4(len);
+                dump_property(depth, name, value);
+            }
+            fdt::NOP => {}
+            fdt::END => return Some(()),
+            _ => return None,
+        }
+    }
+}
+
+// 可打印的字符串按字符串列表打印，长度为4的倍数时按cell打印，其余按字节打印
+fn dump_property(depth: usize, name: &str, value: &[u8]) {
+    print!("{:indent$}{}", "", name, indent = depth * 4);
+    if value.is_empty() {
+        println!(";");
+    } else if is_string_list(value) {
+        print!(" = ");
+        let strings = value[..value.len() - 1].split(|&b| b == 0);
+        for (i, s) in strings.enumerate() {
+            if i != 0 {
+                print!(", ");
+            }
+            print!("\"{}\"", core::str::from_utf8(s).unwrap_or("?"));
+        }
+        println!(";");
+    } else if value.len() % 4 == 0 {
+        print!(" = <");
+        for (i, cell) in value.chunks(4).enumerate() {
+            if i != 0 {
+                print!(" ");
+            }
+            print!(
+                "{:#x}",
+                u32::from_be_bytes([cell[0], cell[1], cell[2], cell[3]])
+            );
+        }
+        println!(">;");
+    } else {
+        print!(" = [");
+        for (i, byte) in value.iter().enumerate() {
+            if i != 0 {
+                print!(" ");
+            }
+            print!("{:02x}", byte);
+        }
+        println!("];");
+    }
+}
+
+fn is_string_list(value: &[u8]) -> bool {
+    value.last() == Some(&0)
+        && value[0] != 0
+        && !value.windows(2).any(|w| w == [0, 0])
+        && value.iter().all(|&b| b == 0 || (0x20..0x7f).contains(&b))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        bytes.get(offset..offset.checked_add(4)?)?.try_into().ok()?,
+    ))
+}
+
+fn read_str(bytes: &[u8]) -> Option<&str> {
+    let len = bytes.iter().position(|&b| b == 0)?;
+    core::str::from_utf8(&bytes[..len]).ok()
+}
+
+#[inline]
+fn align4(len: usize) -> usize {
+    (len + 3) & !3
+}
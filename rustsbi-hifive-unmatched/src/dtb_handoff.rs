@@ -0,0 +1,66 @@
+// S层交还给固件的设备树，在下一次交接（热重启或kexec）时交给S层
+//
+// S层探测硬件以后可能修改了设备树。固件检查以后复制到自己的区域中，
+// 之后热重启时交出这份副本，而不是启动时的设备树
+use crate::device_tree;
+use crate::payload;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+// 能够接受的设备树的最大长度
+const MAX_DTB_SIZE: usize = 64 * 1024; // 64KiB
+
+// 设备树要求8字节对齐
+#[repr(C, align(8))]
+struct DtbBuffer([u8; MAX_DTB_SIZE]);
+
+#[link_section = ".bss.uninit"]
+static mut DTB_BUFFER: DtbBuffer = DtbBuffer([0; MAX_DTB_SIZE]);
+
+// 缓冲区中设备树的长度，为0时S层没有交还过设备树
+static DTB_LEN: AtomicUsize = AtomicUsize::new(0);
+// 启动时交给S层的设备树
+static BOOT_DTB: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug)]
+pub enum HandoffError {
+    /// 区域不在内存中，或者和固件自身的区域重叠
+    InvalidAddress,
+    /// 超过了固件预留的长度
+    TooLarge,
+    /// 不是合法的设备树
+    InvalidDeviceTree,
+}
+
+pub fn init(boot_dtb: usize) {
+    BOOT_DTB.store(boot_dtb, Ordering::Release);
+}
+
+// S层应当只在一个核上交还设备树；交还失败时，之前交还的设备树也不再使用
+pub fn submit(base: usize, size: usize) -> Result<(), HandoffError> {
+    if !payload::is_supervisor_region(base, size) {
+        return Err(HandoffError::InvalidAddress);
+    }
+    if size > MAX_DTB_SIZE {
+        return Err(HandoffError::TooLarge);
+    }
+    let blob = unsafe { core::slice::from_raw_parts(base as *const u8, size) };
+    let total_size = device_tree::check_header(blob).ok_or(HandoffError::InvalidDeviceTree)?;
+    DTB_LEN.store(0, Ordering::Release);
+    let buffer = unsafe { &mut DTB_BUFFER.0 };
+    buffer[..total_size].copy_from_slice(&blob[..total_size]);
+    // 在固件的副本上检查结构，S层之后修改原来的内存不会影响检查的结果
+    if !unsafe { device_tree::is_valid(buffer.as_ptr() as usize) } {
+        return Err(HandoffError::InvalidDeviceTree);
+    }
+    DTB_LEN.store(total_size, Ordering::Release);
+    Ok(())
+}
+
+/// 下一次交接使用的设备树：S层交还过设备树时使用固件中的副本，否则使用启动时的设备树
+pub fn next_dtb() -> usize {
+    if DTB_LEN.load(Ordering::Acquire) != 0 {
+        unsafe { DTB_BUFFER.0.as_ptr() as usize }
+    } else {
+        BOOT_DTB.load(Ordering::Acquire)
+    }
+}
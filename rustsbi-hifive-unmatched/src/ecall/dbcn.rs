@@ -0,0 +1,77 @@
+// 调试控制台（DBCN）扩展，输出到固件的控制台，输入从主串口读取
+//
+// 缓冲区按物理地址给出，必须整个位于内存中而且不和固件自己的区域重叠。
+// 长度为0时不检查地址，直接成功返回0，调用者可以用空指针表示空缓冲区。
+// 一次调用最多处理的字节数有上限，超过时只处理上限这么多并返回处理的字节数，
+// S层需要循环调用；这样一次很大的调用不会长时间占住控制台，让其它核等待
+use super::{invalid_param, not_supported};
+use crate::console;
+use crate::payload;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use rustsbi::SbiRet;
+
+pub const EXTENSION_DBCN: usize = 0x4442434E;
+
+const FUNCTION_CONSOLE_WRITE: usize = 0x0;
+const FUNCTION_CONSOLE_READ: usize = 0x1;
+const FUNCTION_CONSOLE_WRITE_BYTE: usize = 0x2;
+
+/// 设备树中没有给出时，一次调用最多处理的字节数；按115200波特率大约要输出0.35秒
+pub const DEFAULT_MAX_TRANSFER: usize = 4096;
+
+static MAX_TRANSFER: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_TRANSFER);
+
+/// 解析设备树以后调用，设置一次调用最多处理的字节数；为0时不修改，返回是否设置成功
+pub fn set_max_transfer(bytes: usize) -> bool {
+    if bytes == 0 {
+        return false;
+    }
+    MAX_TRANSFER.store(bytes, Ordering::Relaxed);
+    true
+}
+
+pub fn handle_ecall(function: usize, param: [usize; 6]) -> SbiRet {
+    match function {
+        FUNCTION_CONSOLE_WRITE => console_write(param[0], param[1], param[2]),
+        FUNCTION_CONSOLE_READ => console_read(param[0], param[1], param[2]),
+        FUNCTION_CONSOLE_WRITE_BYTE => {
+            console::write_bytes(&[param[0] as u8]);
+            SbiRet::ok(0)
+        }
+        _ => not_supported(),
+    }
+}
+
+// 检查整个缓冲区，返回它的起始地址和这次调用处理的字节数；RV64上地址的高位部分必须为0
+fn buffer(num_bytes: usize, base_lo: usize, base_hi: usize) -> Option<(usize, usize)> {
+    if base_hi != 0 || !payload::is_supervisor_region(base_lo, num_bytes) {
+        return None;
+    }
+    Some((base_lo, num_bytes.min(MAX_TRANSFER.load(Ordering::Relaxed))))
+}
+
+#[inline]
+fn console_write(num_bytes: usize, base_lo: usize, base_hi: usize) -> SbiRet {
+    if num_bytes == 0 {
+        return SbiRet::ok(0);
+    }
+    let (base, len) = match buffer(num_bytes, base_lo, base_hi) {
+        Some(buffer) => buffer,
+        None => return invalid_param(),
+    };
+    let bytes = unsafe { core::slice::from_raw_parts(base as *const u8, len) };
+    SbiRet::ok(console::write_bytes(bytes))
+}
+
+#[inline]
+fn console_read(num_bytes: usize, base_lo: usize, base_hi: usize) -> SbiRet {
+    if num_bytes == 0 {
+        return SbiRet::ok(0);
+    }
+    let (base, len) = match buffer(num_bytes, base_lo, base_hi) {
+        Some(buffer) => buffer,
+        None => return invalid_param(),
+    };
+    let buf = unsafe { core::slice::from_raw_parts_mut(base as *mut u8, len) };
+    SbiRet::ok(console::read_bytes(buf))
+}
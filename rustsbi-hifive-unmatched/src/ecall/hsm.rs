@@ -0,0 +1,195 @@
+// 核状态管理（HSM）扩展，由hart_local中的核状态驱动
+//
+// 停止和挂起的核在M层等待；重新启动时由execute模块按照hart_local中的新入口重新进入S层
+use super::{
+    invalid_param, not_supported, SBI_ERR_ALREADY_AVAILABLE, SBI_ERR_FAILED,
+    SBI_ERR_INVALID_ADDRESS,
+};
+use crate::deadman;
+use crate::hart_local::{self, HartLocal, HartState, MAX_HART_ID};
+use crate::payload;
+use crate::peripheral::Clint;
+use core::sync::atomic::Ordering;
+use riscv::register::{mhartid, mip};
+use rustsbi::SbiRet;
+
+pub const EXTENSION_HSM: usize = 0x48534D;
+
+const FUNCTION_HART_START: usize = 0x0;
+const FUNCTION_HART_STOP: usize = 0x1;
+const FUNCTION_HART_GET_STATUS: usize = 0x2;
+const FUNCTION_HART_SUSPEND: usize = 0x3;
+
+const HART_STATUS_STARTED: usize = 0;
+const HART_STATUS_STOPPED: usize = 1;
+const HART_STATUS_START_PENDING: usize = 2;
+const HART_STATUS_SUSPENDED: usize = 4;
+
+const SUSPEND_TYPE_RETENTIVE: usize = 0x0000_0000;
+const SUSPEND_TYPE_NON_RETENTIVE: usize = 0x8000_0000;
+// 平台自定义的挂起类型，按规范属于合法但没有实现的类型
+const SUSPEND_TYPE_PLATFORM_RETENTIVE: usize = 0x1000_0000;
+const SUSPEND_TYPE_PLATFORM_RETENTIVE_END: usize = 0x7FFF_FFFF;
+const SUSPEND_TYPE_PLATFORM_NON_RETENTIVE: usize = 0x9000_0000;
+const SUSPEND_TYPE_PLATFORM_NON_RETENTIVE_END: usize = 0xFFFF_FFFF;
+
+pub fn handle_ecall(function: usize, param: [usize; 6]) -> SbiRet {
+    match function {
+        FUNCTION_HART_START => hart_start(param[0], param[1], param[2]),
+        FUNCTION_HART_STOP => hart_stop(),
+        FUNCTION_HART_GET_STATUS => hart_get_status(param[0]),
+        FUNCTION_HART_SUSPEND => hart_suspend(param[0], param[1], param[2]),
+        _ => not_supported(),
+    }
+}
+
+fn hart_start(hart_id: usize, start_addr: usize, opaque: usize) -> SbiRet {
+    if hart_id > MAX_HART_ID {
+        return invalid_param();
+    }
+    let hart = hart_local::get(hart_id);
+    match hart.state() {
+        HartState::Running | HartState::StartPending | HartState::Suspended => {
+            return error(SBI_ERR_ALREADY_AVAILABLE)
+        }
+        HartState::Stopped => {}
+        // 没有进入过S层的核不在M层的等待循环中，不能启动
+        HartState::Parked | HartState::Ready | HartState::Panicked => return error(SBI_ERR_FAILED),
+    }
+    if !payload::is_entry_fetchable(start_addr) {
+        return error(SBI_ERR_INVALID_ADDRESS);
+    }
+    hart.start_addr.store(start_addr, Ordering::Relaxed);
+    hart.start_opaque.store(opaque, Ordering::Relaxed);
+    // 检查状态以后，其它核可能已经抢先启动了这个核
+    if !hart.transition(HartState::Stopped, HartState::StartPending) {
+        return error(SBI_ERR_ALREADY_AVAILABLE);
+    }
+    Clint::new(0x2000000 as *mut u8).send_soft(hart_id);
+    SbiRet::ok(0)
+}
+
+// 停止当前核，直到其它核调用hart_start；成功时不会返回到调用处
+fn hart_stop() -> SbiRet {
+    let hart_id = mhartid::read();
+    let hart = hart_local::get(hart_id);
+    if !hart.transition(HartState::Running, HartState::Stopped) {
+        return error(SBI_ERR_FAILED);
+    }
+    // 停止的核不再产生S层的时钟中断，看门狗也一并关闭
+    hart.supervisor_timer.store(u64::MAX, Ordering::Relaxed);
+    hart.deadman_timeout.store(0, Ordering::Relaxed);
+    deadman::rearm(hart_id);
+    unsafe {
+        mip::clear_stimer();
+        mip::clear_ssoft();
+    }
+    let clint = Clint::new(0x2000000 as *mut u8);
+    loop {
+        unsafe { riscv::asm::wfi() };
+        if super::shutdown_requested() {
+            super::halt()
+        }
+        // hart_start先切换状态再发送IPI，清除IPI以后再检查状态不会漏掉启动请求
+        clint.clear_soft(hart_id);
+        if hart.state() == HartState::StartPending {
+            break;
+        }
+    }
+    restart(hart);
+    SbiRet::ok(0)
+}
+
+fn hart_get_status(hart_id: usize) -> SbiRet {
+    if hart_id > MAX_HART_ID {
+        return invalid_param();
+    }
+    let status = match hart_local::get(hart_id).state() {
+        HartState::Running => HART_STATUS_STARTED,
+        HartState::StartPending => HART_STATUS_START_PENDING,
+        HartState::Suspended => HART_STATUS_SUSPENDED,
+        HartState::Parked | HartState::Ready | HartState::Panicked | HartState::Stopped => {
+            HART_STATUS_STOPPED
+        }
+    };
+    SbiRet::ok(status)
+}
+
+// 挂起当前核，直到有中断等待；等待中的中断留给S层处理
+fn hart_suspend(suspend_type: usize, resume_addr: usize, opaque: usize) -> SbiRet {
+    match suspend_type {
+        SUSPEND_TYPE_RETENTIVE => {}
+        SUSPEND_TYPE_NON_RETENTIVE => {
+            if !payload::is_entry_fetchable(resume_addr) {
+                return error(SBI_ERR_INVALID_ADDRESS);
+            }
+        }
+        SUSPEND_TYPE_PLATFORM_RETENTIVE..=SUSPEND_TYPE_PLATFORM_RETENTIVE_END
+        | SUSPEND_TYPE_PLATFORM_NON_RETENTIVE..=SUSPEND_TYPE_PLATFORM_NON_RETENTIVE_END => {
+            return not_supported()
+        }
+        _ => return invalid_param(),
+    }
+    let hart = hart_local::current();
+    if !hart.transition(HartState::Running, HartState::Suspended) {
+        return error(SBI_ERR_FAILED);
+    }
+    unsafe { riscv::asm::wfi() };
+    if super::shutdown_requested() {
+        super::halt()
+    }
+    hart.set_state(HartState::Running);
+    if suspend_type == SUSPEND_TYPE_NON_RETENTIVE {
+        hart.start_addr.store(resume_addr, Ordering::Relaxed);
+        hart.start_opaque.store(opaque, Ordering::Relaxed);
+        restart(hart);
+    }
+    SbiRet::ok(0)
+}
+
+// 单核热复位：要求目标核复位到干净的状态，从新的入口重新进入S层，其余的核不受影响。
+// 目标核收到IPI后，由execute模块完成复位，见execute::warm_reset
+pub fn request_warm_reset(hart_id: usize, entry: usize, opaque: usize) -> SbiRet {
+    if hart_id > MAX_HART_ID {
+        return invalid_param();
+    }
+    let hart = hart_local::get(hart_id);
+    // 停止的核应当使用hart_start启动
+    match hart.state() {
+        HartState::Running | HartState::Suspended => {}
+        _ => return error(SBI_ERR_FAILED),
+    }
+    if !payload::is_entry_fetchable(entry) {
+        return error(SBI_ERR_INVALID_ADDRESS);
+    }
+    hart.reset_opaque.store(opaque, Ordering::Relaxed);
+    hart.reset_addr.store(entry, Ordering::Release);
+    Clint::new(0x2000000 as *mut u8).send_soft(hart_id);
+    SbiRet::ok(0)
+}
+
+// 系统热重启时，让一个核从新的入口重新进入S层：停止的核按hart_start启动，其余的核热复位
+pub fn reboot_hart(hart_id: usize, entry: usize, opaque: usize) -> SbiRet {
+    match hart_local::get(hart_id).state() {
+        HartState::Stopped => hart_start(hart_id, entry, opaque),
+        _ => request_warm_reset(hart_id, entry, opaque),
+    }
+}
+
+// S层的陷入处理程序损坏时，由execute模块停止当前核，等待其它核用hart_start重新启动它。
+// 重新启动以后返回true；当前核不在运行状态、不能停止时返回false
+pub fn stop_broken_hart() -> bool {
+    hart_stop().error != SBI_ERR_FAILED
+}
+
+// 让execute模块在返回S层时改用start_addr作为入口
+#[inline]
+fn restart(hart: &HartLocal) {
+    hart.restart.store(true, Ordering::Relaxed);
+    hart.set_state(HartState::Running);
+}
+
+#[inline]
+fn error(error: usize) -> SbiRet {
+    SbiRet { error, value: 0 }
+}
@@ -0,0 +1,56 @@
+// 旧版SBI的扩展，编号0x00 ~ 0x08，每个扩展只有一个调用
+//
+// 时钟、控制台和发送IPI由rustsbi框架处理，清除IPI和关机在这里处理，关机和SRST扩展的关机相同；
+// 三个远程栅障没有实现。基本扩展的probe_extension按这里的列表回答旧版扩展，
+// 保证探测的结果和实际的处理一致
+use super::not_supported;
+use rustsbi::SbiRet;
+
+pub const LEGACY_SET_TIMER: usize = 0x00;
+pub const LEGACY_CONSOLE_PUTCHAR: usize = 0x01;
+pub const LEGACY_CONSOLE_GETCHAR: usize = 0x02;
+pub const LEGACY_CLEAR_IPI: usize = 0x03;
+pub const LEGACY_SEND_IPI: usize = 0x04;
+pub const LEGACY_SHUTDOWN: usize = 0x08;
+
+// 交给rustsbi框架处理的旧版扩展
+const HANDLED_BY_RUSTSBI: [usize; 4] = [
+    LEGACY_SET_TIMER,
+    LEGACY_CONSOLE_PUTCHAR,
+    LEGACY_CONSOLE_GETCHAR,
+    LEGACY_SEND_IPI,
+];
+
+#[inline]
+pub fn is_legacy(extension: usize) -> bool {
+    extension <= LEGACY_SHUTDOWN
+}
+
+#[inline]
+pub fn handled_by_rustsbi(extension: usize) -> bool {
+    HANDLED_BY_RUSTSBI.contains(&extension)
+}
+
+#[inline]
+fn is_implemented(extension: usize) -> bool {
+    handled_by_rustsbi(extension) || extension == LEGACY_CLEAR_IPI || extension == LEGACY_SHUTDOWN
+}
+
+/// probe_extension对旧版扩展的回答：实现了的返回1，没有实现的返回0
+#[inline]
+pub fn probe(extension: usize) -> SbiRet {
+    SbiRet::ok(is_implemented(extension) as usize)
+}
+
+/// 处理不交给rustsbi框架的旧版调用
+pub fn handle_ecall(extension: usize) -> SbiRet {
+    match extension {
+        // 清除当前核等待中的S层软件中断；旧版调用只在a0中返回0
+        LEGACY_CLEAR_IPI => {
+            unsafe { riscv::register::mip::clear_ssoft() };
+            SbiRet::ok(0)
+        }
+        LEGACY_SHUTDOWN => super::shutdown(0),
+        _ => not_supported(),
+    }
+}
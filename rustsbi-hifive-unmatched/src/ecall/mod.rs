@@ -0,0 +1,88 @@
+// 固件需要自己处理的SBI调用放在这里，其余的调用交给rustsbi框架处理
+mod dbcn;
+mod hsm;
+mod legacy;
+mod pmu;
+mod srst;
+mod usage;
+mod vendor;
+
+pub use dbcn::set_max_transfer as set_console_max_transfer;
+pub use hsm::{reboot_hart, request_warm_reset, stop_broken_hart};
+pub use srst::{halt, shutdown, shutdown_requested, warm_reboot};
+
+use rustsbi::SbiRet;
+
+const EXTENSION_BASE: usize = 0x10;
+const FUNCTION_BASE_PROBE_EXTENSION: usize = 0x3;
+const EXTENSION_RFENCE: usize = 0x52464E43;
+const FUNCTION_RFENCE_REMOTE_HFENCE_GVMA_VMID: usize = 0x3;
+const FUNCTION_RFENCE_REMOTE_HFENCE_VVMA: usize = 0x6;
+
+const SBI_ERR_FAILED: usize = usize::from_ne_bytes(isize::to_ne_bytes(-1));
+const SBI_ERR_NOT_SUPPORTED: usize = usize::from_ne_bytes(isize::to_ne_bytes(-2));
+const SBI_ERR_INVALID_PARAM: usize = usize::from_ne_bytes(isize::to_ne_bytes(-3));
+const SBI_ERR_DENIED: usize = usize::from_ne_bytes(isize::to_ne_bytes(-4));
+const SBI_ERR_INVALID_ADDRESS: usize = usize::from_ne_bytes(isize::to_ne_bytes(-5));
+const SBI_ERR_ALREADY_AVAILABLE: usize = usize::from_ne_bytes(isize::to_ne_bytes(-6));
+const SBI_ERR_ALREADY_STARTED: usize = usize::from_ne_bytes(isize::to_ne_bytes(-7));
+const SBI_ERR_ALREADY_STOPPED: usize = usize::from_ne_bytes(isize::to_ne_bytes(-8));
+
+pub fn handle_ecall(extension: usize, function: usize, param: [usize; 6]) -> SbiRet {
+    usage::mark_used(extension);
+    match extension {
+        // rustsbi框架不知道哪些旧版扩展由固件处理，由legacy模块回答
+        EXTENSION_BASE
+            if function == FUNCTION_BASE_PROBE_EXTENSION && legacy::is_legacy(param[0]) =>
+        {
+            legacy::probe(param[0])
+        }
+        // 调试控制台扩展也由固件实现，rustsbi框架同样不知道
+        EXTENSION_BASE
+            if function == FUNCTION_BASE_PROBE_EXTENSION && param[0] == dbcn::EXTENSION_DBCN =>
+        {
+            SbiRet::ok(1)
+        }
+        // FU740的处理器核都没有H扩展，hfence系列的远程栅障一律返回不支持
+        EXTENSION_RFENCE
+            if (FUNCTION_RFENCE_REMOTE_HFENCE_GVMA_VMID..=FUNCTION_RFENCE_REMOTE_HFENCE_VVMA)
+                .contains(&function) =>
+        {
+            not_supported()
+        }
+        dbcn::EXTENSION_DBCN => dbcn::handle_ecall(function, param),
+        hsm::EXTENSION_HSM => hsm::handle_ecall(function, param),
+        pmu::EXTENSION_PMU => pmu::handle_ecall(function, param),
+        srst::EXTENSION_SRST => srst::handle_ecall(function, param),
+        extension if legacy::is_legacy(extension) && !legacy::handled_by_rustsbi(extension) => {
+            legacy::handle_ecall(extension)
+        }
+        vendor::EXTENSION_UNMATCHED => vendor::handle_ecall(function, param),
+        _ => rustsbi::ecall(extension, function, param),
+    }
+}
+
+#[inline]
+fn not_supported() -> SbiRet {
+    SbiRet {
+        error: SBI_ERR_NOT_SUPPORTED,
+        value: 0,
+    }
+}
+
+#[inline]
+fn invalid_param() -> SbiRet {
+    SbiRet {
+        error: SBI_ERR_INVALID_PARAM,
+        value: 0,
+    }
+}
+
+/// 固件的状态不允许处理这次调用时返回
+#[inline]
+pub fn failed() -> SbiRet {
+    SbiRet {
+        error: SBI_ERR_FAILED,
+        value: 0,
+    }
+}
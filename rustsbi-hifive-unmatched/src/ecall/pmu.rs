@@ -0,0 +1,263 @@
+// 性能监控单元（PMU）扩展
+//
+// U74的计数器有mcycle、minstret和两个可编程的mhpmcounter3、mhpmcounter4；
+// 计数器编号按规范对应cycle、time、instret和hpmcounter3起的CSR。
+// U74没有Sscofpmf扩展，不能按特权级过滤，过滤标志位被忽略。
+//
+// 和规范有意不同的地方：规范中counter_fw_read只读取固件计数器，这个平台没有固件计数器，
+// 这里也接受硬件计数器，返回它完整的64位值。这样M层不打开mcounteren时S层也能读出计数值
+use super::{invalid_param, not_supported, SBI_ERR_ALREADY_STARTED, SBI_ERR_ALREADY_STOPPED};
+use crate::hart_local;
+use crate::peripheral::Clint;
+use core::sync::atomic::Ordering;
+use rustsbi::SbiRet;
+
+pub const EXTENSION_PMU: usize = 0x504D55;
+
+const FUNCTION_NUM_COUNTERS: usize = 0x0;
+const FUNCTION_COUNTER_GET_INFO: usize = 0x1;
+const FUNCTION_COUNTER_CONFIG_MATCHING: usize = 0x2;
+const FUNCTION_COUNTER_START: usize = 0x3;
+const FUNCTION_COUNTER_STOP: usize = 0x4;
+const FUNCTION_COUNTER_FW_READ: usize = 0x5;
+const FUNCTION_COUNTER_FW_READ_HI: usize = 0x6;
+
+const COUNTER_CYCLE: usize = 0;
+const COUNTER_TIME: usize = 1;
+const COUNTER_INSTRET: usize = 2;
+const COUNTER_HPM3: usize = 3;
+const COUNTER_HPM4: usize = 4;
+const NUM_COUNTERS: usize = 5;
+
+// U74的可编程计数器是40位的，mcycle和minstret是64位的
+const HPM_COUNTER_WIDTH: usize = 40;
+
+const CONFIG_FLAG_SKIP_MATCH: usize = 1 << 0;
+const CONFIG_FLAG_CLEAR_VALUE: usize = 1 << 1;
+const CONFIG_FLAG_AUTO_START: usize = 1 << 2;
+const START_FLAG_SET_INIT_VALUE: usize = 1 << 0;
+const STOP_FLAG_RESET: usize = 1 << 0;
+
+// 事件编号的高位是事件类型，低16位是事件代码
+const EVENT_TYPE_HARDWARE: usize = 0;
+const EVENT_TYPE_RAW: usize = 2;
+const EVENT_HW_CPU_CYCLES: usize = 1;
+const EVENT_HW_INSTRUCTIONS: usize = 2;
+
+pub fn handle_ecall(function: usize, param: [usize; 6]) -> SbiRet {
+    match function {
+        FUNCTION_NUM_COUNTERS => SbiRet::ok(NUM_COUNTERS),
+        FUNCTION_COUNTER_GET_INFO => counter_get_info(param[0]),
+        FUNCTION_COUNTER_CONFIG_MATCHING => {
+            counter_config_matching(param[0], param[1], param[2], param[3], param[4])
+        }
+        FUNCTION_COUNTER_START => counter_start(param[0], param[1], param[2], param[3]),
+        FUNCTION_COUNTER_STOP => counter_stop(param[0], param[1], param[2]),
+        FUNCTION_COUNTER_FW_READ => counter_read(param[0]),
+        // RV64上counter_read已经返回完整的64位，高位总是0
+        FUNCTION_COUNTER_FW_READ_HI if param[0] < NUM_COUNTERS => SbiRet::ok(0),
+        FUNCTION_COUNTER_FW_READ_HI => invalid_param(),
+        _ => not_supported(),
+    }
+}
+
+// 硬件计数器的信息：低12位是CSR编号，第12到17位是宽度减一
+fn counter_get_info(counter: usize) -> SbiRet {
+    let width = match counter {
+        COUNTER_CYCLE | COUNTER_TIME | COUNTER_INSTRET => 64,
+        COUNTER_HPM3 | COUNTER_HPM4 => HPM_COUNTER_WIDTH,
+        _ => return invalid_param(),
+    };
+    SbiRet::ok((0xC00 + counter) | ((width - 1) << 12))
+}
+
+// 在base和mask指定的计数器中找一个能统计这个事件、而且还没有被占用的计数器
+fn counter_config_matching(
+    base: usize,
+    mask: usize,
+    flags: usize,
+    event_idx: usize,
+    event_data: usize,
+) -> SbiRet {
+    let counters = match counter_set(base, mask) {
+        Some(counters) => counters,
+        None => return invalid_param(),
+    };
+    let hart = hart_local::current();
+    let configured = hart.pmu_configured.load(Ordering::Relaxed);
+    let counter = if flags & CONFIG_FLAG_SKIP_MATCH != 0 {
+        // S层确认第一个计数器已经配置好了，直接使用它
+        match (0..NUM_COUNTERS).find(|&i| counters & (1 << i) != 0) {
+            Some(counter) if configured & (1 << counter) != 0 => counter,
+            _ => return invalid_param(),
+        }
+    } else {
+        let candidates = match (event_idx >> 16, event_idx & 0xFFFF) {
+            (EVENT_TYPE_HARDWARE, EVENT_HW_CPU_CYCLES) => 1 << COUNTER_CYCLE,
+            (EVENT_TYPE_HARDWARE, EVENT_HW_INSTRUCTIONS) => 1 << COUNTER_INSTRET,
+            // 原始事件直接写入mhpmevent，格式见U74手册：低8位是事件类，其余位是事件掩码
+            (EVENT_TYPE_RAW, 0) if event_data != 0 => (1 << COUNTER_HPM3) | (1 << COUNTER_HPM4),
+            _ => return not_supported(),
+        };
+        let free = counters & candidates & !configured;
+        let counter = match (0..NUM_COUNTERS).find(|&i| free & (1 << i) != 0) {
+            Some(counter) => counter,
+            None => return not_supported(),
+        };
+        if counter >= COUNTER_HPM3 {
+            write_event(counter, event_data);
+        }
+        hart.pmu_configured
+            .fetch_or(1 << counter, Ordering::Relaxed);
+        counter
+    };
+    // 没有要求自动开始时，计数器停在配置好的状态，等S层调用counter_start；
+    // 先停止再清零，这样读出的值仍然是0
+    let auto_start = flags & CONFIG_FLAG_AUTO_START != 0;
+    if !auto_start {
+        set_inhibit(1 << counter, true);
+    }
+    if flags & CONFIG_FLAG_CLEAR_VALUE != 0 {
+        write_counter(counter, 0);
+    }
+    if auto_start {
+        set_inhibit(1 << counter, false);
+    }
+    SbiRet::ok(counter)
+}
+
+fn counter_start(base: usize, mask: usize, flags: usize, initial_value: usize) -> SbiRet {
+    let counters = match configured_set(base, mask) {
+        Some(counters) => counters,
+        None => return invalid_param(),
+    };
+    if read_inhibit() & counters != counters {
+        return error(SBI_ERR_ALREADY_STARTED);
+    }
+    if flags & START_FLAG_SET_INIT_VALUE != 0 {
+        for counter in (0..NUM_COUNTERS).filter(|&i| counters & (1 << i) != 0) {
+            write_counter(counter, initial_value as u64);
+        }
+    }
+    set_inhibit(counters, false);
+    SbiRet::ok(0)
+}
+
+fn counter_stop(base: usize, mask: usize, flags: usize) -> SbiRet {
+    let counters = match configured_set(base, mask) {
+        Some(counters) => counters,
+        None => return invalid_param(),
+    };
+    if read_inhibit() & counters != 0 {
+        return error(SBI_ERR_ALREADY_STOPPED);
+    }
+    set_inhibit(counters, true);
+    if flags & STOP_FLAG_RESET != 0 {
+        for counter in (COUNTER_HPM3..NUM_COUNTERS).filter(|&i| counters & (1 << i) != 0) {
+            write_event(counter, 0);
+        }
+        hart_local::current()
+            .pmu_configured
+            .fetch_and(!counters, Ordering::Relaxed);
+    }
+    SbiRet::ok(0)
+}
+
+// 读出当前核计数器的完整64位值，也接受硬件计数器，见本模块开头的说明
+fn counter_read(counter: usize) -> SbiRet {
+    if counter >= NUM_COUNTERS {
+        return invalid_param();
+    }
+    SbiRet::ok(read_counter(counter) as usize)
+}
+
+// 把base和mask转换为计数器的位图，超出计数器个数时返回None
+#[inline]
+fn counter_set(base: usize, mask: usize) -> Option<usize> {
+    if base >= NUM_COUNTERS {
+        return None;
+    }
+    let counters = mask.checked_shl(base as u32)?;
+    if counters >> base != mask || counters >> NUM_COUNTERS != 0 {
+        return None;
+    }
+    Some(counters)
+}
+
+// 除了超出范围以外，还要求每个计数器都已经配置过
+#[inline]
+fn configured_set(base: usize, mask: usize) -> Option<usize> {
+    let counters = counter_set(base, mask)?;
+    let configured = hart_local::current().pmu_configured.load(Ordering::Relaxed);
+    if counters == 0 || counters & !configured != 0 {
+        return None;
+    }
+    Some(counters)
+}
+
+fn read_counter(counter: usize) -> u64 {
+    let value: usize;
+    unsafe {
+        match counter {
+            COUNTER_CYCLE => core::arch::asm!("csrr {}, mcycle", out(reg) value),
+            COUNTER_TIME => {
+                let mtime = Clint::new(0x2000000 as *mut u8).get_mtime();
+                return crate::time_scale::supervisor_time(mtime);
+            }
+            COUNTER_INSTRET => core::arch::asm!("csrr {}, minstret", out(reg) value),
+            COUNTER_HPM3 => core::arch::asm!("csrr {}, 0xB03", out(reg) value),
+            COUNTER_HPM4 => core::arch::asm!("csrr {}, 0xB04", out(reg) value),
+            _ => unreachable!(),
+        }
+    }
+    value as u64
+}
+
+// time计数器来自CLINT，不能由M层写入
+fn write_counter(counter: usize, value: u64) {
+    let value = value as usize;
+    unsafe {
+        match counter {
+            COUNTER_CYCLE => core::arch::asm!("csrw mcycle, {}", in(reg) value),
+            COUNTER_INSTRET => core::arch::asm!("csrw minstret, {}", in(reg) value),
+            COUNTER_HPM3 => core::arch::asm!("csrw 0xB03, {}", in(reg) value),
+            COUNTER_HPM4 => core::arch::asm!("csrw 0xB04, {}", in(reg) value),
+            _ => {}
+        }
+    }
+}
+
+fn write_event(counter: usize, event: usize) {
+    unsafe {
+        match counter {
+            COUNTER_HPM3 => core::arch::asm!("csrw 0x323, {}", in(reg) event),
+            COUNTER_HPM4 => core::arch::asm!("csrw 0x324, {}", in(reg) event),
+            _ => {}
+        }
+    }
+}
+
+// mcountinhibit（0x320）中置位的计数器停止计数；time不受它控制，总是视为正在计数
+#[inline]
+fn read_inhibit() -> usize {
+    let bits: usize;
+    unsafe { core::arch::asm!("csrr {}, 0x320", out(reg) bits) };
+    bits & !(1 << COUNTER_TIME)
+}
+
+#[inline]
+fn set_inhibit(counters: usize, inhibit: bool) {
+    let counters = counters & !(1 << COUNTER_TIME);
+    unsafe {
+        if inhibit {
+            core::arch::asm!("csrs 0x320, {}", in(reg) counters);
+        } else {
+            core::arch::asm!("csrc 0x320, {}", in(reg) counters);
+        }
+    }
+}
+
+#[inline]
+fn error(error: usize) -> SbiRet {
+    SbiRet { error, value: 0 }
+}
@@ -0,0 +1,98 @@
+// 系统复位扩展。板上的电源管理芯片需要通过I2C控制，这里的关机只是让所有的核停止运行
+use super::{invalid_param, not_supported, SBI_ERR_FAILED};
+use crate::console::println;
+use crate::dtb_handoff;
+use crate::hart_local::MAX_HART_ID;
+use crate::payload;
+use crate::peripheral::Clint;
+use core::sync::atomic::{AtomicBool, Ordering};
+use rustsbi::SbiRet;
+
+pub const EXTENSION_SRST: usize = 0x53525354;
+
+const FUNCTION_SYSTEM_RESET: usize = 0x0;
+
+const RESET_TYPE_SHUTDOWN: usize = 0x0000_0000;
+const RESET_TYPE_COLD_REBOOT: usize = 0x0000_0001;
+const RESET_TYPE_WARM_REBOOT: usize = 0x0000_0002;
+const RESET_REASON_NO_REASON: usize = 0x0000_0000;
+
+static SHUTDOWN_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+pub fn handle_ecall(function: usize, param: [usize; 6]) -> SbiRet {
+    match function {
+        FUNCTION_SYSTEM_RESET => system_reset(param[0], param[1]),
+        _ => not_supported(),
+    }
+}
+
+fn system_reset(reset_type: usize, reset_reason: usize) -> SbiRet {
+    match reset_type {
+        RESET_TYPE_SHUTDOWN => shutdown(reset_reason),
+        RESET_TYPE_WARM_REBOOT => warm_reboot(),
+        // 冷重启需要电源管理芯片配合，暂不支持
+        RESET_TYPE_COLD_REBOOT => not_supported(),
+        _ => invalid_param(),
+    }
+}
+
+// 热重启不经过电源管理芯片，只是让进入过S层的核全部从S层的入口重新开始。
+// 各个核在M层原地复位（见execute::warm_reset），不经过_start，也不重新初始化固件。
+// S层交还过设备树时，重新开始以后使用交还的设备树。固件panic以后也从这里热重启
+pub fn warm_reboot() -> SbiRet {
+    let entry = crate::SUPERVISOR_ENTRY.load(Ordering::Acquire);
+    if !payload::is_entry_fetchable(entry) {
+        return SbiRet {
+            error: SBI_ERR_FAILED,
+            value: 0,
+        };
+    }
+    let dtb = dtb_handoff::next_dtb();
+    println!(
+        "[rustsbi] warm reboot requested by hart {}, restart at {:#x}, device tree {:#x}",
+        riscv::register::mhartid::read(),
+        entry,
+        dtb
+    );
+    let hart_mask = crate::SUPERVISOR_HART_MASK.load(Ordering::Acquire);
+    for hart_id in (0..=MAX_HART_ID).filter(|&id| hart_mask & (1 << id) != 0) {
+        super::reboot_hart(hart_id, entry, dtb);
+    }
+    SbiRet::ok(0)
+}
+
+pub fn shutdown(reset_reason: usize) -> ! {
+    println!(
+        "[rustsbi] system shutdown requested by hart {}, reason {:#x}",
+        riscv::register::mhartid::read(),
+        reset_reason
+    );
+    // 只在正常关机时打印统计信息
+    if reset_reason == RESET_REASON_NO_REASON {
+        super::usage::print_summary();
+        crate::heap_usage::print_summary();
+        #[cfg(feature = "stack-watermark")]
+        crate::stack_watermark::print_summary();
+    }
+    SHUTDOWN_REQUESTED.store(true, Ordering::Release);
+    let clint = Clint::new(0x2000000 as *mut u8);
+    let this_hart = riscv::register::mhartid::read();
+    for hart_id in 1..=MAX_HART_ID {
+        if hart_id != this_hart {
+            clint.send_soft(hart_id);
+        }
+    }
+    halt()
+}
+
+/// 其余的核收到软件中断后，检查是否需要跟随关机
+#[inline]
+pub fn shutdown_requested() -> bool {
+    SHUTDOWN_REQUESTED.load(Ordering::Acquire)
+}
+
+pub fn halt() -> ! {
+    loop {
+        unsafe { core::arch::asm!(".word 0x30500073") }; // cease
+    }
+}
@@ -0,0 +1,63 @@
+// 记录S层用过哪些SBI扩展，关机时打印出来，可以据此决定哪些扩展能够裁剪掉
+use crate::console::println;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+const TRACKED_EXTENSIONS: [(usize, &str); 18] = [
+    (0x00, "legacy-set-timer"),
+    (0x01, "legacy-console-putchar"),
+    (0x02, "legacy-console-getchar"),
+    (0x03, "legacy-clear-ipi"),
+    (0x04, "legacy-send-ipi"),
+    (0x05, "legacy-remote-fence-i"),
+    (0x06, "legacy-remote-sfence-vma"),
+    (0x07, "legacy-remote-sfence-vma-asid"),
+    (0x08, "legacy-shutdown"),
+    (0x10, "base"),
+    (0x54494D45, "timer"),
+    (0x735049, "ipi"),
+    (0x52464E43, "rfence"),
+    (0x48534D, "hsm"),
+    (0x53525354, "srst"),
+    (0x504D55, "pmu"),
+    (0x4442434E, "dbcn"),
+    (super::vendor::EXTENSION_UNMATCHED, "unmatched"),
+];
+
+// 按TRACKED_EXTENSIONS的顺序，第i位为1表示第i个扩展被S层调用过
+static USED: AtomicUsize = AtomicUsize::new(0);
+
+#[inline]
+pub fn mark_used(extension: usize) {
+    if let Some(idx) = TRACKED_EXTENSIONS
+        .iter()
+        .position(|(id, _)| *id == extension)
+    {
+        // 只写一次，避免每次调用都写同一个缓存行
+        if USED.load(Ordering::Relaxed) & (1 << idx) == 0 {
+            USED.fetch_or(1 << idx, Ordering::Relaxed);
+        }
+    }
+}
+
+pub fn used_bitmap() -> usize {
+    USED.load(Ordering::Relaxed)
+}
+
+pub fn print_summary() {
+    let mut used = Vec::new();
+    let mut unused = Vec::new();
+    let bitmap = used_bitmap();
+    for (idx, (_, name)) in TRACKED_EXTENSIONS.iter().enumerate() {
+        if bitmap & (1 << idx) != 0 {
+            used.push(*name);
+        } else {
+            unused.push(*name);
+        }
+    }
+    println!(
+        "[rustsbi] extensions used by supervisor: {}",
+        used.join(", ")
+    );
+    println!("[rustsbi] extensions never used: {}", unused.join(", "));
+}
@@ -0,0 +1,289 @@
+// 本固件专有的SBI扩展，编号位于SBI规范的固件专用区间（0x0A000000 ~ 0x0AFFFFFF）
+use super::{
+    invalid_param, not_supported, SBI_ERR_DENIED, SBI_ERR_FAILED, SBI_ERR_INVALID_ADDRESS,
+};
+use crate::deadman;
+use crate::dtb_handoff::{self, HandoffError};
+use crate::hart_csr_utils;
+use crate::pma::{self, PmaError};
+use rustsbi::SbiRet;
+
+pub const EXTENSION_UNMATCHED: usize = 0x0A55_4E4D; // "UNM"
+
+const FUNCTION_READ_SUPERVISOR_CSR: usize = 0x0;
+const FUNCTION_DEADMAN_ENABLE: usize = 0x1;
+const FUNCTION_DEADMAN_PET: usize = 0x2;
+const FUNCTION_REQUEST_MEMORY_ATTRIBUTE: usize = 0x3;
+const FUNCTION_READ_TRAP_PROFILE: usize = 0x4;
+const FUNCTION_WARM_RESET_HART: usize = 0x5;
+const FUNCTION_READ_STACK_WATERMARK: usize = 0x6;
+const FUNCTION_SUBMIT_DEVICE_TREE: usize = 0x7;
+const FUNCTION_GET_PLATFORM_INFO: usize = 0x8;
+const FUNCTION_READ_MEMORY_TEST_RESULT: usize = 0x9;
+const FUNCTION_READ_BOARD_TEMPERATURE: usize = 0xA;
+const FUNCTION_INJECT_PANIC: usize = 0xB;
+const FUNCTION_READ_HEAP_USAGE: usize = 0xC;
+const FUNCTION_SET_TIME_SCALE: usize = 0xD;
+const FUNCTION_READ_BUILD_CONFIG: usize = 0xE;
+const FUNCTION_READ_HART_EXCLUSION: usize = 0xF;
+const FUNCTION_READ_EXTENSION_USAGE: usize = 0x10;
+const FUNCTION_READ_UART_INIT_ATTEMPTS: usize = 0x11;
+const FUNCTION_GET_HART_SET_LINE: usize = 0x12;
+const FUNCTION_READ_COLD_BOOT_TICKS: usize = 0x13;
+
+pub fn handle_ecall(function: usize, param: [usize; 6]) -> SbiRet {
+    match function {
+        FUNCTION_READ_SUPERVISOR_CSR => read_supervisor_csr(param[0]),
+        FUNCTION_DEADMAN_ENABLE => deadman_enable(param[0], param[1]),
+        FUNCTION_DEADMAN_PET => deadman_pet(),
+        FUNCTION_REQUEST_MEMORY_ATTRIBUTE => request_memory_attribute(param[0], param[1], param[2]),
+        FUNCTION_READ_TRAP_PROFILE => read_trap_profile(param[0], param[1]),
+        FUNCTION_WARM_RESET_HART => super::request_warm_reset(param[0], param[1], param[2]),
+        FUNCTION_READ_STACK_WATERMARK => read_stack_watermark(param[0]),
+        FUNCTION_SUBMIT_DEVICE_TREE => submit_device_tree(param[0], param[1]),
+        // 返回平台信息结构的物理地址，格式见platform_info模块
+        FUNCTION_GET_PLATFORM_INFO => SbiRet::ok(crate::platform_info::address()),
+        FUNCTION_READ_MEMORY_TEST_RESULT => read_memory_test_result(),
+        FUNCTION_READ_BOARD_TEMPERATURE => read_board_temperature(param[0]),
+        FUNCTION_INJECT_PANIC => inject_panic(param[0]),
+        FUNCTION_READ_HEAP_USAGE => read_heap_usage(),
+        FUNCTION_SET_TIME_SCALE => set_time_scale(param[0], param[1]),
+        FUNCTION_READ_BUILD_CONFIG => read_build_config(param[0]),
+        FUNCTION_READ_HART_EXCLUSION => read_hart_exclusion(param[0]),
+        // 返回关机时打印的扩展使用情况，位的顺序见usage模块
+        FUNCTION_READ_EXTENSION_USAGE => SbiRet::ok(super::usage::used_bitmap()),
+        FUNCTION_READ_UART_INIT_ATTEMPTS => read_uart_init_attempts(),
+        // 返回启动日志中列出各个核的那一行的物理地址，以0结尾
+        FUNCTION_GET_HART_SET_LINE => SbiRet::ok(crate::platform_info::hart_set_line_address()),
+        // 返回冷启动时启动核在固件中经过的mtime周期数
+        FUNCTION_READ_COLD_BOOT_TICKS => {
+            SbiRet::ok(crate::COLD_BOOT_TICKS.load(core::sync::atomic::Ordering::Relaxed))
+        }
+        _ => not_supported(),
+    }
+}
+
+// 只允许读取白名单中的S层寄存器，其余的寄存器编号返回参数错误
+#[inline]
+fn read_supervisor_csr(csr: usize) -> SbiRet {
+    match hart_csr_utils::read_supervisor_csr(csr) {
+        Some(value) => SbiRet::ok(value),
+        None => invalid_param(),
+    }
+}
+
+// 参数为超时的时钟周期数和标志位，超时为0时关闭看门狗
+#[inline]
+fn deadman_enable(timeout: usize, flags: usize) -> SbiRet {
+    if flags & !deadman::FLAG_RESET_ON_EXPIRE != 0 {
+        return invalid_param();
+    }
+    deadman::enable(riscv::register::mhartid::read(), timeout as u64, flags);
+    SbiRet::ok(0)
+}
+
+// 看门狗已经到期过时返回拒绝，S层可以据此知道自己曾经被判定为卡死
+#[inline]
+fn deadman_pet() -> SbiRet {
+    if deadman::pet(riscv::register::mhartid::read()) {
+        SbiRet::ok(0)
+    } else {
+        SbiRet {
+            error: SBI_ERR_DENIED,
+            value: 0,
+        }
+    }
+}
+
+// 参数为区域的起始地址、长度和申请的属性。FU740的PMA不能修改，只有和硬件一致的申请才会成功；
+// 没有从设备树读到内存范围时，这个调用不可用
+#[inline]
+fn request_memory_attribute(base: usize, size: usize, attribute: usize) -> SbiRet {
+    let attribute = match pma::Attribute::from_usize(attribute) {
+        Some(attribute) => attribute,
+        None => return invalid_param(),
+    };
+    match pma::request(base, size, attribute) {
+        Ok(()) => SbiRet::ok(0),
+        Err(PmaError::NoMemoryMap) | Err(PmaError::Unsupported) => not_supported(),
+        Err(PmaError::InvalidRange) => invalid_param(),
+        Err(PmaError::InvalidAddress) => SbiRet {
+            error: SBI_ERR_INVALID_ADDRESS,
+            value: 0,
+        },
+    }
+}
+
+// 参数为陷入种类和统计项，返回当前核的统计值；没有打开trap-profile功能时不支持
+#[cfg(feature = "trap-profile")]
+#[inline]
+fn read_trap_profile(trap_kind: usize, field: usize) -> SbiRet {
+    match crate::trap_profile::read(trap_kind, field) {
+        Some(value) => SbiRet::ok(value as usize),
+        None => invalid_param(),
+    }
+}
+
+#[cfg(not(feature = "trap-profile"))]
+#[inline]
+fn read_trap_profile(_trap_kind: usize, _field: usize) -> SbiRet {
+    not_supported()
+}
+
+// 参数为核编号，返回这个核的M层栈用过的最大字节数；没有打开stack-watermark功能时不支持
+#[cfg(feature = "stack-watermark")]
+#[inline]
+fn read_stack_watermark(hart_id: usize) -> SbiRet {
+    if hart_id > crate::hart_local::MAX_HART_ID {
+        return invalid_param();
+    }
+    SbiRet::ok(crate::stack_watermark::peak_usage(hart_id))
+}
+
+#[cfg(not(feature = "stack-watermark"))]
+#[inline]
+fn read_stack_watermark(_hart_id: usize) -> SbiRet {
+    not_supported()
+}
+
+// 参数为设备树的物理地址和长度。固件复制一份，之后热重启时交给S层
+#[inline]
+fn submit_device_tree(base: usize, size: usize) -> SbiRet {
+    match dtb_handoff::submit(base, size) {
+        Ok(()) => SbiRet::ok(0),
+        Err(HandoffError::InvalidAddress) => SbiRet {
+            error: SBI_ERR_INVALID_ADDRESS,
+            value: 0,
+        },
+        Err(HandoffError::TooLarge) | Err(HandoffError::InvalidDeviceTree) => invalid_param(),
+    }
+}
+
+// 返回启动时内存测试中出错的字数；没有测试任何内存块时返回失败，没有打开memory-test功能时不支持
+#[cfg(feature = "memory-test")]
+#[inline]
+fn read_memory_test_result() -> SbiRet {
+    match crate::memory_test::result() {
+        (0, _) => SbiRet {
+            error: SBI_ERR_FAILED,
+            value: 0,
+        },
+        (_, errors) => SbiRet::ok(errors),
+    }
+}
+
+#[cfg(not(feature = "memory-test"))]
+#[inline]
+fn read_memory_test_result() -> SbiRet {
+    not_supported()
+}
+
+// 参数为通道，0是板子，1是FU740芯片；返回启动时读到的温度，单位是千分之一摄氏度，按有符号数解释。
+// 启动时没有读到时返回失败；设备树中没有传感器，或者没有打开board-sensors功能时不支持
+#[cfg(feature = "board-sensors")]
+#[inline]
+fn read_board_temperature(channel: usize) -> SbiRet {
+    use crate::board_sensors;
+    if channel > 1 {
+        return invalid_param();
+    }
+    if !board_sensors::present() {
+        return not_supported();
+    }
+    match board_sensors::temperature(channel) {
+        Some(value) => SbiRet::ok(value as usize),
+        None => SbiRet {
+            error: SBI_ERR_FAILED,
+            value: 0,
+        },
+    }
+}
+
+#[cfg(not(feature = "board-sensors"))]
+#[inline]
+fn read_board_temperature(_channel: usize) -> SbiRet {
+    not_supported()
+}
+
+// 参数为panic以后的处理方式，0是停机，1是热重启，2是进入监视器。固件按这个方式处理，
+// 然后在当前核上panic，成功时不返回。只用于测试，没有打开panic-injection功能时不支持
+#[cfg(feature = "panic-injection")]
+#[inline]
+fn inject_panic(action: usize) -> SbiRet {
+    use crate::panic_action::{self, PanicAction};
+    let action = match PanicAction::from_usize(action) {
+        Some(PanicAction::Monitor) if !cfg!(feature = "monitor") => return not_supported(),
+        Some(action) => action,
+        None => return invalid_param(),
+    };
+    panic_action::set_action(action);
+    panic!("injected by supervisor, panic action {:?}", action)
+}
+
+#[cfg(not(feature = "panic-injection"))]
+#[inline]
+fn inject_panic(_action: usize) -> SbiRet {
+    not_supported()
+}
+
+// 参数为S层时间相对mtime速率的分子和分母，之后所有核读到的时间按新的速率增长。
+// 只用于测试，没有打开time-scale功能时不支持
+#[cfg(feature = "time-scale")]
+#[inline]
+fn set_time_scale(numerator: usize, denominator: usize) -> SbiRet {
+    if crate::time_scale::set(numerator as u64, denominator as u64) {
+        SbiRet::ok(0)
+    } else {
+        invalid_param()
+    }
+}
+
+#[cfg(not(feature = "time-scale"))]
+#[inline]
+fn set_time_scale(_numerator: usize, _denominator: usize) -> SbiRet {
+    not_supported()
+}
+
+// 返回固件的堆用过的最大字节数；扩展堆之前分配的内存被改写过时返回失败
+#[inline]
+fn read_heap_usage() -> SbiRet {
+    if !crate::heap_usage::bootstrap_intact() {
+        return SbiRet {
+            error: SBI_ERR_FAILED,
+            value: 0,
+        };
+    }
+    SbiRet::ok(crate::HEAP_ALLOCATOR.peak_usage())
+}
+
+// 参数为配置项的编号，返回编译时的配置，编号和各项的含义见build_config模块
+#[inline]
+fn read_build_config(field: usize) -> SbiRet {
+    match crate::build_config::read(field) {
+        Some(value) => SbiRet::ok(value),
+        None => invalid_param(),
+    }
+}
+
+// 参数为核编号，返回启动时这个核没有进入S层的原因，0表示进入了S层，取值见hart_local::Exclusion
+#[inline]
+fn read_hart_exclusion(hart_id: usize) -> SbiRet {
+    if hart_id > crate::hart_local::MAX_HART_ID {
+        return invalid_param();
+    }
+    SbiRet::ok(crate::hart_local::get(hart_id).exclusion() as usize)
+}
+
+// 返回最近一次打开UART1时尝试的次数，设置没有生效时返回失败，值仍然是尝试的次数；
+// 没有打开过UART1时不支持
+#[inline]
+fn read_uart_init_attempts() -> SbiRet {
+    match crate::peripheral::uart::uart1_init_result() {
+        Some(Ok(attempts)) => SbiRet::ok(attempts),
+        Some(Err(attempts)) => SbiRet {
+            error: SBI_ERR_FAILED,
+            value: attempts,
+        },
+        None => not_supported(),
+    }
+}
@@ -1,32 +1,100 @@
+use crate::console::println;
+use crate::deadman;
+use crate::ecall;
 use crate::feature;
+use crate::hart_local::{self, HartState};
+use crate::peripheral::Clint;
 use crate::runtime::{MachineTrap, Runtime, SupervisorContext};
 use core::{
     ops::{Generator, GeneratorState},
     pin::Pin,
+    sync::atomic::{AtomicU8, Ordering},
 };
 use riscv::register::scause::{Exception, Trap};
-use riscv::register::{mie, mip};
+use riscv::register::{mcause, mie, mip, mstatus, mtval, satp, scause, sepc, sstatus, stval};
+
+/// 首次进入和热重启进入S层时a0和a1的约定；HSM启动的核按SBI规范总是a0为核编号、a1为参数
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootConvention {
+    /// a0为核编号，a1为设备树地址，和Linux的约定相同
+    Linux = 0,
+    /// a0为设备树地址，a1为核编号
+    DtbFirst = 1,
+}
+
+impl BootConvention {
+    /// 设备树中的写法
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "linux" => Some(BootConvention::Linux),
+            "dtb-first" => Some(BootConvention::DtbFirst),
+            _ => None,
+        }
+    }
+}
+
+static BOOT_CONVENTION: AtomicU8 = AtomicU8::new(BootConvention::Linux as u8);
+
+/// 解析设备树以后调用；设备树中没有给出时使用Linux的约定
+pub fn set_boot_convention(convention: BootConvention) {
+    BOOT_CONVENTION.store(convention as u8, Ordering::Release);
+}
+
+fn boot_registers(hart_id: usize, dtb_pa: usize) -> (usize, usize) {
+    if BOOT_CONVENTION.load(Ordering::Acquire) == BootConvention::DtbFirst as u8 {
+        (dtb_pa, hart_id)
+    } else {
+        (hart_id, dtb_pa)
+    }
+}
 
 pub fn execute_supervisor(supervisor_mepc: usize, hart_id: usize, opaque: usize) {
-    let mut rt = Runtime::new_sbi_supervisor(supervisor_mepc, hart_id, opaque);
+    crate::protect_heap_extension();
+    let (a0, a1) = boot_registers(hart_id, opaque);
+    let mut rt = Runtime::new_sbi_supervisor(supervisor_mepc, a0, a1);
     loop {
         match Pin::new(&mut rt).resume(()) {
             GeneratorState::Yielded(MachineTrap::SbiCall()) => {
                 let ctx = rt.context_mut();
-                let param = [ctx.a0, ctx.a1, ctx.a2, ctx.a3, ctx.a4, ctx.a5];
-                let ans = rustsbi::ecall(ctx.a7, ctx.a6, param);
+                // 委托和时钟状态在进入S层之前就已经设置好。初始化的顺序被打乱时只拒绝这次调用，
+                // 不在最常走的路径上让固件panic
+                let state = hart_local::get(hart_id).state();
+                let ans = if state != HartState::Running {
+                    println!(
+                        "[rustsbi] error: supervisor ecall on hart {} in state {:?}, before its init finished",
+                        hart_id, state
+                    );
+                    ecall::failed()
+                } else {
+                    hart_local::get(hart_id)
+                        .last_ecall_mepc
+                        .store(ctx.mepc, Ordering::Relaxed);
+                    let param = [ctx.a0, ctx.a1, ctx.a2, ctx.a3, ctx.a4, ctx.a5];
+                    ecall::handle_ecall(ctx.a7, ctx.a6, param)
+                };
                 ctx.a0 = ans.error;
                 ctx.a1 = ans.value;
                 ctx.mepc = ctx.mepc.wrapping_add(4);
+                let hart = hart_local::get(hart_id);
+                // hart_stop或非保留上下文的hart_suspend返回时，从新的入口进入S层
+                if hart.restart.swap(false, Ordering::Relaxed) {
+                    restart_supervisor(&mut rt, hart_id);
+                }
+                // 热复位的请求优先于上面的重新启动，例如挂起时收到的热复位
+                if hart.reset_addr.load(Ordering::Acquire) != 0 {
+                    warm_reset(&mut rt, hart_id);
+                }
             }
             GeneratorState::Yielded(MachineTrap::IllegalInstruction()) => {
                 let ctx = rt.context_mut();
-                ctx.mepc = ctx.mepc.wrapping_add(4);
-                /*
-                // FIXME: get_vaddr_u32这个过程可能出错。
-                let ins = unsafe { get_vaddr_u32(ctx.mepc) } as usize;
+                // FU740会把非法指令的内容写入mtval，不需要再去读取mepc处的指令
+                let ins = mtval::read();
                 if !emulate_illegal_instruction(ctx, ins) {
-                    panic!("ill!!!!!!!!!!!!!!!");
+                    if feature::is_supervisor_trap_entry(ctx) {
+                        stop_broken_hart(&mut rt, hart_id);
+                        continue;
+                    }
                     unsafe {
                         if feature::should_transfer_trap(ctx) {
                             feature::do_transfer_trap(
@@ -38,18 +106,169 @@ pub fn execute_supervisor(supervisor_mepc: usize, hart_id: usize, opaque: usize)
                         }
                     }
                 }
-                */
             }
-            GeneratorState::Yielded(MachineTrap::MachineTimer()) => unsafe {
-                mip::set_stimer();
-                mie::clear_mtimer();
-            },
-            GeneratorState::Yielded(MachineTrap::MachineSoft()) => continue,
+            GeneratorState::Yielded(MachineTrap::BrokenTrapHandler()) => {
+                stop_broken_hart(&mut rt, hart_id)
+            }
+            GeneratorState::Yielded(MachineTrap::MachineTimer()) => {
+                deliver_supervisor_interrupts(hart_id, rt.context_mut());
+                mask_stuck_machine_interrupts(hart_id, rt.context_mut())
+            }
+            GeneratorState::Yielded(MachineTrap::MachineSoft()) => {
+                if ecall::shutdown_requested() {
+                    ecall::halt()
+                }
+                if hart_local::get(hart_id).reset_addr.load(Ordering::Acquire) != 0 {
+                    warm_reset(&mut rt, hart_id);
+                } else {
+                    deliver_supervisor_interrupts(hart_id, rt.context_mut());
+                }
+                mask_stuck_machine_interrupts(hart_id, rt.context_mut())
+            }
             GeneratorState::Complete(()) => break,
         }
     }
 }
 
+// 把M层的软件中断和时钟中断转交给S层。无论这次是哪一个中断引起的陷入，两个等待位都会检查：
+// 同时等待时，先转交软件中断（IPI），再转交时钟中断，和RISC-V规定的中断优先级一致。
+// 两个中断都通过mip的等待位转交，S层打开中断后，也会按照同样的优先级先处理IPI。
+// 时钟中断可能是S层的时钟到期，也可能是看门狗到期，两者分别检查后重新设置比较寄存器
+#[inline]
+fn deliver_supervisor_interrupts(hart_id: usize, ctx: &SupervisorContext) {
+    let clint = Clint::new(0x2000000 as *mut u8);
+    let pending = mip::read();
+    unsafe {
+        if pending.msoft() {
+            clint.clear_soft(hart_id);
+            mip::set_ssoft();
+        }
+        if pending.mtimer() && mie::read().mtimer() {
+            let now = clint.get_mtime();
+            deadman::check(hart_id, now, ctx);
+            let hart = hart_local::get(hart_id);
+            if now >= hart.supervisor_timer.load(Ordering::Relaxed) {
+                hart.supervisor_timer.store(u64::MAX, Ordering::Relaxed);
+                mip::set_stimer();
+            }
+            deadman::rearm(hart_id);
+        }
+    }
+}
+
+// S层陷入处理程序的入口处发生没有委托的异常，S层的每次陷入都会在这里再陷入M层。
+// 无论是哪一种异常，都打印出诊断信息，然后停止这个核，不再把异常转交回同一个入口；
+// 其它核用hart_start重新启动它以后，从新的入口进入S层
+fn stop_broken_hart(rt: &mut Runtime, hart_id: usize) {
+    report_broken_trap_handler(hart_id, rt.context_mut().mepc);
+    if !ecall::stop_broken_hart() {
+        panic!("supervisor trap handler on hart {} is broken", hart_id)
+    }
+    restart_supervisor(rt, hart_id);
+}
+
+// 打印出入口处的异常和S层原本要处理的那次陷入
+fn report_broken_trap_handler(hart_id: usize, stvec: usize) {
+    println!(
+        "[rustsbi] hart {} supervisor trap handler at stvec {:#x} is broken, \
+        {:?} at its entry, mtval {:#x}; check the value written to stvec",
+        hart_id,
+        stvec,
+        mcause::read().cause(),
+        mtval::read()
+    );
+    println!(
+        "[rustsbi] the trap it was entered for: scause {:#x}, sepc {:#x}, stval {:#x}; \
+        hart {} stopped, restart it with hart_start",
+        scause::read().bits(),
+        sepc::read(),
+        stval::read(),
+        hart_id
+    );
+}
+
+// 按照HSM规范设置新入口的状态：a0为核编号，a1为参数，关闭S层中断和分页
+fn restart_supervisor(rt: &mut Runtime, hart_id: usize) {
+    let hart = hart_local::get(hart_id);
+    unsafe {
+        sstatus::clear_sie();
+        satp::write(0);
+    }
+    rt.prepare_supervisor(hart.start_addr.load(Ordering::Relaxed));
+    let ctx = rt.context_mut();
+    ctx.a0 = hart_id;
+    ctx.a1 = hart.start_opaque.load(Ordering::Relaxed);
+}
+
+// 单核热复位：经过停止再启动，恢复委托和时钟的设置，清除S层的中断状态，
+// 关闭浮点单元，S层需要重新初始化浮点状态
+fn warm_reset(rt: &mut Runtime, hart_id: usize) {
+    let hart = hart_local::get(hart_id);
+    hart.set_state(HartState::Stopped);
+    let entry = hart.reset_addr.swap(0, Ordering::Acquire);
+    let opaque = hart.reset_opaque.load(Ordering::Relaxed);
+    hart.start_addr.store(entry, Ordering::Relaxed);
+    hart.start_opaque.store(opaque, Ordering::Relaxed);
+    if hart_id != 0 {
+        crate::delegate_interrupt_exception();
+    }
+    let clint = Clint::new(0x2000000 as *mut u8);
+    // 请求热复位的IPI不转交给S层
+    clint.clear_soft(hart_id);
+    crate::init_timer_state(clint, hart_id);
+    hart.deadman_timeout.store(0, Ordering::Relaxed);
+    hart.deadman_fired.store(false, Ordering::Relaxed);
+    unsafe {
+        mip::clear_ssoft();
+        core::arch::asm!("csrw sie, zero");
+        mstatus::set_fs(mstatus::FS::Off);
+    }
+    restart_supervisor(rt, hart_id);
+    hart.set_state(HartState::Running);
+    println!(
+        "[rustsbi] hart {} warm reset, restart at {:#x}",
+        hart_id, entry
+    );
+}
+
+// M层中断不受S层的sstatus.SIE控制，只要等待位和mie同时置位，mret后就会马上再次陷入。
+// 正常情况下，上面转交中断时已经清除了等待的来源；如果仍有来源无法清除，
+// 关闭这个中断并打印警告，保证S层能继续运行
+fn mask_stuck_machine_interrupts(hart_id: usize, ctx: &SupervisorContext) {
+    let clint = Clint::new(0x2000000 as *mut u8);
+    let pending = mip::read();
+    let enabled = mie::read();
+    unsafe {
+        if pending.msoft() && enabled.msoft() {
+            // 转交以后又收到了新的IPI，再转交一次即可，不需要关闭
+            clint.clear_soft(hart_id);
+            mip::set_ssoft();
+        }
+        // 重新设置的比较值可能在转交期间就已经到了，这时的等待位是真实的到期，再转交一次。
+        // 每次转交都会消耗已经到期的S层时钟或看门狗，循环很快结束；
+        // 只有比较值还在将来而等待位仍然置位时，才认为这个中断卡住了
+        while mip::read().mtimer() && mie::read().mtimer() {
+            if clint.get_mtime() >= clint.get_timer(hart_id) {
+                deliver_supervisor_interrupts(hart_id, ctx);
+                continue;
+            }
+            // S层下一次调用set_timer时会重新打开
+            mie::clear_mtimer();
+            println!(
+                "[rustsbi] warning: machine timer still pending on hart {} before mret, masked",
+                hart_id
+            );
+        }
+        if pending.mext() && enabled.mext() {
+            mie::clear_mext();
+            println!(
+                "[rustsbi] warning: machine external interrupt pending on hart {} before mret, masked",
+                hart_id
+            );
+        }
+    }
+}
+
 #[inline]
 unsafe fn get_vaddr_u32(vaddr: usize) -> u32 {
     let mut ans: u32;
@@ -73,6 +292,9 @@ unsafe fn get_vaddr_u32(vaddr: usize) -> u32 {
 }
 
 fn emulate_illegal_instruction(ctx: &mut SupervisorContext, ins: usize) -> bool {
+    if feature::emulate_hypervisor(ctx, ins) {
+        return true;
+    }
     if feature::emulate_rdtime(ctx, ins) {
         return true;
     }
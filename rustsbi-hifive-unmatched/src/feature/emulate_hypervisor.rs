@@ -0,0 +1,42 @@
+use crate::runtime::SupervisorContext;
+use riscv::register::scause::{Exception, Trap};
+
+// FU740没有H扩展，S层访问虚拟化相关的CSR或者执行虚拟化指令都会产生非法指令异常。
+// 这里不模拟这些指令，而是把异常原样转交给S层，让S层看到的行为和没有H扩展的硬件一致
+#[inline]
+pub fn emulate_hypervisor(ctx: &mut SupervisorContext, ins: usize) -> bool {
+    if !is_hypervisor_instruction(ins) {
+        return false;
+    }
+    unsafe {
+        if !super::should_transfer_trap(ctx) {
+            return false; // M层自己执行了虚拟化指令，交给上一级报错
+        }
+        super::do_transfer_trap(ctx, Trap::Exception(Exception::IllegalInstruction));
+    }
+    true
+}
+
+#[inline]
+fn is_hypervisor_instruction(ins: usize) -> bool {
+    const OPCODE_SYSTEM: usize = 0b111_0011;
+    if ins & 0b111_1111 != OPCODE_SYSTEM {
+        return false;
+    }
+    let funct3 = (ins >> 12) & 0b111;
+    match funct3 {
+        // hfence.vvma和hfence.gvma
+        0b000 => {
+            let funct7 = (ins >> 25) & 0b111_1111;
+            let rd = (ins >> 7) & 0b1_1111;
+            rd == 0 && (funct7 == 0b001_0001 || funct7 == 0b011_0001)
+        }
+        // hlv、hlvx和hsv系列指令
+        0b100 => true,
+        // csr指令：地址的第8、9位为0b10时，是HS层或VS层的寄存器（hstatus、vsstatus等）
+        _ => {
+            let csr = (ins >> 20) & 0xFFF;
+            (csr >> 8) & 0b11 == 0b10
+        }
+    }
+}
@@ -1,5 +1,7 @@
+mod emulate_hypervisor;
 mod emulate_rdtime;
 mod transfer_trap;
 
+pub use emulate_hypervisor::emulate_hypervisor;
 pub use emulate_rdtime::emulate_rdtime;
-pub use transfer_trap::{do_transfer_trap, should_transfer_trap};
+pub use transfer_trap::{do_transfer_trap, is_supervisor_trap_entry, should_transfer_trap};
@@ -9,6 +9,13 @@ pub unsafe fn should_transfer_trap(ctx: &mut SupervisorContext) -> bool {
     ctx.mstatus.mpp() != MPP::Machine
 }
 
+// S层陷入处理程序的第一条指令就陷入了M层：stvec无效，或者入口处不是可执行的代码。
+// 这时再转交给S层，只会在同一个地址上无限循环
+#[inline]
+pub fn is_supervisor_trap_entry(ctx: &SupervisorContext) -> bool {
+    ctx.mstatus.mpp() == MPP::Supervisor && ctx.mepc == stvec::read().address()
+}
+
 #[inline]
 pub unsafe fn do_transfer_trap(ctx: &mut SupervisorContext, cause: scause::Trap) {
     // 设置S层异常原因
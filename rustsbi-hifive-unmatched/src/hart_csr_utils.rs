@@ -133,6 +133,25 @@ pub fn set_pmp() {
     riscv::register::satp::write(0);
 }
 
+// 禁止S层和U层访问[start, end)，两端都要4字节对齐：pmp0允许访问之前的地址，pmp1禁止访问这一段，
+// pmp2允许访问之后的全部地址。没有设置L位，M层的访问不受影响
+pub fn deny_supervisor_access(start: usize, end: usize) {
+    const PMP_CFG: usize = 0x0F | (0x08 << 8) | (0x0F << 16); // TOR rwx, TOR ---, TOR rwx
+    unsafe {
+        core::arch::asm!(
+            "csrw   0x3B0, {start}",
+            "csrw   0x3B1, {end}",
+            "li     {tmp}, -1",
+            "csrw   0x3B2, {tmp}",
+            "csrw   0x3A0, {cfg}",
+            start = in(reg) start >> 2,
+            end = in(reg) end >> 2,
+            cfg = in(reg) PMP_CFG,
+            tmp = out(reg) _,
+        );
+    }
+}
+
 
 #[cfg(target_pointer_width = "64")]
 #[inline]
@@ -322,3 +341,21 @@ unsafe fn pmpaddr_r(pmpaddr_id: usize) -> usize {
     id = in(reg) pmpaddr_id, tmp = out(reg) _, len = out(reg) _, ans = out(reg) ans);
     ans
 }
+
+/// 读取当前核S层的寄存器，只支持调试需要的几个寄存器；M层可以直接访问它们，不会打断S层的运行
+pub fn read_supervisor_csr(csr: usize) -> Option<usize> {
+    use riscv::register::{satp, scause, sepc, sie, sip, sscratch, sstatus, stval, stvec};
+    let value = match csr {
+        0x100 => sstatus::read().bits(),
+        0x104 => sie::read().bits(),
+        0x105 => stvec::read().bits(),
+        0x140 => sscratch::read(),
+        0x141 => sepc::read(),
+        0x142 => scause::read().bits(),
+        0x143 => stval::read(),
+        0x144 => sip::read().bits(),
+        0x180 => satp::read().bits(),
+        _ => return None,
+    };
+    Some(value)
+}
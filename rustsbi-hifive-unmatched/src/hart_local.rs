@@ -0,0 +1,181 @@
+// 每个核各自的状态，其它核也可以读取
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+
+pub const MAX_HART_ID: usize = 4;
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HartState {
+    /// 还没有完成初始化，或者仍在等待启动核唤醒
+    Parked = 0,
+    /// 已经完成M层初始化，可以进入S层
+    Ready = 1,
+    /// 在固件中发生了panic，已经停止运行
+    Panicked = 2,
+    /// 已经进入S层，可以处理S层的SBI调用
+    Running = 3,
+    /// S层调用了hart_stop，在M层等待hart_start
+    Stopped = 4,
+    /// 其它核已经调用hart_start，这个核还没有进入新的入口
+    StartPending = 5,
+    /// S层调用了hart_suspend，在M层等待中断
+    Suspended = 6,
+}
+
+/// 启动时这个核没有进入S层的原因，在算出进入S层的核时记录
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exclusion {
+    /// 没有被排除，会进入S层；还没有算出进入S层的核时也是这个值
+    Included = 0,
+    /// 第0个核是S7，没有S模式
+    S7Core = 1,
+    /// 设备树中这个核的status不是okay
+    Disabled = 2,
+    /// 设备树的/cpus节点中没有这个核
+    MissingInDeviceTree = 3,
+    /// 初始化时在固件中发生了panic
+    Panicked = 4,
+    /// 启动核等待结束时没有报告初始化完毕
+    NotInitialized = 5,
+}
+
+impl Exclusion {
+    /// 启动日志中的写法
+    pub fn description(self) -> &'static str {
+        match self {
+            Exclusion::Included => "included",
+            Exclusion::S7Core => "S7 core",
+            Exclusion::Disabled => "disabled in device tree",
+            Exclusion::MissingInDeviceTree => "missing in device tree",
+            Exclusion::Panicked => "panicked",
+            Exclusion::NotInitialized => "not initialized",
+        }
+    }
+}
+
+// 向量模式的M层时钟中断快速路径按偏移直接读取前三个字段，见runtime::fast_machine_timer
+#[repr(C)]
+pub struct HartLocal {
+    /// S层通过set_timer设置的时钟中断时间，没有设置时为u64::MAX
+    pub supervisor_timer: AtomicU64,
+    /// 看门狗的超时长度，为0表示看门狗没有打开
+    pub deadman_timeout: AtomicU64,
+    /// 看门狗下一次到期的时间
+    pub deadman_deadline: AtomicU64,
+    state: AtomicU8,
+    exclusion: AtomicU8,
+    /// 看门狗到期后是否关闭系统
+    pub deadman_reset: AtomicBool,
+    /// 看门狗已经到期过，S层需要重新打开它
+    pub deadman_fired: AtomicBool,
+    /// hart_start或非保留上下文的hart_suspend设置的新入口和参数
+    pub start_addr: AtomicUsize,
+    pub start_opaque: AtomicUsize,
+    /// 从M层返回时需要从start_addr重新进入S层
+    pub restart: AtomicBool,
+    /// 其它核请求热复位这个核时设置的入口和参数；入口为0表示没有请求
+    pub reset_addr: AtomicUsize,
+    pub reset_opaque: AtomicUsize,
+    /// S层通过PMU扩展配置过的计数器，按计数器编号置位
+    pub pmu_configured: AtomicUsize,
+    /// 最近一次SBI调用的地址，停止或挂起的核就停在这个调用上
+    pub last_ecall_mepc: AtomicUsize,
+    /// 各类陷入的处理开销
+    #[cfg(feature = "trap-profile")]
+    pub trap_stats: [crate::trap_profile::TrapStats; crate::trap_profile::TRAP_KINDS],
+}
+
+impl HartLocal {
+    const fn new() -> Self {
+        HartLocal {
+            state: AtomicU8::new(HartState::Parked as u8),
+            exclusion: AtomicU8::new(Exclusion::Included as u8),
+            supervisor_timer: AtomicU64::new(u64::MAX),
+            deadman_timeout: AtomicU64::new(0),
+            deadman_deadline: AtomicU64::new(u64::MAX),
+            deadman_reset: AtomicBool::new(false),
+            deadman_fired: AtomicBool::new(false),
+            start_addr: AtomicUsize::new(0),
+            start_opaque: AtomicUsize::new(0),
+            restart: AtomicBool::new(false),
+            reset_addr: AtomicUsize::new(0),
+            reset_opaque: AtomicUsize::new(0),
+            pmu_configured: AtomicUsize::new(0),
+            last_ecall_mepc: AtomicUsize::new(0),
+            #[cfg(feature = "trap-profile")]
+            trap_stats: [
+                crate::trap_profile::TrapStats::new(),
+                crate::trap_profile::TrapStats::new(),
+                crate::trap_profile::TrapStats::new(),
+                crate::trap_profile::TrapStats::new(),
+            ],
+        }
+    }
+
+    pub fn state(&self) -> HartState {
+        match self.state.load(Ordering::Acquire) {
+            1 => HartState::Ready,
+            2 => HartState::Panicked,
+            3 => HartState::Running,
+            4 => HartState::Stopped,
+            5 => HartState::StartPending,
+            6 => HartState::Suspended,
+            _ => HartState::Parked,
+        }
+    }
+
+    pub fn set_state(&self, state: HartState) {
+        self.state.store(state as u8, Ordering::Release);
+    }
+
+    pub fn exclusion(&self) -> Exclusion {
+        match self.exclusion.load(Ordering::Acquire) {
+            1 => Exclusion::S7Core,
+            2 => Exclusion::Disabled,
+            3 => Exclusion::MissingInDeviceTree,
+            4 => Exclusion::Panicked,
+            5 => Exclusion::NotInitialized,
+            _ => Exclusion::Included,
+        }
+    }
+
+    pub fn set_exclusion(&self, exclusion: Exclusion) {
+        self.exclusion.store(exclusion as u8, Ordering::Release);
+    }
+
+    // 只有当前状态为from时才切换到to，返回是否切换成功
+    pub fn transition(&self, from: HartState, to: HartState) -> bool {
+        self.state
+            .compare_exchange(from as u8, to as u8, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+    }
+
+    // CLINT每个核只有一个比较寄存器，S层的时钟和看门狗共用它，取较早到期的一个
+    pub fn next_timer_deadline(&self) -> u64 {
+        let supervisor = self.supervisor_timer.load(Ordering::Relaxed);
+        if self.deadman_timeout.load(Ordering::Relaxed) == 0 {
+            return supervisor;
+        }
+        supervisor.min(self.deadman_deadline.load(Ordering::Relaxed))
+    }
+}
+
+// 每个核一项，个数和MAX_HART_ID不一致时编译出错
+static HART_LOCAL: [HartLocal; MAX_HART_ID + 1] = [
+    HartLocal::new(),
+    HartLocal::new(),
+    HartLocal::new(),
+    HartLocal::new(),
+    HartLocal::new(),
+];
+
+#[inline]
+pub fn get(hart_id: usize) -> &'static HartLocal {
+    &HART_LOCAL[hart_id]
+}
+
+#[inline]
+pub fn current() -> &'static HartLocal {
+    get(riscv::register::mhartid::read())
+}
@@ -0,0 +1,86 @@
+// 堆的高水位统计
+//
+// 在全局分配器外面包一层，每次分配以后读出分配器统计的已用字节数，记录其中的最大值。
+// 关机时和栈的统计一起打印，用来调整SBI_HEAP_SIZE。
+// 扩展堆之前还从启动时的堆分配一块填好固定值的内存，之后检查它没有被改写
+use crate::console::println;
+use buddy_system_allocator::LockedHeap;
+use core::alloc::{GlobalAlloc, Layout};
+use core::ops::Deref;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub struct TrackedHeap<const ORDER: usize> {
+    heap: LockedHeap<ORDER>,
+    peak: AtomicUsize,
+}
+
+impl<const ORDER: usize> TrackedHeap<ORDER> {
+    pub const fn empty() -> Self {
+        TrackedHeap {
+            heap: LockedHeap::<ORDER>::empty(),
+            peak: AtomicUsize::new(0),
+        }
+    }
+
+    /// 用过的最大字节数，包括分配器为对齐多占用的部分
+    pub fn peak_usage(&self) -> usize {
+        self.peak.load(Ordering::Relaxed)
+    }
+}
+
+// 初始化和扩展堆时直接使用里面的分配器
+impl<const ORDER: usize> Deref for TrackedHeap<ORDER> {
+    type Target = LockedHeap<ORDER>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.heap
+    }
+}
+
+unsafe impl<const ORDER: usize> GlobalAlloc for TrackedHeap<ORDER> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.heap.alloc(layout);
+        if !ptr.is_null() {
+            let used = self.heap.lock().stats_alloc_actual();
+            self.peak.fetch_max(used, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.heap.dealloc(ptr, layout)
+    }
+}
+
+// 检查用的内存的长度和填充的值，为0时还没有分配
+const CANARY_LEN: usize = 64;
+const CANARY_BYTE: u8 = 0xA5;
+static CANARY: AtomicUsize = AtomicUsize::new(0);
+
+/// 扩展堆之前调用，从启动时的堆分配检查用的内存，之后不再释放
+#[allow(unused)]
+pub fn place_bootstrap_canary() {
+    let canary = alloc::boxed::Box::leak(alloc::boxed::Box::new([CANARY_BYTE; CANARY_LEN]));
+    CANARY.store(canary.as_ptr() as usize, Ordering::Release);
+}
+
+/// 扩展堆之前的分配是否保持原样；没有扩展过堆时总是返回true
+pub fn bootstrap_intact() -> bool {
+    let canary = CANARY.load(Ordering::Acquire);
+    if canary == 0 {
+        return true;
+    }
+    let canary = unsafe { core::slice::from_raw_parts(canary as *const u8, CANARY_LEN) };
+    canary.iter().all(|&byte| byte == CANARY_BYTE)
+}
+
+pub fn print_summary() {
+    let peak = crate::HEAP_ALLOCATOR.peak_usage();
+    let total = crate::HEAP_ALLOCATOR.lock().stats_total_bytes();
+    println!(
+        "[rustsbi] peak heap usage: {} of {} bytes, {} bytes never used",
+        peak,
+        total,
+        total.saturating_sub(peak)
+    );
+}
@@ -7,24 +7,55 @@
 
 extern crate alloc;
 
+#[cfg(feature = "board-sensors")]
+mod board_sensors;
+mod build_config;
 mod console;
+mod deadman;
 mod device_tree;
+#[cfg(feature = "dump-device-tree")]
+mod dt_dump;
+mod dtb_handoff;
 mod early_trap;
+mod ecall;
 mod execute;
 mod feature;
 mod hart_csr_utils;
+mod hart_local;
+mod heap_usage;
+#[cfg(feature = "memory-test")]
+mod memory_test;
+#[cfg(feature = "monitor")]
+mod monitor;
+mod panic_action;
+mod payload;
 mod peripheral;
+mod platform_info;
+mod pma;
 mod runtime;
+#[cfg(feature = "stack-watermark")]
+mod stack_watermark;
+mod time_scale;
+#[cfg(feature = "trap-profile")]
+mod trap_profile;
 mod util;
 
 use console::{eprintln, println};
 use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use hart_local::HartState;
 
 #[panic_handler]
 fn on_panic(info: &PanicInfo) -> ! {
     let hart_id = riscv::register::mhartid::read();
     eprintln!("[rustsbi-panic] hart {} {}", hart_id, info); // [rustsbi-panic] hart 0 panicked at xxx
-    loop {}
+    hart_local::get(hart_id).set_state(HartState::Panicked);
+    // 启动核可能正在等待这个核完成初始化，通知它不必再等
+    let waiting_hart = BOOT_HART_WAITING.load(Ordering::Acquire);
+    if waiting_hart != NO_HART_WAITING && waiting_hart != hart_id {
+        peripheral::Clint::new(0x2000000 as *mut u8).send_soft(waiting_hart);
+    }
+    panic_action::handle(hart_id)
 }
 
 static DEVICE_TREE: &'static [u8] = include_bytes!("hifive-unmatched-a00.dtb");
@@ -44,13 +75,24 @@ const FW_DYNAMIC_INFO_MAGIC_VALUE: usize = 0x4942534f;
 
 fn rust_main(hart_id: usize, opaque: usize, fw_dynamic_info: *const FwDynamicInfo) {
     let clint = peripheral::Clint::new(0x2000000 as *mut u8);
+    let cold_start = clint.get_mtime();
     let fw_dynamic_info = unsafe { &*fw_dynamic_info };
     let boot_hart = fw_dynamic_info.boot_hart;
+    let opaque = if opaque == 0 {
+        // 如果上一级没有填写设备树文件，这一级填写
+        DEVICE_TREE.as_ptr() as usize
+    } else {
+        opaque
+    };
 
     if hart_id == boot_hart {
         init_bss();
-        let uart = unsafe { peripheral::Uart::preloaded_uart0() };
-        crate::console::init_stdout(uart);
+        BOOT_HART.store(boot_hart, Ordering::Relaxed);
+        #[cfg(feature = "terminal-probe")]
+        console::set_quiet(!terminal_connected(clint));
+        crate::console::init_stdout(console_uarts(uart_init_attempts(opaque)));
+        // 打开ordered-harts功能时，等到wait_for_secondary_harts再逐个唤醒
+        #[cfg(not(feature = "ordered-harts"))]
         for target_hart_id in 1..=4 {
             if target_hart_id != boot_hart {
                 clint.send_soft(target_hart_id);
@@ -59,18 +101,11 @@ fn rust_main(hart_id: usize, opaque: usize, fw_dynamic_info: *const FwDynamicInf
     } else {
         pause(clint);
     }
-    let opaque = if opaque == 0 {
-        // 如果上一级没有填写设备树文件，这一级填写
-        DEVICE_TREE.as_ptr() as usize
-    } else {
-        opaque
-    };
     early_trap::init(hart_id);
     hart_csr_utils::set_pmp();
     if hart_id == boot_hart {
         init_heap(); // 必须先加载堆内存，才能使用rustsbi框架
-        let uart = unsafe { peripheral::Uart::preloaded_uart0() };
-        init_rustsbi_stdio(uart);
+        init_rustsbi_stdio(console::stdout_uarts().expect("stdout initialized"));
         init_rustsbi_clint(clint);
         println!("[rustsbi] RustSBI version {}", rustsbi::VERSION);
         // println!("{}", rustsbi::LOGO);
@@ -78,19 +113,83 @@ fn rust_main(hart_id: usize, opaque: usize, fw_dynamic_info: *const FwDynamicInf
             "[rustsbi] Implementation: RustSBI-HiFive-Unleashed Version {}",
             env!("CARGO_PKG_VERSION")
         );
-        if let Err(e) = unsafe { device_tree::parse_device_tree(opaque) } {
-            println!("[rustsbi] warning: choose from device tree error, {}", e);
+        let board_info = match unsafe { device_tree::parse_device_tree(opaque) } {
+            Ok(board_info) => board_info,
+            Err(e) => {
+                println!("[rustsbi] warning: choose from device tree error, {}", e);
+                device_tree::BoardInfo::default()
+            }
+        };
+        #[cfg(feature = "heap-extension")]
+        extend_heap(&board_info, fw_dynamic_info.next_addr, opaque);
+        pma::init(board_info.memory);
+        #[cfg(feature = "board-sensors")]
+        match board_info.temperature_sensor {
+            Some((i2c_base, address)) => board_sensors::init(i2c_base, address),
+            None => println!("[rustsbi] no temperature sensor in device tree"),
+        }
+        if board_info.panic_uart1 {
+            init_panic_uart(uart_init_attempts(opaque));
+        }
+        panic_action::init(board_info.panic_action, board_info.panic_reboot_delay);
+        #[cfg(feature = "time-scale")]
+        if let Some((numerator, denominator)) = board_info.time_scale {
+            if time_scale::set(numerator, denominator) {
+                println!(
+                    "[rustsbi] supervisor time runs at {}/{} of mtime",
+                    numerator, denominator
+                );
+            }
+        }
+        if let Some(max_transfer) = board_info.dbcn_max_transfer {
+            if !ecall::set_console_max_transfer(max_transfer) {
+                println!("[rustsbi] warning: debug console transfer limit must not be 0, ignored");
+            }
+        }
+        if let Some(convention) = board_info.boot_convention {
+            println!("[rustsbi] boot convention {:?}", convention);
+            execute::set_boot_convention(convention);
         }
+        dtb_handoff::init(opaque);
         delegate_interrupt_exception();
+        init_timer_state(clint, hart_id);
+        hart_local::get(hart_id).set_state(HartState::Ready);
         hart_csr_utils::print_hartn_csrs();
+        let next_addr = match payload::choose_entry(
+            fw_dynamic_info.next_addr,
+            board_info.payload_size,
+            board_info.rescue_addr,
+        ) {
+            Some(next_addr) => next_addr,
+            None => match manual_entry(&board_info, clint, opaque) {
+                Some(next_addr) => next_addr,
+                None => {
+                    println!("[rustsbi] no bootable supervisor found, halt");
+                    return; // 其余的核仍在等待唤醒，不会进入S层
+                }
+            },
+        };
+        SUPERVISOR_ENTRY.store(next_addr, Ordering::Release);
+        wait_for_secondary_harts(clint, boot_hart);
+        #[cfg(feature = "memory-test")]
+        run_memory_test(&board_info, next_addr, opaque);
+        let hart_mask = supervisor_hart_mask(boot_hart, &board_info);
+        SUPERVISOR_HART_MASK.store(hart_mask, Ordering::Release);
+        platform_info::init(board_info.model, hart_mask);
+        #[cfg(feature = "dump-device-tree")]
+        unsafe {
+            dt_dump::dump_device_tree(opaque)
+        };
         println!(
             "[rustsbi] enter supervisor, opaque register {:#x}, fw_dynamic_info {:?}",
             opaque,
             &fw_dynamic_info
         );
         for target_hart_id in 1..=4 {
-            if target_hart_id != boot_hart {
+            if target_hart_id != boot_hart && hart_mask & (1 << target_hart_id) != 0 {
                 clint.send_soft(target_hart_id);
+                #[cfg(feature = "ordered-harts")]
+                wait_for_supervisor_entry(target_hart_id);
             }
         }
     } else {
@@ -98,10 +197,198 @@ fn rust_main(hart_id: usize, opaque: usize, fw_dynamic_info: *const FwDynamicInf
         if hart_id != 0 {
             delegate_interrupt_exception(); // 第0个核不能委托中断（@dram）
         }
-        pause(clint);
+        init_timer_state(clint, hart_id);
+        #[cfg(feature = "init-faults")]
+        if hart_id == INIT_FAULT_HART {
+            panic!("injected init fault");
+        }
+        // 启动核在这个核报告以前不会唤醒下一个核，输出按核编号的顺序
+        #[cfg(feature = "ordered-harts")]
+        println!("[rustsbi] hart {} initialized", hart_id);
+        // 先清除之前的IPI再报告，报告以后启动核发来的IPI不会再被清除
+        clint.clear_soft(hart_id);
+        // 委托和时钟状态都设置好以后，这个核才算初始化完毕
+        hart_local::get(hart_id).set_state(HartState::Ready);
+        clint.send_soft(boot_hart); // 告诉启动核，这个核已经初始化完毕
+        wait_for_supervisor_mask(clint, hart_id);
+    }
+    if hart_id == boot_hart {
+        COLD_BOOT_TICKS.store(
+            clint.get_mtime().saturating_sub(cold_start) as usize,
+            Ordering::Relaxed,
+        );
     }
     runtime::init();
-    execute::execute_supervisor(fw_dynamic_info.next_addr, hart_id, opaque);
+    hart_local::get(hart_id).set_state(HartState::Running); // 从这里开始才可能收到S层的调用
+    let next_addr = SUPERVISOR_ENTRY.load(Ordering::Acquire);
+    execute::execute_supervisor(next_addr, hart_id, opaque);
+}
+
+// 其余的核都在等待唤醒时进行内存测试，不测试固件、S层负载和设备树所在的区域。
+// 不知道负载的长度时，保守地跳过入口以后的一段内存
+#[cfg(feature = "memory-test")]
+fn run_memory_test(board_info: &device_tree::BoardInfo, next_addr: usize, dtb_pa: usize) {
+    const UNKNOWN_PAYLOAD_SIZE: usize = 256 * 1024 * 1024;
+    let memory = match board_info.memory {
+        Some(memory) => memory,
+        None => {
+            println!("[rustsbi] warning: no memory range in device tree, memory test skipped");
+            return;
+        }
+    };
+    let payload_size = board_info.payload_size.unwrap_or(UNKNOWN_PAYLOAD_SIZE);
+    let dtb_size = unsafe { device_tree::total_size(dtb_pa) };
+    let excluded = [
+        payload::firmware_region(),
+        (next_addr, next_addr.saturating_add(payload_size)),
+        (dtb_pa, dtb_pa.saturating_add(dtb_size)),
+    ];
+    let stride = board_info
+        .memory_test_stride
+        .unwrap_or(memory_test::DEFAULT_STRIDE);
+    memory_test::run(memory, stride, &excluded);
+}
+
+// 没有可以进入的S层入口时，由用户在监视器中手动指定
+#[cfg(feature = "monitor")]
+fn manual_entry(
+    board_info: &device_tree::BoardInfo,
+    clint: peripheral::Clint,
+    dtb_pa: usize,
+) -> Option<usize> {
+    let timeout = board_info
+        .monitor_timeout
+        .unwrap_or(monitor::DEFAULT_TIMEOUT_SECONDS);
+    let boot_hart = riscv::register::mhartid::read();
+    monitor::enter_on_keypress(clint, timeout, boot_hart, dtb_pa)
+}
+
+#[cfg(not(feature = "monitor"))]
+fn manual_entry(
+    _board_info: &device_tree::BoardInfo,
+    _clint: peripheral::Clint,
+    _dtb_pa: usize,
+) -> Option<usize> {
+    None
+}
+
+// 打开init-faults功能时，在初始化中途panic的核
+#[cfg(feature = "init-faults")]
+const INIT_FAULT_HART: usize = hart_local::MAX_HART_ID;
+
+// 启动核的编号，初始化bss以后写入
+static BOOT_HART: AtomicUsize = AtomicUsize::new(0);
+
+#[allow(unused)]
+fn boot_hart() -> usize {
+    BOOT_HART.load(Ordering::Relaxed)
+}
+
+// 由启动核选出的S层入口，其余的核被唤醒后从这里读取
+static SUPERVISOR_ENTRY: AtomicUsize = AtomicUsize::new(0);
+
+// 启动核从进入rust_main到进入S层经过的mtime周期数，S层可以读出它，和热重启的耗时比较
+static COLD_BOOT_TICKS: AtomicUsize = AtomicUsize::new(0);
+
+// 会进入S层的核，按核编号置位；启动核唤醒其余的核之前写入
+static SUPERVISOR_HART_MASK: AtomicUsize = AtomicUsize::new(0);
+
+// 根据设备树和各个核的初始化结果，算出会进入S层的核，并用一行日志列出被排除的核和原因。
+// 原因同时记录在各个核的hart_local中，这一行日志也保存在platform_info中，
+// S层可以通过专有SBI调用读出它们
+fn supervisor_hart_mask(boot_hart: usize, board_info: &device_tree::BoardInfo) -> usize {
+    use core::fmt::Write;
+    use hart_local::Exclusion;
+    let mut mask = 1 << boot_hart;
+    let mut excluded = 0;
+    let mut line = platform_info::HartSetLine::new();
+    write!(line, "[rustsbi] boot hart {}", boot_hart).ok();
+    for hart_id in (0..=hart_local::MAX_HART_ID).filter(|&id| id != boot_hart) {
+        let hart = hart_local::get(hart_id);
+        let reason = if hart_id == 0 {
+            Exclusion::S7Core
+        } else if board_info.missing_harts & (1 << hart_id) != 0 {
+            Exclusion::MissingInDeviceTree
+        } else if board_info.disabled_harts & (1 << hart_id) != 0 {
+            Exclusion::Disabled
+        } else {
+            match hart.state() {
+                HartState::Ready => {
+                    mask |= 1 << hart_id;
+                    continue;
+                }
+                HartState::Panicked => Exclusion::Panicked,
+                _ => Exclusion::NotInitialized,
+            }
+        };
+        hart.set_exclusion(reason);
+        write!(
+            line,
+            "{} hart {} ({})",
+            if excluded == 0 { ", excluded:" } else { "," },
+            hart_id,
+            reason.description()
+        )
+        .ok();
+        excluded += 1;
+    }
+    write!(line, ", supervisor hart mask {:#x}", mask).ok();
+    println!("{}", line.as_str());
+    mask
+}
+
+// 正在等待其余核完成初始化的启动核编号
+static BOOT_HART_WAITING: AtomicUsize = AtomicUsize::new(NO_HART_WAITING);
+const NO_HART_WAITING: usize = usize::MAX;
+
+// 等待其余的核初始化完毕；发生panic的核不再等待，启动核记录后继续启动其余的核
+fn wait_for_secondary_harts(clint: peripheral::Clint, boot_hart: usize) {
+    BOOT_HART_WAITING.store(boot_hart, Ordering::Release);
+    #[cfg(not(feature = "ordered-harts"))]
+    wait_until_reported(clint, boot_hart, 1..=4);
+    // 按核编号逐个唤醒，上一个核初始化完毕以后再唤醒下一个
+    #[cfg(feature = "ordered-harts")]
+    for hart_id in (1..=4).filter(|&id| id != boot_hart) {
+        clint.send_soft(hart_id);
+        wait_until_reported(clint, boot_hart, hart_id..=hart_id);
+    }
+    BOOT_HART_WAITING.store(NO_HART_WAITING, Ordering::Release);
+    for hart_id in (1..=4).filter(|&id| id != boot_hart) {
+        if hart_local::get(hart_id).state() == HartState::Panicked {
+            println!(
+                "[rustsbi] warning: hart {} panicked during init, continue without it",
+                hart_id
+            );
+        }
+    }
+}
+
+// 等待范围内除启动核以外的核都报告初始化完毕或者panic；这些核报告时会发IPI唤醒启动核
+fn wait_until_reported(
+    clint: peripheral::Clint,
+    boot_hart: usize,
+    harts: core::ops::RangeInclusive<usize>,
+) {
+    use riscv::asm::wfi;
+    loop {
+        clint.clear_soft(boot_hart);
+        let all_reported = harts
+            .clone()
+            .filter(|&id| id != boot_hart)
+            .all(|id| hart_local::get(id).state() != HartState::Parked);
+        if all_reported {
+            break;
+        }
+        unsafe { wfi() };
+    }
+}
+
+// 等待刚唤醒的核离开Ready状态，也就是开始进入S层或者发生了panic，然后再唤醒下一个核
+#[cfg(feature = "ordered-harts")]
+fn wait_for_supervisor_entry(hart_id: usize) {
+    while hart_local::get(hart_id).state() == HartState::Ready {
+        core::hint::spin_loop();
+    }
 }
 
 fn init_bss() {
@@ -118,9 +405,88 @@ fn init_bss() {
     }
 }
 
-fn init_rustsbi_stdio(uart: peripheral::Uart) {
+// 控制台使用的串口；打开console-mirror功能后，所有输出同时镜像到UART1
+#[allow(unused_variables)]
+fn console_uarts(uart_init_attempts: usize) -> console::MultiUart {
+    let uart0 = unsafe { peripheral::Uart::preloaded_uart0() };
+    #[allow(unused_mut)]
+    let mut uarts = console::MultiUart::new(uart0);
+    #[cfg(feature = "console-mirror")]
+    {
+        // 先只用UART0输出，这样才能打印UART1的初始化结果
+        console::init_stdout(uarts);
+        match unsafe { peripheral::Uart::uart1_like_uart0(uart_init_attempts) } {
+            Ok((uart1, attempts)) => {
+                println!("[rustsbi] uart1 ready after {} attempt(s)", attempts);
+                uarts.push(uart1);
+            }
+            Err(attempts) => println!(
+                "[rustsbi] warning: uart1 settings did not take after {} attempts, console stays on uart0",
+                attempts
+            ),
+        }
+    }
+    uarts
+}
+
+// 在一段时间内读UART0的接收队列，统计不像是键盘输入的字节：悬空的接收线会被读成连续的0，
+// 波特率不对时读到的多是最高位置位的字节。接好的终端空闲时不会发送任何数据，
+// 启动时按下的按键也都是普通的ASCII字符
+#[cfg(feature = "terminal-probe")]
+fn terminal_connected(clint: peripheral::Clint) -> bool {
+    use embedded_hal::serial::Read;
+    // mtime的频率是1MHz，等待10ms，115200波特率下足够收到上百个字节
+    const PROBE_TICKS: u64 = 10_000;
+    const NOISE_LIMIT: usize = 4;
+    let mut uart0 = unsafe { peripheral::Uart::preloaded_uart0() };
+    let deadline = clint.get_mtime() + PROBE_TICKS;
+    let mut noise = 0;
+    while clint.get_mtime() < deadline {
+        if let Ok(byte) = uart0.read() {
+            if byte == 0 || byte >= 0x80 {
+                noise += 1;
+            }
+        }
+    }
+    noise < NOISE_LIMIT
+}
+
+// 设置串口后回读寄存器确认，最多尝试的次数，可以由/chosen中的rustsbi,uart-init-attempts改变，为0时使用默认值。
+// 打开console-mirror功能时，UART1在控制台和堆初始化之前就要打开，因此直接从设备树的结构块中读取
+const DEFAULT_UART_INIT_ATTEMPTS: usize = 3;
+
+fn uart_init_attempts(dtb_pa: usize) -> usize {
+    match unsafe { device_tree::read_chosen_cells(dtb_pa, "rustsbi,uart-init-attempts") } {
+        Some(attempts) if attempts != 0 => attempts,
+        _ => DEFAULT_UART_INIT_ATTEMPTS,
+    }
+}
+
+// 设备树要求panic时额外输出到UART1。先在UART1上打印一行，便于确认调试串口的接线
+fn init_panic_uart(uart_init_attempts: usize) {
+    use core::fmt::Write;
+    let mut uart1 = match unsafe { peripheral::Uart::uart1_like_uart0(uart_init_attempts) } {
+        Ok((uart1, _)) => uart1,
+        Err(attempts) => {
+            println!(
+                "[rustsbi] warning: uart1 settings did not take after {} attempts, no panic console",
+                attempts
+            );
+            return;
+        }
+    };
+    // 控制台已经镜像到UART1时，panic的输出本来就会到达UART1
+    if console::stdout_uarts().map_or(false, |uarts| uarts.contains(&uart1)) {
+        return;
+    }
+    uart1.write_str("[rustsbi] panic console on uart1\r\n").ok();
+    console::init_panic_uart(uart1);
+    println!("[rustsbi] panic output also goes to uart1");
+}
+
+fn init_rustsbi_stdio(uarts: console::MultiUart) {
     use rustsbi::legacy_stdio::init_legacy_stdio_embedded_hal;
-    init_legacy_stdio_embedded_hal(uart);
+    init_legacy_stdio_embedded_hal(uarts);
 }
 
 fn init_rustsbi_clint(clint: peripheral::Clint) {
@@ -153,6 +519,36 @@ fn delegate_interrupt_exception() {
     }
 }
 
+// 清除上一级引导程序可能留下的时钟状态，S层第一次调用set_timer之前不应产生时钟中断
+fn init_timer_state(clint: peripheral::Clint, hart_id: usize) {
+    use riscv::register::{mie, mip};
+    hart_local::get(hart_id)
+        .supervisor_timer
+        .store(u64::MAX, Ordering::Relaxed);
+    clint.set_timer(hart_id, u64::MAX);
+    unsafe {
+        mip::clear_stimer();
+        mie::clear_mtimer();
+    }
+}
+
+// 等到启动核把这个核加入SUPERVISOR_HART_MASK。不进入S层的核，即使收到其它的IPI也继续等待。
+// 每次等待之前检查掩码，进入时不清除IPI：检查以后才发来的IPI会让wfi立即返回，不会丢失
+fn wait_for_supervisor_mask(clint: peripheral::Clint, hart_id: usize) {
+    use riscv::asm::wfi;
+    use riscv::register::mie;
+    let prev_msoft = mie::read().msoft();
+    unsafe { mie::set_msoft() };
+    while SUPERVISOR_HART_MASK.load(Ordering::Acquire) & (1 << hart_id) == 0 {
+        unsafe { wfi() };
+        clint.clear_soft(hart_id);
+    }
+    clint.clear_soft(hart_id);
+    if !prev_msoft {
+        unsafe { mie::clear_msoft() };
+    }
+}
+
 pub fn pause(clint: peripheral::Clint) {
     use riscv::asm::wfi;
     use riscv::register::{mhartid, mie, mip};
@@ -175,14 +571,13 @@ pub fn pause(clint: peripheral::Clint) {
     }
 }
 
+// 启动时的堆，解析设备树之前就要使用；打开heap-extension功能时，之后还会扩展
 const SBI_HEAP_SIZE: usize = 64 * 1024; // 64KiB
 #[link_section = ".bss.uninit"]
 static mut HEAP_SPACE: [u8; SBI_HEAP_SIZE] = [0; SBI_HEAP_SIZE];
 
-use buddy_system_allocator::LockedHeap;
-
 #[global_allocator]
-static HEAP_ALLOCATOR: LockedHeap<32> = LockedHeap::<32>::empty();
+static HEAP_ALLOCATOR: heap_usage::TrackedHeap<32> = heap_usage::TrackedHeap::<32>::empty();
 
 #[inline]
 fn init_heap() {
@@ -193,11 +588,103 @@ fn init_heap() {
     }
 }
 
+// 堆扩展区域的起始和结束地址，没有扩展时为0；固件占用的区域包括这一段，见payload::firmware_region
+static HEAP_EXTENSION_START: AtomicUsize = AtomicUsize::new(0);
+static HEAP_EXTENSION_END: AtomicUsize = AtomicUsize::new(0);
+
+fn heap_extension_end() -> usize {
+    HEAP_EXTENSION_END.load(Ordering::Acquire)
+}
+
+// 每个核进入S层之前调用：扩展过堆时，用PMP禁止S层访问扩展区域。
+// 扩展区域不在交给S层的设备树中，S层可能把它当作普通内存使用
+fn protect_heap_extension() {
+    let end = heap_extension_end();
+    if end != 0 {
+        let start = HEAP_EXTENSION_START.load(Ordering::Acquire);
+        hart_csr_utils::deny_supervisor_access(start, end);
+    }
+}
+
+// 扩展区域最大的长度
+#[cfg(feature = "heap-extension")]
+const HEAP_EXTENSION_MAX: usize = 1024 * 1024; // 1MiB
+
+// 解析设备树以后，把固件之后的一段内存加入堆。之前的堆仍然有效，已经分配的内存不受影响。
+// 扩展区域不能超出设备树给出的内存，也不能覆盖S层负载、备用负载和设备树
+#[cfg(feature = "heap-extension")]
+fn extend_heap(board_info: &device_tree::BoardInfo, next_addr: usize, dtb_pa: usize) {
+    const PAGE_SIZE: usize = 4096;
+    let memory_end = match board_info.memory {
+        Some((_, end)) => end,
+        None => {
+            println!("[rustsbi] warning: no memory range in device tree, heap not extended");
+            return;
+        }
+    };
+    let (_, firmware_end) = payload::firmware_region();
+    let start = (firmware_end + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+    let mut end = start.saturating_add(HEAP_EXTENSION_MAX).min(memory_end);
+    let limits = [Some(next_addr), board_info.rescue_addr, Some(dtb_pa)];
+    for limit in limits.into_iter().flatten().filter(|&limit| limit >= start) {
+        end = end.min(limit);
+    }
+    let end = end & !(PAGE_SIZE - 1);
+    if end <= start {
+        println!("[rustsbi] warning: no room after firmware, heap not extended");
+        return;
+    }
+    // 扩展以后仍然检查这块启动时分配的内存，见heap_usage::bootstrap_intact
+    heap_usage::place_bootstrap_canary();
+    unsafe { HEAP_ALLOCATOR.lock().add_to_heap(start, end) };
+    HEAP_EXTENSION_START.store(start, Ordering::Release);
+    HEAP_EXTENSION_END.store(end, Ordering::Release);
+    println!(
+        "[rustsbi] heap extended by {:#x} bytes at {:#x} ~ {:#x}",
+        end - start,
+        start,
+        end
+    );
+}
+
 const PER_HART_STACK_SIZE: usize = 4 * 4096; // 16KiB
 const SBI_STACK_SIZE: usize = 5 * PER_HART_STACK_SIZE; // 5 harts
 #[link_section = ".bss.uninit"]
 static mut SBI_STACK: [u8; SBI_STACK_SIZE] = [0; SBI_STACK_SIZE];
 
+// 从warm_entry进入。按单核热复位的方式恢复委托、时钟和M层的陷入入口，再从S层的入口重新开始，
+// 入口和设备树和SRST扩展的热重启相同
+extern "C" fn warm_main(hart_id: usize) -> ! {
+    use riscv::register::{mip, mstatus, sstatus};
+    let clint = peripheral::Clint::new(0x2000000 as *mut u8);
+    let hart = hart_local::get(hart_id);
+    if hart_id != 0 {
+        delegate_interrupt_exception();
+    }
+    clint.clear_soft(hart_id);
+    init_timer_state(clint, hart_id);
+    hart.deadman_timeout.store(0, Ordering::Relaxed);
+    hart.deadman_fired.store(false, Ordering::Relaxed);
+    hart.reset_addr.store(0, Ordering::Relaxed);
+    hart.restart.store(false, Ordering::Relaxed);
+    unsafe {
+        mip::clear_ssoft();
+        sstatus::clear_sie();
+        core::arch::asm!("csrw sie, zero", "csrw satp, zero");
+        mstatus::set_fs(mstatus::FS::Off);
+    }
+    runtime::init();
+    hart.set_state(HartState::Running);
+    let entry = SUPERVISOR_ENTRY.load(Ordering::Acquire);
+    let dtb = dtb_handoff::next_dtb();
+    println!("[rustsbi] hart {} warm restart at {:#x}", hart_id, entry);
+    execute::execute_supervisor(entry, hart_id, dtb);
+    ecall::halt()
+}
+
+// 打开stack-watermark功能时，启动时用来填满栈的值
+const STACK_FILL_PATTERN: u32 = 0x57AC_57AC;
+
 #[naked]
 #[link_section = ".text.entry"]
 #[export_name = "_start"]
@@ -245,12 +732,49 @@ unsafe extern "C" fn entry() -> ! {
     addi    t2, t2, -1
     bnez    t2, 1b
     ",
-    // 3. jump to main function (absolute address)
+    // 3. fill this hart's stack with a known pattern for stack-watermark
+    "
+.if {stack_watermark}
+    sub     t1, sp, t0
+    li      t2, {stack_fill_pattern}
+2:  sw      t2, 0(t1)
+    addi    t1, t1, 4
+    bltu    t1, sp, 2b
+.endif
+    ",
+    // 4. jump to main function (absolute address)
     "call   {rust_main}",
-    // 4. after main function return, invoke CEASE instruction
+    // 5. after main function return, invoke CEASE instruction
     ".word 0x30500073", // cease
     per_hart_stack_size = const PER_HART_STACK_SIZE,
+    stack_watermark = const cfg!(feature = "stack-watermark") as usize,
+    stack_fill_pattern = const STACK_FILL_PATTERN,
     stack = sym SBI_STACK,
     rust_main = sym rust_main,
     options(noreturn))
 }
+
+// 固件自己的热启动入口，目前只在panic以后热重启时使用。
+// 核已经初始化过一次，寄存器的内容都由固件控制，不需要像_start那样清除寄存器、
+// 初始化bss和填充栈，只丢弃当前的栈，从这个核的栈顶进入warm_main。
+// 这个符号不导出，也不在.text.entry段中，外部的引导程序不会从这里冷启动
+#[naked]
+unsafe extern "C" fn warm_entry() -> ! {
+    core::arch::asm!(
+    // sp = bootstack + (hart_id + 1) * HART_STACK_SIZE，和_start相同
+    "
+    la      sp, {stack}
+    li      t0, {per_hart_stack_size}
+    csrr    a0, mhartid
+    addi    t2, a0, 1
+1:  add     sp, sp, t0
+    addi    t2, t2, -1
+    bnez    t2, 1b
+    ",
+    "call   {warm_main}",
+    ".word 0x30500073", // cease
+    per_hart_stack_size = const PER_HART_STACK_SIZE,
+    stack = sym SBI_STACK,
+    warm_main = sym warm_main,
+    options(noreturn))
+}
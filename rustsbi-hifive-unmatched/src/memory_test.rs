@@ -0,0 +1,104 @@
+// 启动早期的内存测试，只在打开memory-test功能时编译，用于硬件调试
+//
+// 为了控制启动时间，只按照步长抽样测试一部分内存块。测试一块内存之前先保存它原来的内容，
+// 测试以后再恢复，因此上一级引导程序留在内存中的数据不会被破坏；
+// 即使这样，固件、S层负载和设备树所在的区域也不会被测试
+use crate::console::println;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+// 每次测试的内存块大小
+const BLOCK_SIZE: usize = 4096;
+const BLOCK_WORDS: usize = BLOCK_SIZE / 8;
+/// 设备树中没有给出步长时，每64MiB测试一块
+pub const DEFAULT_STRIDE: usize = 64 * 1024 * 1024;
+// 最多逐个打印的错误个数，其余的错误只计数
+const MAX_REPORTED_ERRORS: usize = 16;
+
+const PATTERNS: [u64; 4] = [
+    0x0000_0000_0000_0000,
+    0xFFFF_FFFF_FFFF_FFFF,
+    0x5555_5555_5555_5555,
+    0xAAAA_AAAA_AAAA_AAAA,
+];
+
+// 保存被测试的内存块原来的内容；固件的栈放不下一整块
+#[link_section = ".bss.uninit"]
+static mut SAVED_BLOCK: [u64; BLOCK_WORDS] = [0; BLOCK_WORDS];
+
+// 测试过的块数和出错的字数，供S层通过专有SBI调用读出
+static TESTED_BLOCKS: AtomicUsize = AtomicUsize::new(0);
+static ERRORS: AtomicUsize = AtomicUsize::new(0);
+
+/// 测试memory范围中按stride抽样的内存块，跳过和excluded中任何一段区域重叠的块。
+/// 只能在其余的核都还在等待唤醒时调用
+pub fn run(memory: (usize, usize), stride: usize, excluded: &[(usize, usize)]) {
+    let (start, end) = memory;
+    let stride = stride.max(BLOCK_SIZE);
+    println!(
+        "[rustsbi] memory test: {:#x} ~ {:#x}, one {} byte block every {:#x} bytes",
+        start, end, BLOCK_SIZE, stride
+    );
+    let mut tested = 0;
+    let mut errors = 0;
+    let mut block = (start + BLOCK_SIZE - 1) & !(BLOCK_SIZE - 1);
+    while block < end && end - block >= BLOCK_SIZE {
+        let block_end = block + BLOCK_SIZE;
+        if !excluded.iter().any(|&(s, e)| block < e && s < block_end) {
+            errors += unsafe { test_block(block, errors) };
+            tested += 1;
+        }
+        block = match block.checked_add(stride) {
+            Some(next) => next,
+            None => break,
+        };
+    }
+    TESTED_BLOCKS.store(tested, Ordering::Relaxed);
+    ERRORS.store(errors, Ordering::Release);
+    if errors == 0 {
+        println!("[rustsbi] memory test: {} blocks passed", tested);
+    } else {
+        println!(
+            "[rustsbi] memory test FAILED: {} mismatched words in {} blocks",
+            errors, tested
+        );
+    }
+}
+
+/// 测试的结果：测试过的块数和出错的字数
+pub fn result() -> (usize, usize) {
+    let errors = ERRORS.load(Ordering::Acquire);
+    (TESTED_BLOCKS.load(Ordering::Relaxed), errors)
+}
+
+// 依次写入固定的图案和每个字自己的地址，全部写完以后再读回，这样也能发现地址线的问题
+unsafe fn test_block(base: usize, reported: usize) -> usize {
+    let block = base as *mut u64;
+    for (i, saved) in SAVED_BLOCK.iter_mut().enumerate() {
+        *saved = block.add(i).read_volatile();
+    }
+    let mut errors = 0;
+    for pattern in PATTERNS.iter().map(|&p| Some(p)).chain(Some(None)) {
+        let expected = |i: usize| pattern.unwrap_or(base as u64 + (i * 8) as u64);
+        for i in 0..BLOCK_WORDS {
+            block.add(i).write_volatile(expected(i));
+        }
+        for i in 0..BLOCK_WORDS {
+            let value = block.add(i).read_volatile();
+            if value != expected(i) {
+                if reported + errors < MAX_REPORTED_ERRORS {
+                    println!(
+                        "[rustsbi] memory test: mismatch at {:#x}, wrote {:#018x}, read {:#018x}",
+                        base + i * 8,
+                        expected(i),
+                        value
+                    );
+                }
+                errors += 1;
+            }
+        }
+    }
+    for (i, &saved) in SAVED_BLOCK.iter().enumerate() {
+        block.add(i).write_volatile(saved);
+    }
+    errors
+}
@@ -0,0 +1,205 @@
+// 交互式监视器，只在打开monitor功能时编译
+//
+// 固件不能进入S层时，用户可以在串口上查看固件的状态、读出内存，并手动指定S层的入口。
+// 这时监视器运行在启动核上，其余的核仍在等待唤醒。设备树选择panic时进入监视器的，
+// 监视器运行在发生panic的核上，其余的核可能仍在S层运行
+use crate::console::{self, print, println};
+use crate::hart_local::{self, HartState, MAX_HART_ID};
+use crate::payload;
+use crate::peripheral::{Clint, TICKS_PER_SECOND};
+use core::sync::atomic::Ordering;
+
+/// 设备树中没有给出时，等待用户按键的秒数
+pub const DEFAULT_TIMEOUT_SECONDS: usize = 10;
+const MAX_LINE: usize = 64;
+
+/// 等待用户在timeout秒内按下任意键，然后进入监视器；timeout为0时一直等待。
+/// 返回用户指定的S层入口，超时或者用户选择停机时返回None
+pub fn enter_on_keypress(
+    clint: Clint,
+    timeout: usize,
+    boot_hart: usize,
+    dtb_pa: usize,
+) -> Option<usize> {
+    println!(
+        "[rustsbi] press any key within {} second(s) to enter the monitor",
+        timeout
+    );
+    let deadline = clint
+        .get_mtime()
+        .saturating_add(timeout as u64 * TICKS_PER_SECOND);
+    loop {
+        if console::getchar().is_some() {
+            break;
+        }
+        if timeout != 0 && clint.get_mtime() >= deadline {
+            return None;
+        }
+    }
+    // 用户按了键，说明串口上确实接了终端
+    console::set_quiet(false);
+    run(clint, boot_hart, dtb_pa)
+}
+
+/// 固件panic以后进入监视器，不等待按键。返回用户指定的S层入口，用户选择停机时返回None
+pub fn enter_after_panic(clint: Clint, hart_id: usize, dtb_pa: usize) -> Option<usize> {
+    console::set_quiet(false);
+    println!("[rustsbi] hart {} panicked, entering the monitor", hart_id);
+    run(clint, crate::boot_hart(), dtb_pa)
+}
+
+fn run(clint: Clint, boot_hart: usize, dtb_pa: usize) -> Option<usize> {
+    println!("[rustsbi] monitor, type 'help' for commands");
+    let mut entry = None;
+    let mut line = [0u8; MAX_LINE];
+    loop {
+        print!("monitor> ");
+        let len = read_line(&mut line);
+        let line = core::str::from_utf8(&line[..len]).unwrap_or("");
+        let mut words = line.split_whitespace();
+        match (words.next(), words.next()) {
+            (None, _) => {}
+            (Some("help"), _) => print_help(),
+            (Some("info"), _) => print_info(entry, dtb_pa),
+            (Some("harts"), None) => print_harts(boot_hart),
+            (Some("harts"), Some(interval)) => match parse_number(interval) {
+                Some(interval) if interval > 0 => watch_harts(clint, boot_hart, interval),
+                _ => println!("harts: need an interval in seconds"),
+            },
+            (Some("peek"), Some(addr)) => match parse_number(addr) {
+                Some(addr) if addr % 8 == 0 => {
+                    let value = unsafe { core::ptr::read_volatile(addr as *const u64) };
+                    println!("{:#x}: {:#018x}", addr, value);
+                }
+                _ => println!("peek: need an 8-byte aligned address"),
+            },
+            (Some("entry"), Some(addr)) => match parse_number(addr) {
+                Some(addr) if payload::is_entry_fetchable(addr) => {
+                    entry = Some(addr);
+                    println!("entry set to {:#x}, type 'boot' to enter it", addr);
+                }
+                Some(addr) => println!("entry: {:#x} is not fetchable", addr),
+                None => println!("entry: cannot parse {}", addr),
+            },
+            (Some("boot"), _) => match entry {
+                // 入口处的内容可能在设置以后被改动，进入之前再检查一次
+                Some(addr) if payload::is_entry_fetchable(addr) => return Some(addr),
+                Some(addr) => println!("boot: {:#x} is no longer fetchable", addr),
+                None => println!("boot: set an entry first"),
+            },
+            (Some("halt"), _) => return None,
+            (Some(command), _) => println!("unknown command {}, type 'help'", command),
+        }
+    }
+}
+
+fn print_help() {
+    println!("help          show this message");
+    println!("info          show firmware and boot state");
+    println!("harts [secs]  show every hart, repeat every secs until a key is pressed");
+    println!("peek <addr>   read 8 bytes of memory");
+    println!("entry <addr>  set the supervisor entry");
+    println!("boot          enter the supervisor at the entry");
+    println!("halt          stop here");
+}
+
+fn print_info(entry: Option<usize>, dtb_pa: usize) {
+    let (firmware_start, firmware_end) = payload::firmware_region();
+    println!("firmware:  {:#x} ~ {:#x}", firmware_start, firmware_end);
+    println!("dtb:       {:#x}", dtb_pa);
+    match entry {
+        Some(entry) => println!("entry:     {:#x}", entry),
+        None => println!("entry:     not set"),
+    }
+}
+
+// 每个核一行：状态、是否启动核、停止或挂起时停在的S层地址，以及各类陷入的次数。
+// 陷入次数来自trap-profile功能的统计，没有打开时显示为-
+fn print_harts(boot_hart: usize) {
+    println!("hart  state          boot  mepc                sbi       illegal   timer     soft");
+    for hart_id in 0..=MAX_HART_ID {
+        let hart = hart_local::get(hart_id);
+        let state = hart.state();
+        print!(
+            "{:<4}  {:<13}  {:<4}  ",
+            hart_id,
+            state_name(state),
+            if hart_id == boot_hart { "yes" } else { "" }
+        );
+        match state {
+            HartState::Stopped | HartState::Suspended => {
+                print!("{:<#18x}  ", hart.last_ecall_mepc.load(Ordering::Relaxed))
+            }
+            _ => print!("{:<18}  ", "-"),
+        }
+        #[cfg(feature = "trap-profile")]
+        for count in crate::trap_profile::counts(hart_id) {
+            print!("{:<8}  ", count);
+        }
+        #[cfg(not(feature = "trap-profile"))]
+        print!("{:<8}  {:<8}  {:<8}  {:<8}  ", "-", "-", "-", "-");
+        println!("");
+    }
+}
+
+// 每隔interval秒打印一次，直到用户按键
+fn watch_harts(clint: Clint, boot_hart: usize, interval: usize) {
+    let interval = interval as u64 * TICKS_PER_SECOND;
+    loop {
+        print_harts(boot_hart);
+        let deadline = clint.get_mtime().saturating_add(interval);
+        while clint.get_mtime() < deadline {
+            if console::getchar().is_some() {
+                return;
+            }
+        }
+    }
+}
+
+fn state_name(state: HartState) -> &'static str {
+    match state {
+        HartState::Parked => "parked",
+        HartState::Ready => "ready",
+        HartState::Panicked => "panicked",
+        HartState::Running => "started",
+        HartState::Stopped => "stopped",
+        HartState::StartPending => "start-pending",
+        HartState::Suspended => "suspended",
+    }
+}
+
+// 读入一行，回显输入的字符，支持退格；返回这一行的长度
+fn read_line(buf: &mut [u8]) -> usize {
+    let mut len = 0;
+    loop {
+        let byte = match console::getchar() {
+            Some(byte) => byte,
+            None => continue,
+        };
+        match byte {
+            b'\r' | b'\n' => {
+                println!("");
+                return len;
+            }
+            // 退格和DEL
+            0x08 | 0x7F if len > 0 => {
+                len -= 1;
+                print!("\x08 \x08");
+            }
+            0x20..=0x7E if len < buf.len() => {
+                buf[len] = byte;
+                len += 1;
+                print!("{}", byte as char);
+            }
+            _ => {}
+        }
+    }
+}
+
+// 接受十六进制（带0x前缀）和十进制的数
+fn parse_number(s: &str) -> Option<usize> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
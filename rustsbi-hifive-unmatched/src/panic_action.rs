@@ -0,0 +1,143 @@
+// 固件panic以后的处理方式，由设备树/chosen节点中的rustsbi,panic-action选择
+//
+// 默认停机。选择热重启时，等待一段时间后按SRST扩展的热重启让进入过S层的核重新进入S层，
+// 发生panic的核也换回干净的栈重新进入；选择监视器时，在串口上进入交互式监视器，需要打开monitor功能。
+// 解析设备树之前发生的panic总是停机
+use crate::console::eprintln;
+use crate::peripheral::{Clint, TICKS_PER_SECOND};
+use core::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PanicAction {
+    /// 停止发生panic的核，其余的核不受影响
+    Halt = 0,
+    /// 等待一段时间后热重启
+    Reboot = 1,
+    /// 进入交互式监视器
+    Monitor = 2,
+}
+
+impl PanicAction {
+    /// 设备树中的写法
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "halt" => Some(PanicAction::Halt),
+            "reboot" => Some(PanicAction::Reboot),
+            "monitor" => Some(PanicAction::Monitor),
+            _ => None,
+        }
+    }
+
+    /// 专有SBI调用中的编号，和这里的取值相同
+    pub fn from_usize(value: usize) -> Option<Self> {
+        match value {
+            0 => Some(PanicAction::Halt),
+            1 => Some(PanicAction::Reboot),
+            2 => Some(PanicAction::Monitor),
+            _ => None,
+        }
+    }
+}
+
+/// 设备树中没有给出时，热重启之前等待的秒数
+pub const DEFAULT_REBOOT_DELAY_SECONDS: usize = 5;
+
+static ACTION: AtomicU8 = AtomicU8::new(PanicAction::Halt as u8);
+static REBOOT_DELAY: AtomicUsize = AtomicUsize::new(DEFAULT_REBOOT_DELAY_SECONDS);
+// 只有第一个发生panic的核执行选择的处理方式，之后发生panic的核直接停机
+static HANDLING: AtomicBool = AtomicBool::new(false);
+
+/// 解析设备树以后调用，设置处理方式；没有打开monitor功能时，选择监视器也按停机处理
+pub fn init(action: Option<PanicAction>, reboot_delay: Option<usize>) {
+    use crate::console::println;
+    let action = action.unwrap_or(PanicAction::Halt);
+    let reboot_delay = reboot_delay.unwrap_or(DEFAULT_REBOOT_DELAY_SECONDS);
+    REBOOT_DELAY.store(reboot_delay, Ordering::Relaxed);
+    match action {
+        PanicAction::Halt => {}
+        PanicAction::Reboot => println!(
+            "[rustsbi] on panic, warm reboot after {} second(s)",
+            reboot_delay
+        ),
+        PanicAction::Monitor if cfg!(feature = "monitor") => {
+            println!("[rustsbi] on panic, enter the monitor")
+        }
+        PanicAction::Monitor => {
+            println!(
+                "[rustsbi] warning: panic action monitor needs the monitor feature, halt on panic"
+            );
+            return;
+        }
+    }
+    set_action(action);
+}
+
+/// 修改处理方式，之后发生的panic按新的方式处理
+pub fn set_action(action: PanicAction) {
+    ACTION.store(action as u8, Ordering::Release);
+}
+
+fn action() -> PanicAction {
+    PanicAction::from_usize(ACTION.load(Ordering::Acquire) as usize).unwrap_or(PanicAction::Halt)
+}
+
+/// 在panic处理函数打印panic信息以后调用
+pub fn handle(hart_id: usize) -> ! {
+    if HANDLING.swap(true, Ordering::AcqRel) {
+        crate::ecall::halt()
+    }
+    match action() {
+        PanicAction::Halt => crate::ecall::halt(),
+        PanicAction::Reboot => {
+            let delay = REBOOT_DELAY.load(Ordering::Relaxed);
+            eprintln!(
+                "[rustsbi-panic] hart {} panicked, warm reboot in {} second(s)",
+                hart_id, delay
+            );
+            let clint = Clint::new(0x2000000 as *mut u8);
+            let deadline = clint
+                .get_mtime()
+                .saturating_add(delay as u64 * TICKS_PER_SECOND);
+            while clint.get_mtime() < deadline {
+                core::hint::spin_loop();
+            }
+            reboot(hart_id)
+        }
+        PanicAction::Monitor => monitor(hart_id),
+    }
+}
+
+// 热重启只能重新启动还在正常运行或者停止的核，发生panic的核自己重新进入S层
+fn reboot(hart_id: usize) -> ! {
+    if crate::ecall::warm_reboot().error != 0 {
+        eprintln!("[rustsbi-panic] no supervisor to reboot into, halt");
+        crate::ecall::halt()
+    }
+    if crate::SUPERVISOR_HART_MASK.load(Ordering::Acquire) & (1 << hart_id) == 0 {
+        // 这个核本来就不进入S层
+        crate::ecall::halt()
+    }
+    // 重新进入S层以后再次发生的panic，仍然按选择的方式处理
+    HANDLING.store(false, Ordering::Release);
+    unsafe { crate::warm_entry() }
+}
+
+// 用户在监视器中指定了入口时，从这个入口热重启
+#[cfg(feature = "monitor")]
+fn monitor(hart_id: usize) -> ! {
+    let clint = Clint::new(0x2000000 as *mut u8);
+    let dtb_pa = crate::dtb_handoff::next_dtb();
+    match crate::monitor::enter_after_panic(clint, hart_id, dtb_pa) {
+        Some(entry) => {
+            crate::SUPERVISOR_ENTRY.store(entry, Ordering::Release);
+            reboot(hart_id)
+        }
+        None => crate::ecall::halt(),
+    }
+}
+
+#[cfg(not(feature = "monitor"))]
+fn monitor(_hart_id: usize) -> ! {
+    crate::ecall::halt()
+}
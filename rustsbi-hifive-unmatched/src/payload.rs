@@ -0,0 +1,83 @@
+// 下一阶段引导程序（一般是操作系统内核）的入口检查
+use crate::console::println;
+
+// HiFive Unmatched板载16GiB内存
+const DRAM_START: usize = 0x8000_0000;
+const DRAM_END: usize = DRAM_START + 16 * 1024 * 1024 * 1024;
+
+/// 选出可以跳转的入口地址；主入口不可用时，尝试使用设备树中的备用入口。
+/// payload_size是设备树中给出的主负载长度，没有给出时只检查入口本身
+pub fn choose_entry(
+    next_addr: usize,
+    payload_size: Option<usize>,
+    rescue_addr: Option<usize>,
+) -> Option<usize> {
+    let (firmware_start, firmware_end) = firmware_region();
+    let payload_end = next_addr.saturating_add(payload_size.unwrap_or(1));
+    if next_addr < firmware_end && firmware_start < payload_end {
+        // 引导程序把S层镜像放在了固件上面，固件已经被覆盖或者即将被覆盖
+        println!(
+            "[rustsbi] error: supervisor image {:#x} ~ {:#x} overlaps firmware {:#x} ~ {:#x}",
+            next_addr, payload_end, firmware_start, firmware_end
+        );
+    } else if is_entry_fetchable(next_addr) {
+        return Some(next_addr);
+    } else {
+        println!(
+            "[rustsbi] error: supervisor entry {:#x} is not fetchable",
+            next_addr
+        );
+    }
+    let rescue_addr = rescue_addr?;
+    if !is_entry_fetchable(rescue_addr) {
+        println!(
+            "[rustsbi] error: rescue entry {:#x} is not fetchable either",
+            rescue_addr
+        );
+        return None;
+    }
+    println!("[rustsbi] **************************************************");
+    println!(
+        "[rustsbi] * FALLBACK: booting rescue payload at {:#x}",
+        rescue_addr
+    );
+    println!("[rustsbi] **************************************************");
+    Some(rescue_addr)
+}
+
+/// 入口地址必须对齐，位于内存中而且不在固件自己的区域内，入口处还要有像样的指令
+pub fn is_entry_fetchable(addr: usize) -> bool {
+    if addr & 0b1 != 0 || !(DRAM_START..DRAM_END - 4).contains(&addr) {
+        return false;
+    }
+    let (firmware_start, firmware_end) = firmware_region();
+    if (firmware_start..firmware_end).contains(&addr) {
+        return false;
+    }
+    // 全0和全1的指令都是非法指令，通常说明这里没有加载任何负载
+    let ins = unsafe { core::ptr::read_volatile(addr as *const u16) };
+    ins != 0 && ins != 0xFFFF
+}
+
+/// 区域必须整个位于内存中，而且不和固件自己的区域重叠
+pub fn is_supervisor_region(base: usize, size: usize) -> bool {
+    let end = match base.checked_add(size) {
+        Some(end) => end,
+        None => return false,
+    };
+    if base < DRAM_START || end > DRAM_END {
+        return false;
+    }
+    let (firmware_start, firmware_end) = firmware_region();
+    !(base < firmware_end && firmware_start < end)
+}
+
+/// 固件自身占用的内存范围，包括代码、数据、堆和各个核的栈，以及扩展以后的堆
+pub fn firmware_region() -> (usize, usize) {
+    extern "C" {
+        static stext: u32;
+        static ebss: u32;
+    }
+    let (start, end) = unsafe { (&stext as *const _ as usize, &ebss as *const _ as usize) };
+    (start, end.max(crate::heap_extension_end()))
+}
@@ -1,3 +1,6 @@
+/// mtime的频率是1MHz
+pub const TICKS_PER_SECOND: u64 = 1_000_000;
+
 #[derive(Clone, Copy)]
 pub struct Clint {
     base: *mut u8,
@@ -22,6 +25,10 @@ impl Clint {
         }
     }
 
+    pub fn get_timer(&self, hart_id: usize) -> u64 {
+        unsafe { core::ptr::read_volatile((self.base.offset(0x4000) as *mut u64).add(hart_id)) }
+    }
+
     pub fn send_soft(&self, hart_id: usize) {
         unsafe {
             core::ptr::write_volatile((self.base as *mut u32).add(hart_id), 1);
@@ -53,6 +60,16 @@ impl rustsbi::Ipi for Clint {
 impl rustsbi::Timer for Clint {
     fn set_timer(&self, time_value: u64) {
         let this_mhartid = riscv::register::mhartid::read();
-        self.set_timer(this_mhartid, time_value);
+        // 保存的是换算到mtime的值，打开time-scale功能时和S层给出的值不同
+        crate::hart_local::get(this_mhartid).supervisor_timer.store(
+            crate::time_scale::machine_time(time_value),
+            core::sync::atomic::Ordering::Relaxed,
+        );
+        // 比较寄存器和看门狗共用，由deadman模块取较早的时间写入，并重新打开M层时钟中断
+        crate::deadman::rearm(this_mhartid);
+        unsafe {
+            // 清除上一次转交给S层的时钟中断
+            riscv::register::mip::clear_stimer();
+        }
     }
 }
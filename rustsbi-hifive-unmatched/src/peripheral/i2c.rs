@@ -0,0 +1,121 @@
+// FU740的I2C控制器（OpenCores I2C master），寄存器间隔4个字节，每个寄存器只有8位
+use core::ptr::{read_volatile, write_volatile};
+
+const PRESCALE_LO: usize = 0x00;
+const PRESCALE_HI: usize = 0x04;
+const CONTROL: usize = 0x08;
+// 写入时是发送寄存器，读出时是接收寄存器
+const DATA: usize = 0x0C;
+// 写入时是命令寄存器，读出时是状态寄存器
+const COMMAND: usize = 0x10;
+
+const CONTROL_ENABLE: u8 = 1 << 7;
+const COMMAND_START: u8 = 1 << 7;
+const COMMAND_STOP: u8 = 1 << 6;
+const COMMAND_READ: u8 = 1 << 5;
+const COMMAND_WRITE: u8 = 1 << 4;
+const COMMAND_NACK: u8 = 1 << 3;
+const STATUS_NO_ACK: u8 = 1 << 7;
+const STATUS_ARBITRATION_LOST: u8 = 1 << 5;
+const STATUS_IN_PROGRESS: u8 = 1 << 1;
+
+// 等待一个字节传输完成的最大轮询次数
+const TRANSFER_SPINS: usize = 1_000_000;
+// 上一级引导程序没有打开控制器时使用的分频，按pclk最高260MHz计算，总线不超过100kHz；
+// pclk更低时总线只会更慢
+const DEFAULT_PRESCALE: u16 = 519;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum I2cError {
+    /// 等待传输完成超时，可能是总线被拉住
+    Timeout,
+    /// 设备没有应答
+    NoAck,
+    /// 总线上有其它主设备
+    ArbitrationLost,
+}
+
+pub struct I2c {
+    base: usize,
+}
+
+impl I2c {
+    // 上一级引导程序已经打开控制器时沿用它的分频，否则按DEFAULT_PRESCALE打开
+    pub unsafe fn new(base: usize) -> Self {
+        let i2c = Self { base };
+        if i2c.read(CONTROL) & CONTROL_ENABLE == 0 {
+            i2c.write(PRESCALE_LO, DEFAULT_PRESCALE as u8);
+            i2c.write(PRESCALE_HI, (DEFAULT_PRESCALE >> 8) as u8);
+            i2c.write(CONTROL, CONTROL_ENABLE);
+        }
+        i2c
+    }
+
+    /// 读出设备从reg开始的buf.len()个寄存器：先写入寄存器编号，再重新开始读
+    pub fn read_registers(&self, address: u8, reg: u8, buf: &mut [u8]) -> Result<(), I2cError> {
+        let ans = self.try_read_registers(address, reg, buf);
+        if ans.is_err() {
+            // 出错以后发出停止条件释放总线，避免影响之后S层的驱动
+            self.write(COMMAND, COMMAND_STOP);
+            let _ = self.wait();
+        }
+        ans
+    }
+
+    fn try_read_registers(&self, address: u8, reg: u8, buf: &mut [u8]) -> Result<(), I2cError> {
+        self.send(address << 1, COMMAND_START)?;
+        if buf.is_empty() {
+            return self.send(reg, COMMAND_STOP);
+        }
+        self.send(reg, 0)?;
+        self.send((address << 1) | 1, COMMAND_START)?;
+        let len = buf.len();
+        for (i, byte) in buf.iter_mut().enumerate() {
+            // 最后一个字节不应答，并且发出停止条件
+            let command = if i + 1 == len {
+                COMMAND_READ | COMMAND_NACK | COMMAND_STOP
+            } else {
+                COMMAND_READ
+            };
+            self.write(COMMAND, command);
+            self.wait()?;
+            *byte = self.read(DATA);
+        }
+        Ok(())
+    }
+
+    // 发送一个字节，检查设备的应答
+    fn send(&self, byte: u8, command: u8) -> Result<(), I2cError> {
+        self.write(DATA, byte);
+        self.write(COMMAND, command | COMMAND_WRITE);
+        if self.wait()? & STATUS_NO_ACK != 0 {
+            return Err(I2cError::NoAck);
+        }
+        Ok(())
+    }
+
+    // 等待当前的传输完成，返回状态寄存器
+    fn wait(&self) -> Result<u8, I2cError> {
+        for _ in 0..TRANSFER_SPINS {
+            let status = self.read(COMMAND);
+            if status & STATUS_ARBITRATION_LOST != 0 {
+                return Err(I2cError::ArbitrationLost);
+            }
+            if status & STATUS_IN_PROGRESS == 0 {
+                return Ok(status);
+            }
+            core::hint::spin_loop();
+        }
+        Err(I2cError::Timeout)
+    }
+
+    #[inline]
+    fn read(&self, offset: usize) -> u8 {
+        unsafe { read_volatile((self.base + offset) as *const u8) }
+    }
+
+    #[inline]
+    fn write(&self, offset: usize, value: u8) {
+        unsafe { write_volatile((self.base + offset) as *mut u8, value) }
+    }
+}
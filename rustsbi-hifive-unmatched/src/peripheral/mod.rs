@@ -2,4 +2,8 @@
 pub(crate) mod uart;
 pub use uart::Uart;
 mod clint;
-pub use clint::Clint;
+pub use clint::{Clint, TICKS_PER_SECOND};
+#[cfg(feature = "board-sensors")]
+mod i2c;
+#[cfg(feature = "board-sensors")]
+pub use i2c::{I2c, I2cError};
@@ -1,9 +1,10 @@
 use core::convert::Infallible;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use embedded_hal::serial::{Read, Write};
 use fu740_hal::pac;
 
 // UART that is initialized by prior steps of bootloading
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub struct Uart {
     inner: *const pac::uart0::RegisterBlock,
 }
@@ -18,8 +19,144 @@ impl Uart {
         let inner = pac::UART0::ptr();
         Self { inner }
     }
+
+    // UART1没有被之前的引导阶段初始化，这里按UART0的波特率分频打开它。
+    // 刚改变时钟时，设置可能不会马上生效，因此回读寄存器确认，最多尝试max_attempts次。
+    // 成功时返回串口和尝试的次数，失败时返回尝试的次数
+    pub unsafe fn uart1_like_uart0(max_attempts: usize) -> Result<(Self, usize), usize> {
+        let uart0 = &*pac::UART0::ptr();
+        let div = uart0.div.read().div().bits();
+        let mut uart1 = Self {
+            inner: pac::UART1::ptr(),
+        };
+        #[cfg(not(feature = "uart-init-faults"))]
+        let result = configure_with_retry(&mut uart1, div, max_attempts);
+        #[cfg(feature = "uart-init-faults")]
+        let result = configure_with_retry(&mut FailFirstReadback::new(uart1), div, max_attempts);
+        let recorded = match result {
+            Ok(attempts) => attempts,
+            Err(attempts) => attempts | UART1_INIT_FAILED,
+        };
+        UART1_INIT_RESULT.store(recorded, Ordering::Relaxed);
+        result.map(|attempts| (uart1, attempts))
+    }
+
+    // 发送队列一直是满的时候调用：关闭发送，按原来的分频重新写入设置，再打开发送。
+    // 返回发送队列是否重新有了空位
+    pub unsafe fn reset_transmitter(&self) -> bool {
+        let regs = &*self.inner;
+        let div = regs.div.read().div().bits();
+        regs.txctrl.modify(|_, w| w.txen().clear_bit());
+        for _ in 0..UART_INIT_RETRY_DELAY {
+            core::hint::spin_loop();
+        }
+        regs.div.write(|w| w.div().bits(div));
+        regs.txctrl.modify(|_, w| w.txen().set_bit());
+        // 按115200波特率，发送一个字节不到100微秒
+        for _ in 0..UART_RECOVERY_SPINS {
+            if regs.txdata.read().full().bit_is_clear() {
+                return true;
+            }
+            core::hint::spin_loop();
+        }
+        false
+    }
+}
+
+/// 串口设置寄存器的写入和回读。初始化重试的逻辑只通过它访问寄存器，
+/// 因此可以换成回读结果可控的实现来模拟设置没有马上生效的串口
+pub trait SettingRegisters {
+    /// 写入分频和收发使能
+    unsafe fn write_settings(&mut self, div: u16);
+    /// 回读的设置是否和写入的一致
+    unsafe fn settings_took(&mut self, div: u16) -> bool;
+}
+
+impl SettingRegisters for Uart {
+    unsafe fn write_settings(&mut self, div: u16) {
+        let regs = &*self.inner;
+        regs.div.write(|w| w.div().bits(div));
+        regs.txctrl.write(|w| w.txen().set_bit());
+        regs.rxctrl.write(|w| w.rxen().set_bit());
+    }
+
+    unsafe fn settings_took(&mut self, div: u16) -> bool {
+        let regs = &*self.inner;
+        regs.div.read().div().bits() == div
+            && regs.txctrl.read().txen().bit_is_set()
+            && regs.rxctrl.read().rxen().bit_is_set()
+    }
+}
+
+/// 写入设置并回读确认，最多尝试max_attempts次。成功和失败时都返回尝试的次数
+pub unsafe fn configure_with_retry<R: SettingRegisters>(
+    regs: &mut R,
+    div: u16,
+    max_attempts: usize,
+) -> Result<usize, usize> {
+    for attempt in 1..=max_attempts {
+        regs.write_settings(div);
+        if regs.settings_took(div) {
+            return Ok(attempt);
+        }
+        for _ in 0..UART_INIT_RETRY_DELAY {
+            core::hint::spin_loop();
+        }
+    }
+    Err(max_attempts)
+}
+
+/// 打开uart-init-faults功能时使用：第一次回读总是报告设置没有生效，
+/// 模拟需要两次尝试才能设置好的串口
+#[cfg(feature = "uart-init-faults")]
+pub struct FailFirstReadback<R> {
+    inner: R,
+    failed: bool,
+}
+
+#[cfg(feature = "uart-init-faults")]
+impl<R> FailFirstReadback<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            failed: false,
+        }
+    }
+}
+
+#[cfg(feature = "uart-init-faults")]
+impl<R: SettingRegisters> SettingRegisters for FailFirstReadback<R> {
+    unsafe fn write_settings(&mut self, div: u16) {
+        self.inner.write_settings(div)
+    }
+
+    unsafe fn settings_took(&mut self, div: u16) -> bool {
+        if !self.failed {
+            self.failed = true;
+            return false;
+        }
+        self.inner.settings_took(div)
+    }
 }
 
+// 最近一次打开UART1的结果：0表示还没有打开过，否则是尝试的次数，设置没有生效时最高位置位
+static UART1_INIT_RESULT: AtomicUsize = AtomicUsize::new(0);
+const UART1_INIT_FAILED: usize = 1 << (usize::BITS - 1);
+
+/// 最近一次打开UART1的结果，成功和失败时都是尝试的次数；还没有打开过时返回None
+pub fn uart1_init_result() -> Option<Result<usize, usize>> {
+    match UART1_INIT_RESULT.load(Ordering::Relaxed) {
+        0 => None,
+        recorded if recorded & UART1_INIT_FAILED != 0 => Some(Err(recorded & !UART1_INIT_FAILED)),
+        attempts => Some(Ok(attempts)),
+    }
+}
+
+// 两次尝试之间等待的循环次数
+const UART_INIT_RETRY_DELAY: usize = 10_000;
+// 重新打开发送以后，等待发送队列出现空位的循环次数
+const UART_RECOVERY_SPINS: usize = 1_000_000;
+
 // Ref: fu740-hal
 
 impl Read<u8> for Uart {
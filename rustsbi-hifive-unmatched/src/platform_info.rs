@@ -0,0 +1,113 @@
+// 给通用工具使用的平台信息，作用类似SMBIOS/DMI，但只有几个字段
+//
+// 结构放在固件自己的区域中，S层通过专有SBI调用得到它的物理地址。
+// 所有字段都是小端序，字符串以0结尾，多余的字节填0：
+//
+// | 偏移 | 长度 | 字段 |
+// |:---|:---|:---|
+// | 0x00 | 8 | 签名，固定为"RSBIINFO" |
+// | 0x08 | 4 | 结构版本，目前为1 |
+// | 0x0C | 4 | 结构的总长度 |
+// | 0x10 | 4 | 进入S层的核的个数 |
+// | 0x14 | 4 | 进入S层的核，按核编号置位 |
+// | 0x18 | 32 | 板子的名称，来自设备树根节点的model属性 |
+// | 0x38 | 16 | 固件版本 |
+// | 0x48 | 16 | RustSBI版本 |
+use core::fmt;
+use core::mem::size_of;
+
+const SIGNATURE: [u8; 8] = *b"RSBIINFO";
+const VERSION: u32 = 1;
+// 设备树中没有model属性时使用的名称
+const DEFAULT_BOARD_NAME: &str = "HiFive Unmatched";
+
+#[repr(C)]
+pub struct PlatformInfo {
+    signature: [u8; 8],
+    version: u32,
+    length: u32,
+    hart_count: u32,
+    hart_mask: u32,
+    board_name: [u8; 32],
+    firmware_version: [u8; 16],
+    rustsbi_version: [u8; 16],
+}
+
+static mut PLATFORM_INFO: PlatformInfo = PlatformInfo {
+    signature: [0; 8],
+    version: 0,
+    length: 0,
+    hart_count: 0,
+    hart_mask: 0,
+    board_name: [0; 32],
+    firmware_version: [0; 16],
+    rustsbi_version: [0; 16],
+};
+
+// 由启动核在进入S层之前填写；签名最后写入，S层看到签名时其余字段都已经填好
+pub fn init(board_name: Option<&str>, hart_mask: usize) {
+    let info = unsafe { &mut PLATFORM_INFO };
+    info.version = VERSION;
+    info.length = size_of::<PlatformInfo>() as u32;
+    info.hart_count = hart_mask.count_ones();
+    info.hart_mask = hart_mask as u32;
+    copy_str(
+        &mut info.board_name,
+        board_name.unwrap_or(DEFAULT_BOARD_NAME),
+    );
+    copy_str(&mut info.firmware_version, env!("CARGO_PKG_VERSION"));
+    copy_str(&mut info.rustsbi_version, rustsbi::VERSION);
+    info.signature = SIGNATURE;
+}
+
+/// 平台信息结构的物理地址
+pub fn address() -> usize {
+    unsafe { &PLATFORM_INFO as *const _ as usize }
+}
+
+const HART_SET_LINE_SIZE: usize = 256;
+
+// 启动时列出进入S层的核和被排除的核的那一行日志，以0结尾，S层可以读出来检查
+static mut HART_SET_LINE: [u8; HART_SET_LINE_SIZE] = [0; HART_SET_LINE_SIZE];
+
+/// 逐段写入启动日志中列出各个核的那一行，写入的内容同时保存下来；
+/// 只由启动核在进入S层之前使用
+pub struct HartSetLine {
+    len: usize,
+}
+
+impl HartSetLine {
+    pub fn new() -> Self {
+        unsafe { HART_SET_LINE.fill(0) };
+        HartSetLine { len: 0 }
+    }
+
+    /// 保存下来的内容
+    pub fn as_str(&self) -> &str {
+        let line = unsafe { &HART_SET_LINE[..self.len] };
+        core::str::from_utf8(line).unwrap_or("")
+    }
+}
+
+// 超过长度的部分被丢弃，保证至少留下一个0作为结尾；各段都是ASCII，不会截断在字符中间
+impl fmt::Write for HartSetLine {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let line = unsafe { &mut HART_SET_LINE };
+        let len = s.len().min(HART_SET_LINE_SIZE - 1 - self.len);
+        line[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
+/// 保存下来的那一行日志的物理地址
+pub fn hart_set_line_address() -> usize {
+    unsafe { HART_SET_LINE.as_ptr() as usize }
+}
+
+// 过长的字符串被截断，保证至少留下一个0作为结尾
+fn copy_str(dst: &mut [u8], src: &str) {
+    let len = src.len().min(dst.len() - 1);
+    dst[..len].copy_from_slice(&src.as_bytes()[..len]);
+    dst[len..].fill(0);
+}
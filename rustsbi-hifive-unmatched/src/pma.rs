@@ -0,0 +1,70 @@
+// 物理内存属性（PMA）
+//
+// FU740的PMA由硬件固定，内存总是可缓存的，不能由软件改为非缓存或IO区域。
+// S层通过专有SBI调用申请某段内存的属性，固件检查这段内存的范围，并如实报告能否满足
+use crate::payload;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+// 设备树中的内存范围；设备树不可用时为0，这时不接受任何申请
+static MEMORY_START: AtomicUsize = AtomicUsize::new(0);
+static MEMORY_END: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Attribute {
+    /// 普通的可缓存内存
+    Cacheable,
+    /// 不经过缓存的内存，一般用作DMA缓冲区
+    NonCacheable,
+}
+
+impl Attribute {
+    pub fn from_usize(value: usize) -> Option<Attribute> {
+        match value {
+            0 => Some(Attribute::Cacheable),
+            1 => Some(Attribute::NonCacheable),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum PmaError {
+    /// 没有从设备树中读到内存范围
+    NoMemoryMap,
+    /// 长度为0或者区域越界
+    InvalidRange,
+    /// 区域不在内存中，或者和固件自身的区域重叠
+    InvalidAddress,
+    /// 硬件不能提供这种属性
+    Unsupported,
+}
+
+pub fn init(memory: Option<(usize, usize)>) {
+    if let Some((start, end)) = memory {
+        MEMORY_START.store(start, Ordering::Relaxed);
+        MEMORY_END.store(end, Ordering::Release);
+    }
+}
+
+pub fn request(base: usize, size: usize, attribute: Attribute) -> Result<(), PmaError> {
+    let memory_end = MEMORY_END.load(Ordering::Acquire);
+    let memory_start = MEMORY_START.load(Ordering::Relaxed);
+    if memory_end == 0 {
+        return Err(PmaError::NoMemoryMap);
+    }
+    let end = match base.checked_add(size) {
+        Some(end) if size != 0 => end,
+        _ => return Err(PmaError::InvalidRange),
+    };
+    if base < memory_start || end > memory_end {
+        return Err(PmaError::InvalidAddress);
+    }
+    let (firmware_start, firmware_end) = payload::firmware_region();
+    if base < firmware_end && firmware_start < end {
+        return Err(PmaError::InvalidAddress);
+    }
+    match attribute {
+        Attribute::Cacheable => Ok(()),
+        Attribute::NonCacheable => Err(PmaError::Unsupported),
+    }
+}
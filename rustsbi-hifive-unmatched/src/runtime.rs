@@ -10,6 +10,7 @@ use riscv::register::{
     mtvec::{self, TrapMode},
 };
 
+#[cfg(not(feature = "vectored-trap"))]
 #[inline]
 pub fn init() {
     let mut addr = from_supervisor_save as usize;
@@ -19,15 +20,32 @@ pub fn init() {
     unsafe { mtvec::write(addr, TrapMode::Direct) };
 }
 
+#[cfg(feature = "vectored-trap")]
+#[inline]
+pub fn init() {
+    // 向量表的入口地址对齐到256个字节，见trap_vector_table
+    let addr = (trap_vector_table as usize + 0xFF) & !0xFF;
+    unsafe { mtvec::write(addr, TrapMode::Vectored) };
+}
+
 pub struct Runtime {
     context: SupervisorContext,
+    // 上一次陷入的种类，恢复上下文之前用来记录这次陷入的开销
+    #[cfg(feature = "trap-profile")]
+    last_trap: Option<usize>,
 }
 
 impl Runtime {
     pub fn new_sbi_supervisor(supervisor_mepc: usize, a0: usize, a1: usize) -> Self {
         let context: SupervisorContext = unsafe { core::mem::MaybeUninit::zeroed().assume_init() };
-        let mut ans = Runtime { context };
+        let mut ans = Runtime {
+            context,
+            #[cfg(feature = "trap-profile")]
+            last_trap: None,
+        };
         ans.prepare_supervisor(supervisor_mepc);
+        ans.context.hart_local =
+            crate::hart_local::get(riscv::register::mhartid::read()) as *const _ as usize;
         ans.context.a0 = a0;
         ans.context.a1 = a1;
         ans
@@ -54,6 +72,10 @@ impl Generator for Runtime {
     type Yield = MachineTrap;
     type Return = ();
     fn resume(mut self: Pin<&mut Self>, _arg: ()) -> GeneratorState<Self::Yield, Self::Return> {
+        #[cfg(feature = "trap-profile")]
+        if let Some(trap_kind) = self.last_trap.take() {
+            crate::trap_profile::record_exit(trap_kind, self.context.trap_cycle);
+        }
         unsafe { do_resume(&mut self.context as *mut _) };
         let mtval = mtval::read();
         let trap = match mcause::read().cause() {
@@ -61,11 +83,19 @@ impl Generator for Runtime {
             Trap::Exception(Exception::IllegalInstruction) => MachineTrap::IllegalInstruction(),
             Trap::Interrupt(Interrupt::MachineTimer) => MachineTrap::MachineTimer(),
             Trap::Interrupt(Interrupt::MachineSoft) => MachineTrap::MachineSoft(),
+            // 没有委托的异常发生在S层陷入处理程序的入口，说明stvec无效，交给execute模块停止这个核
+            _ if crate::feature::is_supervisor_trap_entry(&self.context) => {
+                MachineTrap::BrokenTrapHandler()
+            }
             e => panic!(
                 "unhandled exception: {:?}! mtval: {:x?}, ctx: {:x?}",
                 e, mtval, self.context
             ),
         };
+        #[cfg(feature = "trap-profile")]
+        {
+            self.last_trap = trap.kind();
+        }
         GeneratorState::Yielded(trap)
     }
 }
@@ -76,6 +106,21 @@ pub enum MachineTrap {
     IllegalInstruction(),
     MachineTimer(),
     MachineSoft(),
+    BrokenTrapHandler(),
+}
+
+#[cfg(feature = "trap-profile")]
+impl MachineTrap {
+    // 陷入种类的编号，见trap_profile::TRAP_KINDS；停止损坏的核不计入统计
+    fn kind(&self) -> Option<usize> {
+        match self {
+            MachineTrap::SbiCall() => Some(0),
+            MachineTrap::IllegalInstruction() => Some(1),
+            MachineTrap::MachineTimer() => Some(2),
+            MachineTrap::MachineSoft() => Some(3),
+            MachineTrap::BrokenTrapHandler() => None,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -115,6 +160,8 @@ pub struct SupervisorContext {
     pub mstatus: Mstatus,     // 31
     pub mepc: usize,          // 32
     pub machine_stack: usize, // 33
+    pub trap_cycle: usize,    // 34，打开trap-profile功能时，保存陷入时的mcycle
+    pub hart_local: usize,    // 35，当前核HartLocal的地址，M层时钟中断的快速路径使用
 }
 
 #[naked]
@@ -209,10 +256,14 @@ pub unsafe extern "C" fn from_supervisor_save() -> ! {
     asm!( // sp:特权级栈,mscratch:特权级上下文
         ".p2align 2",
         "csrrw  sp, mscratch, sp", // 新mscratch:特权级栈, 新sp:特权级上下文
+        "sd     t0, 4*8(sp)",
+        ".if {trap_profile}
+        csrr    t0, mcycle
+        sd      t0, 34*8(sp)
+        .endif", // 尽早读出进入时间，保存上下文的开销也计算在内
         "sd     ra, 0*8(sp)
         sd      gp, 2*8(sp)
         sd      tp, 3*8(sp)
-        sd      t0, 4*8(sp)
         sd      t1, 5*8(sp)
         sd      t2, 6*8(sp)
         sd      s0, 7*8(sp)
@@ -248,6 +299,7 @@ pub unsafe extern "C" fn from_supervisor_save() -> ! {
         "sd     t2, 1*8(sp)", // 保存特权级栈
         "j      {to_machine_restore}",
         to_machine_restore = sym to_machine_restore,
+        trap_profile = const cfg!(feature = "trap-profile") as usize,
         options(noreturn)
     )
 }
@@ -279,3 +331,92 @@ unsafe extern "C" fn to_machine_restore() -> ! {
         options(noreturn)
     )
 }
+
+// 向量模式的中断向量表。异常和没有快速处理函数的中断都进入常规的保存流程，交给生成器处理
+#[cfg(feature = "vectored-trap")]
+#[naked]
+#[link_section = ".text"]
+unsafe extern "C" fn trap_vector_table() -> ! {
+    asm!(
+        ".p2align 8",
+        ".option push
+        .option norvc", // 每个表项都必须是4个字节的j指令
+        "j      {from_supervisor_save}", // 0: 异常
+        "j      {from_supervisor_save}", // 1: S层软件中断
+        "j      {from_supervisor_save}",
+        "j      {from_supervisor_save}", // 3: M层软件中断
+        "j      {from_supervisor_save}",
+        "j      {from_supervisor_save}", // 5: S层时钟中断
+        "j      {from_supervisor_save}",
+        "j      {fast_machine_timer}", // 7: M层时钟中断
+        "j      {from_supervisor_save}",
+        "j      {from_supervisor_save}", // 9: S层外部中断
+        "j      {from_supervisor_save}",
+        "j      {from_supervisor_save}", // 11: M层外部中断
+        ".option pop",
+        from_supervisor_save = sym from_supervisor_save,
+        fast_machine_timer = sym fast_machine_timer,
+        options(noreturn)
+    )
+}
+
+// M层时钟中断的快速处理，只处理S层的时钟到期，和生成器的MachineTimer分支作用相同，
+// 但不需要保存全部上下文。比较寄存器和看门狗共用：看门狗先到期，或者S层的时间还没到
+// （比较寄存器刚被改写、等待位还没有清除），都交给生成器检查。
+// 看门狗打开时把比较寄存器改为看门狗的时间，而不是关闭M层时钟中断，否则看门狗就不会再到期。
+// 软件中断的优先级更高，能进入这里说明此时没有等待中的软件中断，不需要先转交IPI
+#[cfg(feature = "vectored-trap")]
+#[naked]
+#[link_section = ".text"]
+unsafe extern "C" fn fast_machine_timer() -> ! {
+    asm!(
+        // sp:特权级栈,mscratch:特权级上下文
+        ".p2align 2",
+        "csrrw  sp, mscratch, sp", // 新mscratch:特权级栈, 新sp:特权级上下文
+        // 借用上下文中t0~t3的位置，下次保存上下文时会被覆盖
+        "sd     t0, 4*8(sp)
+        sd      t1, 5*8(sp)
+        sd      t2, 6*8(sp)
+        sd      t3, 27*8(sp)",
+        "ld     t0, 35*8(sp)", // t0:当前核的HartLocal
+        "ld     t1, 0*8(t0)",  // t1:S层的时钟
+        "li     t2, {mtime}
+        ld      t2, 0(t2)", // t2:当前时间
+        "bltu   t2, t1, 1f", // S层的时间还没到
+        "ld     t2, 1*8(t0)", // t2:看门狗的超时长度
+        "beqz   t2, 2f",
+        "ld     t2, 2*8(t0)", // t2:看门狗的到期时间
+        "bgeu   t1, t2, 1f", // 看门狗不晚于S层的时钟，由生成器检查看门狗
+        "csrr   t3, mhartid
+        slli    t3, t3, 3
+        li      t1, {mtimecmp}
+        add     t3, t3, t1
+        sd      t2, 0(t3)", // 比较寄存器改为看门狗的到期时间
+        "j      3f",
+        "2:
+        li      t1, 1 << 7
+        csrc    mie, t1", // 没有打开看门狗，关闭M层时钟中断
+        "3:
+        li      t1, -1
+        sd      t1, 0*8(t0)", // S层的时钟已经转交，和生成器一样清除
+        "li     t1, 1 << 5
+        csrs    mip, t1", // 设置S层时钟中断
+        "ld     t0, 4*8(sp)
+        ld      t1, 5*8(sp)
+        ld      t2, 6*8(sp)
+        ld      t3, 27*8(sp)",
+        "csrrw  sp, mscratch, sp", // sp:特权级栈, mscratch:特权级上下文
+        "mret",
+        "1:
+        ld      t0, 4*8(sp)
+        ld      t1, 5*8(sp)
+        ld      t2, 6*8(sp)
+        ld      t3, 27*8(sp)",
+        "csrrw  sp, mscratch, sp", // sp:特权级栈, mscratch:特权级上下文
+        "j      {from_supervisor_save}",
+        mtime = const 0x200_bff8,
+        mtimecmp = const 0x200_4000,
+        from_supervisor_save = sym from_supervisor_save,
+        options(noreturn)
+    )
+}
@@ -0,0 +1,31 @@
+// 栈的高水位统计，只在打开stack-watermark功能时编译
+//
+// 入口代码在进入rust_main之前用STACK_FILL_PATTERN填满当前核的栈；
+// 之后从栈底向上找到第一个被改写过的字，就是这个核用过的最深的位置
+use crate::console::println;
+use crate::hart_local::MAX_HART_ID;
+use crate::{PER_HART_STACK_SIZE, SBI_STACK, STACK_FILL_PATTERN};
+
+/// 返回某个核用过的最大栈空间的字节数
+pub fn peak_usage(hart_id: usize) -> usize {
+    let stack_base = unsafe { &SBI_STACK } as *const _ as usize;
+    let bottom = (stack_base + hart_id * PER_HART_STACK_SIZE) as *const u32;
+    let words = PER_HART_STACK_SIZE / 4;
+    let untouched = (0..words)
+        .take_while(|&i| unsafe { bottom.add(i).read_volatile() } == STACK_FILL_PATTERN)
+        .count();
+    PER_HART_STACK_SIZE - untouched * 4
+}
+
+pub fn print_summary() {
+    for hart_id in 0..=MAX_HART_ID {
+        let peak = peak_usage(hart_id);
+        println!(
+            "[rustsbi] hart {} peak stack usage: {} of {} bytes, {} bytes never used",
+            hart_id,
+            peak,
+            PER_HART_STACK_SIZE,
+            PER_HART_STACK_SIZE - peak
+        );
+    }
+}
@@ -0,0 +1,84 @@
+// 交给S层的时间的速率缩放，只在打开time-scale功能时生效，用于测试和时间有关的代码
+//
+// S层看到的时间 = 切换时的S层时间 + (mtime - 切换时的mtime) * 分子 / 分母。
+// 切换缩放比例时记下当时的两个时间，S层的时间仍然连续而且不会倒退。
+// rdtime的模拟、PMU的time计数器和set_timer的比较值都经过这里换算；
+// 看门狗等固件自己的计时仍然使用mtime
+#[cfg(feature = "time-scale")]
+use crate::util::AmoMutex;
+
+#[cfg(feature = "time-scale")]
+struct Scale {
+    numerator: u64,
+    denominator: u64,
+    // 上一次切换比例时的mtime和S层时间
+    base_mtime: u64,
+    base_time: u64,
+}
+
+#[cfg(feature = "time-scale")]
+static SCALE: AmoMutex<Scale> = AmoMutex::new(Scale {
+    numerator: 1,
+    denominator: 1,
+    base_mtime: 0,
+    base_time: 0,
+});
+
+/// 设置新的缩放比例，分子和分母都不能为0；已经设置的时钟中断仍按原来的比例换算
+#[cfg(feature = "time-scale")]
+pub fn set(numerator: u64, denominator: u64) -> bool {
+    if numerator == 0 || denominator == 0 {
+        return false;
+    }
+    let mtime = crate::peripheral::Clint::new(0x2000000 as *mut u8).get_mtime();
+    let mut scale = SCALE.lock();
+    let time = scale.supervisor_time(mtime);
+    scale.base_mtime = mtime;
+    scale.base_time = time;
+    scale.numerator = numerator;
+    scale.denominator = denominator;
+    true
+}
+
+/// 把mtime换算成S层看到的时间
+#[cfg(feature = "time-scale")]
+pub fn supervisor_time(mtime: u64) -> u64 {
+    SCALE.lock().supervisor_time(mtime)
+}
+
+/// 把S层给出的时间换算成比较寄存器的值，向上取整，保证中断不会早于S层要求的时间
+#[cfg(feature = "time-scale")]
+pub fn machine_time(time: u64) -> u64 {
+    if time == u64::MAX {
+        // S层用最大值表示不需要时钟中断
+        return u64::MAX;
+    }
+    let scale = SCALE.lock();
+    if time <= scale.base_time {
+        return scale.base_mtime;
+    }
+    let delta = (time - scale.base_time) as u128 * scale.denominator as u128;
+    let delta = (delta + scale.numerator as u128 - 1) / scale.numerator as u128;
+    (scale.base_mtime as u128 + delta).min(u64::MAX as u128) as u64
+}
+
+#[cfg(feature = "time-scale")]
+impl Scale {
+    fn supervisor_time(&self, mtime: u64) -> u64 {
+        let delta = mtime.saturating_sub(self.base_mtime) as u128 * self.numerator as u128
+            / self.denominator as u128;
+        (self.base_time as u128 + delta).min(u64::MAX as u128) as u64
+    }
+}
+
+#[cfg(not(feature = "time-scale"))]
+#[inline]
+pub fn supervisor_time(mtime: u64) -> u64 {
+    mtime
+}
+
+#[cfg(not(feature = "time-scale"))]
+#[inline]
+pub fn machine_time(time: u64) -> u64 {
+    time
+}
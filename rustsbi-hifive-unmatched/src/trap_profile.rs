@@ -0,0 +1,74 @@
+// 陷入处理开销的统计，只在打开trap-profile功能时编译
+//
+// 进入时间在保存上下文时从mcycle读出，退出时间在恢复上下文之前读出，
+// 两者之差包括保存上下文和M层处理的时间。向量模式的快速路径不保存上下文，不会统计
+use crate::hart_local;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+// 按照MachineTrap的顺序：SBI调用、非法指令、M层时钟中断、M层软件中断
+pub const TRAP_KINDS: usize = 4;
+
+pub const FIELD_COUNT: usize = 0;
+pub const FIELD_MIN: usize = 1;
+pub const FIELD_MAX: usize = 2;
+pub const FIELD_AVERAGE: usize = 3;
+
+pub struct TrapStats {
+    count: AtomicU64,
+    total: AtomicU64,
+    min: AtomicU64,
+    max: AtomicU64,
+}
+
+impl TrapStats {
+    pub const fn new() -> Self {
+        TrapStats {
+            count: AtomicU64::new(0),
+            total: AtomicU64::new(0),
+            min: AtomicU64::new(u64::MAX),
+            max: AtomicU64::new(0),
+        }
+    }
+
+    // 每个核只记录自己的陷入，不需要更强的内存顺序
+    fn record(&self, cycles: u64) {
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.total.fetch_add(cycles, Ordering::Relaxed);
+        self.min.fetch_min(cycles, Ordering::Relaxed);
+        self.max.fetch_max(cycles, Ordering::Relaxed);
+    }
+
+    fn read(&self, field: usize) -> Option<u64> {
+        let count = self.count.load(Ordering::Relaxed);
+        match field {
+            FIELD_COUNT => Some(count),
+            FIELD_MIN if count == 0 => Some(0),
+            FIELD_MIN => Some(self.min.load(Ordering::Relaxed)),
+            FIELD_MAX => Some(self.max.load(Ordering::Relaxed)),
+            FIELD_AVERAGE if count == 0 => Some(0),
+            FIELD_AVERAGE => Some(self.total.load(Ordering::Relaxed) / count),
+            _ => None,
+        }
+    }
+}
+
+// 在恢复S层上下文之前调用，trap_cycle是这次陷入时保存的mcycle
+pub fn record_exit(trap_kind: usize, trap_cycle: usize) {
+    let cycles = riscv::register::mcycle::read().wrapping_sub(trap_cycle);
+    hart_local::current().trap_stats[trap_kind].record(cycles as u64);
+}
+
+/// 读出当前核某一类陷入的统计值
+pub fn read(trap_kind: usize, field: usize) -> Option<u64> {
+    hart_local::current().trap_stats.get(trap_kind)?.read(field)
+}
+
+/// 某个核各类陷入的次数，其它核也可以读取
+pub fn counts(hart_id: usize) -> [u64; TRAP_KINDS] {
+    let stats = &hart_local::get(hart_id).trap_stats;
+    let mut counts = [0; TRAP_KINDS];
+    for (count, stats) in counts.iter_mut().zip(stats.iter()) {
+        *count = stats.count.load(Ordering::Relaxed);
+    }
+    counts
+}
@@ -44,9 +44,51 @@ impl<T> AmoMutex<T> {
             data: unsafe { &mut *self.data.get() },
         }
     }
-    // pub unsafe fn force_unlock(&self) {
-    //     *self.lock.get() = 0
-    // }
+    /// Attempts to lock the mutex, giving up after `max_spins` failed attempts.
+    pub fn try_lock_for(&self, max_spins: usize) -> Option<AmoMutexGuard<T>> {
+        for _ in 0..max_spins {
+            let held: u32;
+            unsafe {
+                core::arch::asm!(
+                    "amoswap.w.aq {held}, {one}, ({lock})", // attempt to acquire lock
+                    lock = in(reg) self.lock.get(),
+                    held = out(reg) held,
+                    one = in(reg) 1,
+                    options(nostack)
+                );
+            }
+            if held == 0 {
+                return Some(AmoMutexGuard {
+                    lock: self.lock.get(),
+                    data: unsafe { &mut *self.data.get() },
+                });
+            }
+            core::hint::spin_loop();
+        }
+        None
+    }
+    /// Takes the lock regardless of whether another context holds it.
+    ///
+    /// # Safety
+    ///
+    /// The previous holder may still be using the data, and when its guard is
+    /// dropped it releases the lock out from under the new holder. Only call this
+    /// where losing mutual exclusion is preferable to deadlock, such as panic. The
+    /// returned guard releases the lock when dropped like any other, so a path
+    /// that goes on to run normal code afterwards finds it free again, even if
+    /// the previous holder's guard was left behind on a discarded stack.
+    pub unsafe fn force_lock(&self) -> AmoMutexGuard<T> {
+        core::arch::asm!(
+            "amoswap.w.aq x0, {one}, ({lock})", // mark as held without checking
+            lock = in(reg) self.lock.get(),
+            one = in(reg) 1,
+            options(nostack)
+        );
+        AmoMutexGuard {
+            lock: self.lock.get(),
+            data: &mut *self.data.get(),
+        }
+    }
 }
 
 unsafe impl<T: ?Sized + Send> Sync for AmoMutex<T> {}
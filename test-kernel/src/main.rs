@@ -8,16 +8,27 @@ mod mm;
 mod sbi;
 mod util;
 
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use riscv::register::{
-    scause::{self, Exception, Trap},
-    sepc,
+    satp,
+    scause::{self, Exception, Interrupt, Trap},
+    sepc, sie, sip, sscratch, sstatus,
     stvec::{self, TrapMode},
+    time,
 };
 
-pub extern "C" fn rust_main(hartid: usize, dtb_pa: usize) -> ! {
+pub extern "C" fn rust_main(hartid: usize, dtb_pa: usize, dtb_first: usize) -> ! {
+    // set_timer right after entering S mode must find the hart fully initialized
+    entry_set_timer_burst();
+    if WARM_REBOOTED.load(Ordering::SeqCst) {
+        after_warm_reboot(hartid, dtb_pa)
+    }
+    record_entry(hartid);
     if hartid == 0 {
         // initialization
         mm::init_heap();
+        BOOT_DTB.store(dtb_pa, Ordering::SeqCst);
+        BOOT_DTB_FIRST.store(dtb_first != 0, Ordering::SeqCst);
     }
     if hartid == 0 {
         println!(
@@ -25,14 +36,47 @@ pub extern "C" fn rust_main(hartid: usize, dtb_pa: usize) -> ! {
             hartid, dtb_pa
         );
         test_base_extension();
+        test_extension_usage();
+        test_boot_convention(dtb_pa);
+        test_rescue_entry(dtb_pa);
+        test_legacy_probe();
+        test_debug_console();
+        test_debug_console_limit(dtb_pa);
+        test_secondary_order();
+        test_entry_set_timer();
         test_sbi_ins_emulation();
+        test_read_supervisor_csr();
         unsafe { stvec::write(start_trap as usize, TrapMode::Direct) };
         println!(">> Test-kernel: Trigger illegal exception");
         unsafe { core::arch::asm!("csrw mcycle, x0") }; // mcycle cannot be written, this is always a 4-byte illegal instruction
+        test_hypervisor_extension();
+        test_timer_interrupt();
+        test_timer_dispatch_latency();
+        test_time_scale();
+        test_interrupt_priority(hartid);
+        test_deadman_timer();
+        test_memory_attribute();
+        test_trap_profile();
+        test_pending_interrupt_progress(hartid);
+        test_hsm_error_codes(hartid);
+        test_warm_reset();
+        test_stack_watermark(hartid);
+        test_heap_usage();
+        test_pmu_counter();
+        test_platform_info();
+        test_hart_exclusion(dtb_pa);
+        test_hart_set_line(dtb_pa);
+        test_init_fault();
+        test_build_config(hartid);
+        test_early_memory_test();
+        test_board_temperature();
+        test_stuck_uart();
+        test_console_mirror();
+        test_uart_init_retry(dtb_pa);
+        test_broken_trap_handler();
+        test_panic_halt();
     }
     if hartid == 0 {
-        let sbi_ret = sbi::hart_stop(3);
-        println!(">> Stop hart 3, return value {:?}", sbi_ret);
         for i in 0..4 {
             let sbi_ret = sbi::hart_get_status(i);
             println!(">> Hart {} state return value: {:?}", i, sbi_ret);
@@ -49,8 +93,13 @@ pub extern "C" fn rust_main(hartid: usize, dtb_pa: usize) -> ! {
         println!(">> Error for non-retentive suspend: {:?}", sbi_ret);
         loop {}
     } else {
-        // hartid == 3
-        loop {}
+        // hartid == 3, started again by hart 2
+        let sbi_ret = sbi::hart_stop();
+        println!(
+            "!! Test-kernel: SBI test FAILED due to hart stop returned {:?}",
+            sbi_ret
+        );
+        sbi::shutdown()
     }
     if hartid == 0 {
         println!(
@@ -58,13 +107,13 @@ pub extern "C" fn rust_main(hartid: usize, dtb_pa: usize) -> ! {
             hartid
         );
         let bv: usize = 0b10;
-        let sbi_ret = sbi::send_ipi(&bv as *const _ as usize, hartid); // wake hartid + 1
+        let sbi_ret = sbi::send_ipi(bv, hartid); // wake hartid + 1
         println!(">> Wake hart 1, sbi return value {:?}", sbi_ret);
         loop {} // wait for machine shutdown
     } else if hartid == 1 {
         // send software IPI to activate hart 2
         let bv: usize = 0b10;
-        let sbi_ret = sbi::send_ipi(&bv as *const _ as usize, hartid); // wake hartid + 1
+        let sbi_ret = sbi::send_ipi(bv, hartid); // wake hartid + 1
         println!(">> Wake hart 2, sbi return value {:?}", sbi_ret);
         loop {}
     } else {
@@ -86,11 +135,255 @@ extern "C" fn hart_2_resume(hart_id: usize, param: usize) {
     loop {} // wait for machine shutdown
 }
 
+const WARM_RESET_HART: usize = 2;
+const WARM_RESET_PARAM: usize = 0x2222_5555;
+static WARM_RESET_DONE: AtomicBool = AtomicBool::new(false);
+static WARM_RESET_CLEAN: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn hart_2_warm_restart(hart_id: usize, param: usize) {
+    // a clean restart has the interrupt enable and paging state cleared
+    let clean = hart_id == WARM_RESET_HART
+        && param == WARM_RESET_PARAM
+        && sie::read().bits() == 0
+        && !sstatus::read().sie()
+        && satp::read().bits() == 0;
+    WARM_RESET_CLEAN.store(clean, Ordering::SeqCst);
+    WARM_RESET_DONE.store(true, Ordering::SeqCst);
+    // continue the hart 2 part of the test flow
+    let sbi_ret = sbi::hart_suspend(0x80000000, hart_2_resume as usize, 0x4567890a);
+    println!(">> Error for non-retentive suspend: {:?}", sbi_ret);
+    loop {
+        core::hint::spin_loop()
+    }
+}
+
 extern "C" fn hart_3_start(hart_id: usize, param: usize) {
     println!(
         "<< The parameter passed to hart {} start is: {:#x}",
         hart_id, param
     );
+    test_device_tree_handoff(hart_id)
+}
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const HANDOFF_DTB_SIZE: usize = 64 * 1024;
+
+#[repr(C, align(8))]
+struct DtbBuffer([u8; HANDOFF_DTB_SIZE]);
+
+static mut HANDOFF_DTB: DtbBuffer = DtbBuffer([0; HANDOFF_DTB_SIZE]);
+static BOOT_DTB: AtomicUsize = AtomicUsize::new(0);
+// whether entry found the device tree in a0 and the hart id in a1
+static BOOT_DTB_FIRST: AtomicBool = AtomicBool::new(false);
+// statics are not reloaded on warm reboot, these tell the second entry apart
+static WARM_REBOOTED: AtomicBool = AtomicBool::new(false);
+static WARM_REBOOT_HART: AtomicUsize = AtomicUsize::new(0);
+static HANDOFF_DTB_LEN: AtomicUsize = AtomicUsize::new(0);
+
+fn fdt_header_field(dtb_pa: usize, index: usize) -> u32 {
+    u32::from_be(unsafe { core::ptr::read_volatile((dtb_pa as *const u32).add(index)) })
+}
+
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+
+// Looks up a property directly under /chosen
+fn chosen_property(dtb_pa: usize, name: &str) -> Option<&'static [u8]> {
+    node_property(dtb_pa, &[b"chosen"], name)
+}
+
+// Looks up a property directly under the node at path, given from the root as
+// full node names with unit addresses; properties always come before subnodes,
+// so leaving the node or entering any of its subnodes ends the search
+fn node_property(dtb_pa: usize, path: &[&[u8]], name: &str) -> Option<&'static [u8]> {
+    let total_size = fdt_header_field(dtb_pa, 1) as usize;
+    let off_struct = fdt_header_field(dtb_pa, 2) as usize;
+    let off_strings = fdt_header_field(dtb_pa, 3) as usize;
+    let blob = unsafe { core::slice::from_raw_parts(dtb_pa as *const u8, total_size) };
+    let word = |offset: usize| -> Option<u32> {
+        Some(u32::from_be_bytes(
+            blob.get(offset..offset + 4)?.try_into().ok()?,
+        ))
+    };
+    let cstr = |offset: usize| -> Option<&'static [u8]> {
+        let rest = blob.get(offset..)?;
+        Some(&rest[..rest.iter().position(|&b| b == 0)?])
+    };
+    // nodes open from the root, and how many of them match the start of path
+    let (mut offset, mut depth, mut matched) = (off_struct, 0, 0);
+    loop {
+        let token = word(offset)?;
+        offset += 4;
+        let in_node = matched == path.len() && depth == path.len() + 1;
+        match token {
+            FDT_BEGIN_NODE => {
+                if in_node {
+                    return None;
+                }
+                let node_name = cstr(offset)?;
+                if depth >= 1 && matched == depth - 1 && node_name == path[matched] {
+                    matched += 1;
+                }
+                depth += 1;
+                offset = (offset + node_name.len() + 1 + 3) & !3;
+            }
+            FDT_END_NODE => {
+                if in_node || depth <= 1 {
+                    return None;
+                }
+                depth -= 1;
+                matched = matched.min(depth - 1);
+            }
+            FDT_PROP => {
+                let len = word(offset)? as usize;
+                let name_offset = word(offset + 4)? as usize;
+                offset += 8;
+                if in_node && cstr(off_strings + name_offset)? == name.as_bytes() {
+                    return blob.get(offset..offset + len);
+                }
+                offset = (offset + len + 3) & !3;
+            }
+            FDT_NOP => {}
+            _ => return None,
+        }
+    }
+}
+
+// the last test: hand a device tree back to the firmware and warm reboot with it
+fn test_device_tree_handoff(hartid: usize) -> ! {
+    println!(">> Test-kernel: Testing device tree handoff on warm reboot");
+    let boot_dtb = BOOT_DTB.load(Ordering::SeqCst);
+    let total_size = fdt_header_field(boot_dtb, 1) as usize;
+    let buffer = unsafe { &mut HANDOFF_DTB.0 };
+    if total_size > HANDOFF_DTB_SIZE {
+        println!("<< Test-kernel: Boot device tree too large to copy, skipped");
+        println!("<< Test-kernel: All hart SBI test SUCCESS, shutdown");
+        sbi::shutdown()
+    }
+    // a blob without the magic is refused
+    let sbi_ret = sbi::submit_device_tree(buffer.as_ptr() as usize, total_size);
+    if sbi_ret.error == sbi::SBI_ERR_NOT_SUPPORTED {
+        println!("<< Test-kernel: Device tree handoff not supported, skipped");
+        println!("<< Test-kernel: All hart SBI test SUCCESS, shutdown");
+        sbi::shutdown()
+    }
+    if sbi_ret.error != sbi::SBI_ERR_INVALID_PARAM {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to invalid device tree returned {:?}",
+            sbi_ret
+        );
+        sbi::shutdown()
+    }
+    let boot_blob = unsafe { core::slice::from_raw_parts(boot_dtb as *const u8, total_size) };
+    buffer[..total_size].copy_from_slice(boot_blob);
+    let sbi_ret = sbi::submit_device_tree(buffer.as_ptr() as usize, total_size);
+    if sbi_ret.error != sbi::SBI_SUCCESS {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to device tree submission returned {:?}",
+            sbi_ret
+        );
+        sbi::shutdown()
+    }
+    HANDOFF_DTB_LEN.store(total_size, Ordering::SeqCst);
+    WARM_REBOOT_HART.store(hartid, Ordering::SeqCst);
+    WARM_REBOOTED.store(true, Ordering::SeqCst);
+    let sbi_ret = sbi::reset(sbi::RESET_TYPE_WARM_REBOOT, sbi::RESET_REASON_NO_REASON);
+    println!(
+        "!! Test-kernel: SBI test FAILED due to warm reboot returned {:?}",
+        sbi_ret
+    );
+    sbi::shutdown()
+}
+
+fn after_warm_reboot(hartid: usize, dtb_pa: usize) -> ! {
+    if hartid != WARM_REBOOT_HART.load(Ordering::SeqCst) {
+        // wait for machine shutdown
+        loop {
+            core::hint::spin_loop()
+        }
+    }
+    if PANIC_REBOOTED.load(Ordering::SeqCst) {
+        after_panic_reboot(hartid)
+    }
+    println!(
+        "<< Test-kernel: Hart {} entered again after warm reboot, DTB physical address = {:#x}",
+        hartid, dtb_pa
+    );
+    let handoff_len = HANDOFF_DTB_LEN.load(Ordering::SeqCst);
+    // the firmware hands over its own copy, not the buffer submitted
+    if dtb_pa == BOOT_DTB.load(Ordering::SeqCst)
+        || dtb_pa == unsafe { HANDOFF_DTB.0.as_ptr() as usize }
+        || fdt_header_field(dtb_pa, 0) != FDT_MAGIC
+        || fdt_header_field(dtb_pa, 1) as usize != handoff_len
+    {
+        println!("!! Test-kernel: SBI test FAILED due to submitted device tree not handed over");
+        sbi::shutdown()
+    }
+    println!("<< Test-kernel: Submitted device tree handed over on warm reboot");
+    test_panic_reboot(hartid)
+}
+
+static PANIC_REBOOTED: AtomicBool = AtomicBool::new(false);
+static PANIC_REBOOT_TIME: AtomicUsize = AtomicUsize::new(0);
+
+// panic on the hart that came back from the warm reboot; the firmware waits for
+// the configured delay, then reboots every hart including the one that panicked
+fn test_panic_reboot(hartid: usize) -> ! {
+    println!(
+        ">> Test-kernel: Testing panic action reboot on hart {}",
+        hartid
+    );
+    PANIC_REBOOT_TIME.store(time::read(), Ordering::SeqCst);
+    PANIC_REBOOTED.store(true, Ordering::SeqCst);
+    let sbi_ret = sbi::inject_panic(sbi::PANIC_ACTION_REBOOT);
+    if sbi_ret.error == sbi::SBI_ERR_NOT_SUPPORTED {
+        println!("<< Test-kernel: Panic injection not supported, skipped");
+        println!("<< Test-kernel: All hart SBI test SUCCESS, shutdown");
+        sbi::shutdown()
+    }
+    println!(
+        "!! Test-kernel: SBI test FAILED due to injected panic returned {:?}",
+        sbi_ret
+    );
+    sbi::shutdown()
+}
+
+// must match the firmware's defaults and mtime frequency
+const PANIC_REBOOT_DEFAULT_DELAY: u64 = 5;
+const TICKS_PER_SECOND: u64 = 1_000_000;
+
+// the panicking hart comes back through the firmware's warm entry, which skips
+// the cold start, so apart from the configured delay the reboot must not take
+// longer than the firmware's own cold path to the supervisor did
+fn after_panic_reboot(hartid: usize) -> ! {
+    let elapsed = (time::read() - PANIC_REBOOT_TIME.load(Ordering::SeqCst)) as u64;
+    let delay_seconds = match chosen_property(
+        BOOT_DTB.load(Ordering::SeqCst),
+        "rustsbi,panic-reboot-delay",
+    ) {
+        Some(cells) if cells.len() == 4 => u32::from_be_bytes(cells.try_into().unwrap()) as u64,
+        Some(cells) if cells.len() == 8 => u64::from_be_bytes(cells.try_into().unwrap()),
+        _ => PANIC_REBOOT_DEFAULT_DELAY,
+    };
+    let delay = delay_seconds * TICKS_PER_SECOND;
+    let cold = sbi::read_cold_boot_ticks();
+    println!(
+        "<< Test-kernel: Hart {} entered again {} ticks after panic, delay {} ticks, cold boot {} ticks",
+        hartid, elapsed, delay, cold.value
+    );
+    if elapsed < delay {
+        println!("!! Test-kernel: SBI test FAILED due to reboot before the panic reboot delay");
+        sbi::shutdown()
+    }
+    if cold.error != sbi::SBI_SUCCESS || elapsed - delay > cold.value as u64 {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to warm path slower than cold boot, read {:?}",
+            cold
+        );
+        sbi::shutdown()
+    }
     println!("<< Test-kernel: All hart SBI test SUCCESS, shutdown");
     sbi::shutdown()
 }
@@ -127,6 +420,327 @@ fn test_base_extension() {
     println!("<< Test-kernel: Device mimpid: {:x}", sbi::get_mimpid());
 }
 
+// By now this kernel has printed, armed timers on entry and probed the base
+// extension, and it never issues the legacy IPI, fence or shutdown calls
+fn test_extension_usage() {
+    println!(">> Test-kernel: Testing extension usage summary");
+    let sbi_ret = sbi::read_extension_usage();
+    if sbi_ret.error == sbi::SBI_ERR_NOT_SUPPORTED {
+        println!("<< Test-kernel: Extension usage not supported, skipped");
+        return;
+    }
+    let used = sbi_ret.value;
+    println!("<< Test-kernel: Extension usage bitmap {:#x}", used);
+    let expected_used = sbi::USAGE_LEGACY_SET_TIMER
+        | sbi::USAGE_LEGACY_CONSOLE_PUTCHAR
+        | sbi::USAGE_BASE
+        | sbi::USAGE_TIMER
+        | sbi::USAGE_UNMATCHED;
+    if used & expected_used != expected_used {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to calls missing from usage, expected {:#x}",
+            expected_used
+        );
+        sbi::shutdown()
+    }
+    let never_used_mask = (sbi::USAGE_LEGACY_SHUTDOWN << 1) - sbi::USAGE_LEGACY_CLEAR_IPI;
+    if used & never_used_mask != 0 {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to unused legacy calls marked used: {:#x}",
+            used & never_used_mask
+        );
+        sbi::shutdown()
+    }
+    println!("<< Test-kernel: Extension usage matches the calls made so far");
+}
+
+fn test_boot_convention(dtb_pa: usize) {
+    println!(">> Test-kernel: Testing boot register convention");
+    // The firmware follows rustsbi,boot-convention in /chosen, Linux by default
+    let expect_dtb_first = match chosen_property(dtb_pa, "rustsbi,boot-convention") {
+        None | Some(b"linux\0") => false,
+        Some(b"dtb-first\0") => true,
+        Some(other) => {
+            println!(
+                "!! Test-kernel: SBI test FAILED due to unknown boot convention {:?} in device tree",
+                other
+            );
+            sbi::shutdown()
+        }
+    };
+    let dtb_first = BOOT_DTB_FIRST.load(Ordering::SeqCst);
+    if fdt_header_field(dtb_pa, 0) != FDT_MAGIC || dtb_first != expect_dtb_first {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to entered with {} convention, device tree asks for {}",
+            if dtb_first { "dtb-first" } else { "linux" },
+            if expect_dtb_first { "dtb-first" } else { "linux" }
+        );
+        sbi::shutdown()
+    }
+    println!(
+        "<< Test-kernel: Entered with the {} boot convention",
+        if dtb_first { "dtb-first" } else { "linux" }
+    );
+}
+
+// `cargo xtask image test-kernel --rescue` packs this kernel with an odd FIT
+// entry, which is never fetchable, and its real entry as rustsbi,rescue-addr;
+// reaching here at all means the firmware took the rescue entry, its banner
+// "FALLBACK: booting rescue payload" is on the console
+fn test_rescue_entry(dtb_pa: usize) {
+    println!(">> Test-kernel: Testing rescue entry");
+    let rescue_addr = match chosen_property(dtb_pa, "rustsbi,rescue-addr") {
+        Some(cells) if cells.len() == 4 => u32::from_be_bytes(cells.try_into().unwrap()) as usize,
+        Some(cells) if cells.len() == 8 => u64::from_be_bytes(cells.try_into().unwrap()) as usize,
+        Some(cells) => {
+            println!(
+                "!! Test-kernel: SBI test FAILED due to rescue address of {} bytes in device tree",
+                cells.len()
+            );
+            sbi::shutdown()
+        }
+        None => {
+            println!("<< Test-kernel: No rescue entry in device tree, skipped");
+            return;
+        }
+    };
+    if rescue_addr != entry as usize {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to rescue entry {:#x}, kernel entry is {:#x}",
+            rescue_addr, entry as usize
+        );
+        sbi::shutdown()
+    }
+    println!(
+        "<< Test-kernel: Entered through the rescue entry {:#x}",
+        rescue_addr
+    );
+}
+
+// the firmware implements every legacy call except the three remote fences
+const LEGACY_IMPLEMENTED: [(usize, bool); 9] = [
+    (sbi::SBI_SET_TIMER, true),
+    (sbi::SBI_CONSOLE_PUTCHAR, true),
+    (sbi::SBI_CONSOLE_GETCHAR, true),
+    (sbi::SBI_CLEAR_IPI, true),
+    (sbi::SBI_SEND_IPI, true),
+    (sbi::SBI_REMOTE_FENCE_I, false),
+    (sbi::SBI_REMOTE_SFENCE_VMA, false),
+    (sbi::SBI_REMOTE_SFENCE_VMA_ASID, false),
+    (sbi::SBI_SHUTDOWN, true),
+];
+
+fn test_legacy_probe() {
+    println!(">> Test-kernel: Probing legacy extensions");
+    for (extension, implemented) in LEGACY_IMPLEMENTED {
+        let probed = sbi::probe_extension(extension);
+        if (probed != 0) != implemented {
+            println!(
+                "!! Test-kernel: SBI test FAILED due to legacy extension {:#x} probed as {}",
+                extension, probed
+            );
+            sbi::shutdown()
+        }
+    }
+    println!("<< Test-kernel: Legacy extensions probed as implemented");
+}
+
+const DBCN_TEST_MESSAGE: &[u8] = b"<< Test-kernel: Debug console write\n";
+
+fn test_debug_console() {
+    println!(">> Test-kernel: Testing debug console zero-length write");
+    if sbi::probe_extension(sbi::EXTENSION_DBCN) == 0 {
+        println!("<< Test-kernel: Debug console extension not supported, skipped");
+        return;
+    }
+    // A zero-length write succeeds without looking at the address, even a null one
+    for base in [0, DBCN_TEST_MESSAGE.as_ptr() as usize] {
+        let sbi_ret = sbi::console_write(0, base, 0);
+        if sbi_ret.error != sbi::SBI_SUCCESS || sbi_ret.value != 0 {
+            println!(
+                "!! Test-kernel: SBI test FAILED due to zero-length write at {:#x} returned {:?}",
+                base, sbi_ret
+            );
+            sbi::shutdown()
+        }
+    }
+    let sbi_ret = sbi::console_write(1, 0, 0);
+    if sbi_ret.error != sbi::SBI_ERR_INVALID_PARAM {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to write from a null address returned {:?}",
+            sbi_ret
+        );
+        sbi::shutdown()
+    }
+    let sbi_ret = sbi::console_write(
+        DBCN_TEST_MESSAGE.len(),
+        DBCN_TEST_MESSAGE.as_ptr() as usize,
+        0,
+    );
+    if sbi_ret.error != sbi::SBI_SUCCESS || sbi_ret.value != DBCN_TEST_MESSAGE.len() {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to debug console write returned {:?}",
+            sbi_ret
+        );
+        sbi::shutdown()
+    }
+    println!("<< Test-kernel: Zero-length write succeeded with zero bytes written");
+}
+
+// Must match the firmware's default when /chosen has no rustsbi,dbcn-max-transfer
+const DBCN_DEFAULT_MAX_TRANSFER: usize = 4096;
+const DBCN_LARGE_BUFFER_SIZE: usize = 2 * DBCN_DEFAULT_MAX_TRANSFER;
+static DBCN_LARGE_BUFFER: [u8; DBCN_LARGE_BUFFER_SIZE] = [b'.'; DBCN_LARGE_BUFFER_SIZE];
+
+fn test_debug_console_limit(dtb_pa: usize) {
+    println!(">> Test-kernel: Testing debug console transfer limit");
+    if sbi::probe_extension(sbi::EXTENSION_DBCN) == 0 {
+        println!("<< Test-kernel: Debug console extension not supported, skipped");
+        return;
+    }
+    let max_transfer = match chosen_property(dtb_pa, "rustsbi,dbcn-max-transfer") {
+        Some(cells) if cells.len() == 4 => u32::from_be_bytes(cells.try_into().unwrap()) as usize,
+        Some(cells) if cells.len() == 8 => u64::from_be_bytes(cells.try_into().unwrap()) as usize,
+        _ => DBCN_DEFAULT_MAX_TRANSFER,
+    };
+    if max_transfer >= DBCN_LARGE_BUFFER_SIZE {
+        println!(
+            "<< Test-kernel: Transfer limit {} not below the test buffer, skipped",
+            max_transfer
+        );
+        return;
+    }
+    // Ask for more than the limit; only the first max_transfer dots come out
+    let sbi_ret = sbi::console_write(
+        DBCN_LARGE_BUFFER_SIZE,
+        DBCN_LARGE_BUFFER.as_ptr() as usize,
+        0,
+    );
+    println!("");
+    if sbi_ret.error != sbi::SBI_SUCCESS || sbi_ret.value != max_transfer {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to oversized write returned {:?}, limit {}",
+            sbi_ret, max_transfer
+        );
+        sbi::shutdown()
+    }
+    println!(
+        "<< Test-kernel: Oversized write processed {} of {} bytes",
+        sbi_ret.value, DBCN_LARGE_BUFFER_SIZE
+    );
+}
+
+// Order in which harts first reached rust_main; later HSM restarts are
+// recorded too but come only after the test below has looked
+const MAX_HARTS: usize = 5;
+static ENTRY_ORDER: [AtomicUsize; MAX_HARTS] = [
+    AtomicUsize::new(usize::MAX),
+    AtomicUsize::new(usize::MAX),
+    AtomicUsize::new(usize::MAX),
+    AtomicUsize::new(usize::MAX),
+    AtomicUsize::new(usize::MAX),
+];
+static ENTRY_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+fn record_entry(hartid: usize) {
+    let idx = ENTRY_COUNT.fetch_add(1, Ordering::SeqCst);
+    if idx < MAX_HARTS {
+        ENTRY_ORDER[idx].store(hartid, Ordering::SeqCst);
+    }
+}
+
+// Number of set_timer calls every hart makes first thing on entry; a hart
+// whose init has not finished yet gets SBI_ERR_FAILED back
+const ENTRY_SET_TIMER_BURST: usize = 64;
+static ENTRY_SET_TIMER_FAILURES: AtomicUsize = AtomicUsize::new(0);
+
+fn entry_set_timer_burst() {
+    for round in 0..ENTRY_SET_TIMER_BURST {
+        let deadline = if round % 2 == 0 {
+            time::read() + TIMER_TEST_TIMEOUT as usize
+        } else {
+            usize::MAX
+        };
+        if sbi::set_timer_checked(deadline).error != sbi::SBI_SUCCESS {
+            ENTRY_SET_TIMER_FAILURES.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+    sbi::set_timer(usize::MAX);
+}
+
+fn wait_for_entries(count: usize) {
+    let start = time::read() as u64;
+    while ENTRY_COUNT.load(Ordering::SeqCst) < count {
+        if time::read() as u64 - start > TIMER_TEST_TIMEOUT {
+            println!(
+                "!! Test-kernel: SBI test FAILED due to only {} of {} harts entered",
+                ENTRY_COUNT.load(Ordering::SeqCst),
+                count
+            );
+            sbi::shutdown()
+        }
+        core::hint::spin_loop();
+    }
+}
+
+fn test_entry_set_timer() {
+    println!(">> Test-kernel: Testing set_timer bursts at hart entry");
+    // Excluded harts never reach rust_main
+    let count = (0..MAX_HARTS)
+        .filter(|&hartid| !hart_excluded(hartid))
+        .count();
+    wait_for_entries(count);
+    let failures = ENTRY_SET_TIMER_FAILURES.load(Ordering::SeqCst);
+    if failures != 0 {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to {} of {} entry set_timer calls rejected",
+            failures,
+            count * ENTRY_SET_TIMER_BURST
+        );
+        sbi::shutdown()
+    }
+    println!(
+        "<< Test-kernel: {} harts each made {} set_timer calls at entry without error",
+        count, ENTRY_SET_TIMER_BURST
+    );
+}
+
+fn test_secondary_order() {
+    println!(">> Test-kernel: Testing secondary hart entry order");
+    let features = sbi::read_build_config(sbi::BUILD_CONFIG_FEATURES);
+    let ordered_bit = sbi::BUILD_FEATURE_NAMES
+        .iter()
+        .position(|feature| *feature == "ordered-harts")
+        .unwrap();
+    if features.error != sbi::SBI_SUCCESS || features.value & (1 << ordered_bit) == 0 {
+        println!("<< Test-kernel: Ordered hart bring-up not enabled, skipped");
+        return;
+    }
+    let info = sbi::get_platform_info();
+    if info.error != sbi::SBI_SUCCESS {
+        println!("<< Test-kernel: Platform info not supported, skipped");
+        return;
+    }
+    let hart_count =
+        unsafe { core::ptr::read_volatile((info.value + 0x10) as *const u32) } as usize;
+    let count = hart_count.min(MAX_HARTS);
+    wait_for_entries(count);
+    let mut order = [usize::MAX; MAX_HARTS];
+    for (slot, entry) in order.iter_mut().zip(ENTRY_ORDER.iter()).take(count) {
+        *slot = entry.load(Ordering::SeqCst);
+    }
+    let order = &order[..count];
+    println!("<< Test-kernel: Harts entered in order {:?}", order);
+    // The boot hart brings up every secondary before entering itself, so
+    // everything before the last entry must be in ascending hart-id order
+    let secondaries = &order[..count.saturating_sub(1)];
+    if secondaries.windows(2).any(|pair| pair[0] >= pair[1]) {
+        println!("!! Test-kernel: SBI test FAILED due to secondaries entered out of order");
+        sbi::shutdown()
+    }
+    println!("<< Test-kernel: Secondary harts entered in ascending hart-id order");
+}
+
 fn test_sbi_ins_emulation() {
     println!(">> Test-kernel: Testing SBI instruction emulation");
     let time_start = riscv::register::time::read64();
@@ -140,14 +754,1605 @@ fn test_sbi_ins_emulation() {
     }
 }
 
-pub extern "C" fn rust_trap_exception() {
-    let cause = scause::read().cause();
+fn test_read_supervisor_csr() {
+    println!(">> Test-kernel: Testing supervisor CSR read vendor call");
+    let value = 0x5a5a_1234;
+    sscratch::write(value);
+    let sbi_ret = sbi::read_supervisor_csr(0x140); // sscratch
+    if sbi_ret.error != sbi::SBI_SUCCESS || sbi_ret.value != value {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to sscratch read back as {:?}",
+            sbi_ret
+        );
+        sbi::shutdown()
+    }
+    let sbi_ret = sbi::read_supervisor_csr(0x300); // mstatus, not whitelisted
+    if sbi_ret.error != sbi::SBI_ERR_INVALID_PARAM {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to mstatus read returned {:?}",
+            sbi_ret
+        );
+        sbi::shutdown()
+    }
+    println!("<< Test-kernel: Supervisor CSR read back: {:#x}", value);
+}
+
+fn test_hypervisor_extension() {
+    println!(">> Test-kernel: Testing hypervisor extension absence");
+    let sbi_ret = sbi::remote_hfence_gvma(0, usize::MAX, 0, 0);
+    if sbi_ret.error != sbi::SBI_ERR_NOT_SUPPORTED {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to remote hfence returned {:?}",
+            sbi_ret
+        );
+        sbi::shutdown()
+    }
+    println!("<< Test-kernel: Remote hfence not supported");
+    let trap_count = ILLEGAL_TRAP_COUNT.load(Ordering::SeqCst);
+    unsafe { core::arch::asm!("csrr {}, 0x600", out(reg) _) }; // hstatus, only exists with H extension
+    if ILLEGAL_TRAP_COUNT.load(Ordering::SeqCst) == trap_count {
+        println!("!! Test-kernel: SBI test FAILED due to hstatus being readable");
+        sbi::shutdown()
+    }
+    println!("<< Test-kernel: Hypervisor extension reported absent");
+}
+
+const TIMER_TEST_TICKS: u64 = 10_000; // 10ms at the 1MHz timebase
+const TIMER_TEST_TIMEOUT: u64 = 1_000_000;
+
+fn test_timer_interrupt() {
+    println!(">> Test-kernel: Testing timer interrupt");
+    let time_start = time::read64();
+    sbi::set_timer((time_start + TIMER_TEST_TICKS) as usize);
+    unsafe {
+        sie::set_stimer();
+        sstatus::set_sie();
+    }
+    while !TIMER_FIRED.load(Ordering::SeqCst) {
+        if time::read64() > time_start + TIMER_TEST_TIMEOUT {
+            println!("!! Test-kernel: SBI test FAILED due to timer interrupt not received");
+            sbi::shutdown()
+        }
+    }
+    unsafe {
+        sstatus::clear_sie();
+        sie::clear_stimer();
+    }
+    let elapsed = time::read64() - time_start;
+    if elapsed < TIMER_TEST_TICKS {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to timer interrupt after only {} ticks",
+            elapsed
+        );
+        sbi::shutdown()
+    }
+    println!(
+        "<< Test-kernel: Timer interrupt received after {} ticks",
+        elapsed
+    );
+}
+
+// firmware PMU counter 0 is mcycle, which the time scale does not touch
+const PMU_COUNTER_CYCLE: usize = 0;
+
+fn read_cycle() -> u64 {
+    sbi::pmu_counter_read(PMU_COUNTER_CYCLE).value as u64
+}
+
+const DISPATCH_SAMPLES: usize = 16;
+const DISPATCH_DEADLINE_TICKS: u64 = 100;
+static TIMER_CYCLE: AtomicUsize = AtomicUsize::new(0);
+
+// Not a pass/fail test: prints how long a machine timer interrupt takes to
+// reach the supervisor handler, to compare firmware built with and without
+// vectored-trap. The loop reads mcycle back to back, so the interrupt lands
+// between two reads; the handler reads it again first thing. The figure
+// includes one PMU read, whose cost the back-to-back gap shows.
+fn test_timer_dispatch_latency() {
+    println!(">> Test-kernel: Measuring timer interrupt dispatch latency");
+    let mode = if build_feature_enabled("vectored-trap") {
+        "vectored"
+    } else {
+        "direct"
+    };
+    let (mut latency_min, mut latency_total, mut gap_min) = (u64::MAX, 0, u64::MAX);
+    unsafe {
+        sie::set_stimer();
+        sstatus::set_sie();
+    }
+    for _ in 0..DISPATCH_SAMPLES {
+        TIMER_FIRED.store(false, Ordering::SeqCst);
+        let time_start = time::read64();
+        sbi::set_timer((time_start + DISPATCH_DEADLINE_TICKS) as usize);
+        let mut last = read_cycle();
+        let latency = loop {
+            let now = read_cycle();
+            if TIMER_FIRED.load(Ordering::SeqCst) {
+                // the interrupt came either after this read or just before it
+                let handler = TIMER_CYCLE.load(Ordering::SeqCst) as u64;
+                break handler - if now < handler { now } else { last };
+            }
+            gap_min = gap_min.min(now - last);
+            last = now;
+            if time::read64() > time_start + TIMER_TEST_TIMEOUT {
+                println!("!! Test-kernel: SBI test FAILED due to timer interrupt not received");
+                sbi::shutdown()
+            }
+        };
+        latency_min = latency_min.min(latency);
+        latency_total += latency;
+    }
+    unsafe {
+        sstatus::clear_sie();
+        sie::clear_stimer();
+    }
+    println!(
+        "<< Test-kernel: Timer dispatch ({} mtvec): min {} cycles, average {} cycles, back-to-back mcycle read {} cycles",
+        mode,
+        latency_min,
+        latency_total / DISPATCH_SAMPLES as u64,
+        gap_min
+    );
+}
+
+// cycles spent while the supervisor time advances by ticks
+fn cycles_for_ticks(ticks: u64) -> u64 {
+    let time_start = time::read64();
+    let cycle_start = read_cycle();
+    while time::read64() < time_start + ticks {}
+    read_cycle() - cycle_start
+}
+
+// at 2x the supervisor time advances twice as fast against mcycle, and a timer
+// set the same number of ticks ahead fires in about half the cycles
+fn test_time_scale() {
+    println!(">> Test-kernel: Testing supervisor time scale");
+    let sbi_ret = sbi::set_time_scale(1, 1);
+    if sbi_ret.error == sbi::SBI_ERR_NOT_SUPPORTED {
+        println!("<< Test-kernel: Time scale not enabled in this build, skipped");
+        return;
+    }
+    let sbi_ret = sbi::set_time_scale(0, 1);
+    if sbi_ret.error != sbi::SBI_ERR_INVALID_PARAM {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to zero time scale returned {:?}",
+            sbi_ret
+        );
+        sbi::shutdown()
+    }
+    let unscaled = cycles_for_ticks(TIMER_TEST_TICKS);
+    sbi::set_time_scale(2, 1);
+    let scaled = cycles_for_ticks(TIMER_TEST_TICKS);
+    TIMER_FIRED.store(false, Ordering::SeqCst);
+    let time_start = time::read64();
+    let cycle_start = read_cycle();
+    sbi::set_timer((time_start + TIMER_TEST_TICKS) as usize);
+    unsafe {
+        sie::set_stimer();
+        sstatus::set_sie();
+    }
+    while !TIMER_FIRED.load(Ordering::SeqCst) {
+        if time::read64() > time_start + 2 * TIMER_TEST_TIMEOUT {
+            println!("!! Test-kernel: SBI test FAILED due to scaled timer interrupt not received");
+            sbi::shutdown()
+        }
+    }
+    unsafe {
+        sstatus::clear_sie();
+        sie::clear_stimer();
+    }
+    let timer_cycles = read_cycle() - cycle_start;
+    let elapsed = time::read64() - time_start;
+    sbi::set_time_scale(1, 1);
+    // allow a quarter either way for the rdtime emulation overhead
+    let near_half = |cycles: u64| 3 * unscaled < 8 * cycles && 8 * cycles < 5 * unscaled;
+    if !near_half(scaled) || !near_half(timer_cycles) || elapsed < TIMER_TEST_TICKS {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to {} ticks taking {} cycles at 2x, timer after {} cycles and {} ticks, {} cycles at 1x",
+            TIMER_TEST_TICKS, scaled, timer_cycles, elapsed, unscaled
+        );
+        sbi::shutdown()
+    }
+    println!(
+        "<< Test-kernel: {} ticks took {} cycles at 1x, {} cycles at 2x",
+        TIMER_TEST_TICKS, unscaled, scaled
+    );
+}
+
+const INTERRUPT_SOFT: usize = 1;
+const INTERRUPT_TIMER: usize = 2;
+
+fn test_interrupt_priority(hartid: usize) {
+    println!(">> Test-kernel: Testing IPI and timer interrupt priority");
+    INTERRUPT_COUNT.store(0, Ordering::SeqCst);
+    // make both interrupts pending while supervisor interrupts are disabled
+    sbi::set_timer(time::read64() as usize);
+    sbi::send_ipi(1 << hartid, 0);
+    let time_start = time::read64();
+    loop {
+        let sip = sip::read();
+        if sip.ssoft() && sip.stimer() {
+            break;
+        }
+        if time::read64() > time_start + TIMER_TEST_TIMEOUT {
+            println!("!! Test-kernel: SBI test FAILED due to IPI and timer not both pending");
+            sbi::shutdown()
+        }
+    }
+    unsafe {
+        sie::set_ssoft();
+        sie::set_stimer();
+        sstatus::set_sie();
+    }
+    while INTERRUPT_COUNT.load(Ordering::SeqCst) < 2 {}
+    unsafe {
+        sstatus::clear_sie();
+        sie::clear_stimer();
+        sie::clear_ssoft();
+    }
+    let first = INTERRUPT_ORDER[0].load(Ordering::SeqCst);
+    let second = INTERRUPT_ORDER[1].load(Ordering::SeqCst);
+    if first != INTERRUPT_SOFT || second != INTERRUPT_TIMER {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to interrupt order {}, {}",
+            first, second
+        );
+        sbi::shutdown()
+    }
+    println!("<< Test-kernel: IPI delivered before timer interrupt");
+}
+
+const DEADMAN_TEST_TIMEOUT: u64 = 50_000; // 50ms at the 1MHz timebase
+const DEADMAN_TEST_PETS: usize = 3;
+
+fn spin_ticks(ticks: u64) {
+    let time_start = time::read64();
+    while time::read64() < time_start + ticks {}
+}
+
+fn test_deadman_timer() {
+    println!(">> Test-kernel: Testing deadman timer");
+    let sbi_ret = sbi::deadman_enable(DEADMAN_TEST_TIMEOUT as usize, 0);
+    if sbi_ret.error != sbi::SBI_SUCCESS {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to deadman enable returned {:?}",
+            sbi_ret
+        );
+        sbi::shutdown()
+    }
+    for _ in 0..DEADMAN_TEST_PETS {
+        spin_ticks(DEADMAN_TEST_TIMEOUT / 5);
+        let sbi_ret = sbi::deadman_pet();
+        if sbi_ret.error != sbi::SBI_SUCCESS {
+            println!(
+                "!! Test-kernel: SBI test FAILED due to deadman fired while being petted, {:?}",
+                sbi_ret
+            );
+            sbi::shutdown()
+        }
+    }
+    // A supervisor timer due before the deadline shares the comparator with
+    // the watchdog; it must still arrive, and must leave the watchdog armed
+    sbi::deadman_pet();
+    TIMER_FIRED.store(false, Ordering::SeqCst);
+    let time_start = time::read64();
+    sbi::set_timer((time_start + TIMER_TEST_TICKS) as usize);
+    unsafe {
+        sie::set_stimer();
+        sstatus::set_sie();
+    }
+    while !TIMER_FIRED.load(Ordering::SeqCst) {
+        if time::read64() > time_start + DEADMAN_TEST_TIMEOUT {
+            println!("!! Test-kernel: SBI test FAILED due to timer lost while deadman armed");
+            sbi::shutdown()
+        }
+    }
+    unsafe {
+        sstatus::clear_sie();
+        sie::clear_stimer();
+    }
+    // stop petting, the firmware should log that the supervisor appears hung
+    spin_ticks(DEADMAN_TEST_TIMEOUT * 4);
+    let sbi_ret = sbi::deadman_pet();
+    if sbi_ret.error != sbi::SBI_ERR_DENIED {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to deadman not fired, pet returned {:?}",
+            sbi_ret
+        );
+        sbi::shutdown()
+    }
+    sbi::deadman_enable(0, 0);
+    println!("<< Test-kernel: Deadman timer fired after petting stopped");
+}
+
+const DMA_BUFFER_SIZE: usize = 4096;
+static mut DMA_BUFFER: [u8; DMA_BUFFER_SIZE] = [0; DMA_BUFFER_SIZE];
+
+fn test_memory_attribute() {
+    println!(">> Test-kernel: Testing memory attribute request");
+    let base = unsafe { DMA_BUFFER.as_ptr() as usize };
+    let sbi_ret =
+        sbi::request_memory_attribute(base, DMA_BUFFER_SIZE, sbi::MEMORY_ATTRIBUTE_CACHEABLE);
+    if sbi_ret.error == sbi::SBI_ERR_NOT_SUPPORTED {
+        println!("<< Test-kernel: Memory attribute request unavailable, skipped");
+        return;
+    }
+    if sbi_ret.error != sbi::SBI_SUCCESS {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to cacheable request returned {:?}",
+            sbi_ret
+        );
+        sbi::shutdown()
+    }
+    // FU740 PMAs are fixed by hardware, DRAM can never become non-cacheable
+    let sbi_ret =
+        sbi::request_memory_attribute(base, DMA_BUFFER_SIZE, sbi::MEMORY_ATTRIBUTE_NON_CACHEABLE);
+    if sbi_ret.error != sbi::SBI_ERR_NOT_SUPPORTED {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to non-cacheable request returned {:?}",
+            sbi_ret
+        );
+        sbi::shutdown()
+    }
+    let sbi_ret =
+        sbi::request_memory_attribute(0, DMA_BUFFER_SIZE, sbi::MEMORY_ATTRIBUTE_CACHEABLE);
+    if sbi_ret.error != sbi::SBI_ERR_INVALID_ADDRESS {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to region outside memory returned {:?}",
+            sbi_ret
+        );
+        sbi::shutdown()
+    }
+    let sbi_ret = sbi::request_memory_attribute(base, 0, sbi::MEMORY_ATTRIBUTE_CACHEABLE);
+    if sbi_ret.error != sbi::SBI_ERR_INVALID_PARAM {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to empty region returned {:?}",
+            sbi_ret
+        );
+        sbi::shutdown()
+    }
+    println!("<< Test-kernel: Memory attribute requests validated, DRAM stays cacheable");
+}
+
+const TRAP_PROFILE_TIMER_TRAPS: usize = 100;
+
+fn test_trap_profile() {
+    println!(">> Test-kernel: Testing trap profile");
+    let count_before =
+        sbi::read_trap_profile(sbi::TRAP_KIND_MACHINE_TIMER, sbi::TRAP_PROFILE_COUNT);
+    if count_before.error == sbi::SBI_ERR_NOT_SUPPORTED {
+        println!("<< Test-kernel: Trap profile not enabled in this build, skipped");
+        return;
+    }
+    // every already expired set_timer traps into machine timer interrupt once
+    for _ in 0..TRAP_PROFILE_TIMER_TRAPS {
+        sbi::set_timer(time::read64() as usize);
+        while !sip::read().stimer() {}
+    }
+    sbi::set_timer(usize::MAX);
+    let count = sbi::read_trap_profile(sbi::TRAP_KIND_MACHINE_TIMER, sbi::TRAP_PROFILE_COUNT);
+    let min = sbi::read_trap_profile(sbi::TRAP_KIND_MACHINE_TIMER, sbi::TRAP_PROFILE_MIN).value;
+    let max = sbi::read_trap_profile(sbi::TRAP_KIND_MACHINE_TIMER, sbi::TRAP_PROFILE_MAX).value;
+    let average =
+        sbi::read_trap_profile(sbi::TRAP_KIND_MACHINE_TIMER, sbi::TRAP_PROFILE_AVERAGE).value;
+    if count.value < count_before.value + TRAP_PROFILE_TIMER_TRAPS {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to only {} timer traps counted",
+            count.value - count_before.value
+        );
+        sbi::shutdown()
+    }
+    if min == 0 || min > average || average > max {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to implausible cycles, min {}, avg {}, max {}",
+            min, average, max
+        );
+        sbi::shutdown()
+    }
+    println!(
+        "<< Test-kernel: Timer trap cycles min {}, avg {}, max {}",
+        min, average, max
+    );
+}
+
+const PROGRESS_TEST_CALLS: usize = 1000;
+
+fn test_pending_interrupt_progress(hartid: usize) {
+    println!(">> Test-kernel: Testing forward progress with pending interrupts");
+    // leave an expired timer and an IPI pending while supervisor interrupts are disabled
+    sbi::set_timer(0);
+    sbi::send_ipi(1 << hartid, 0);
+    let time_start = time::read64();
+    for _ in 0..PROGRESS_TEST_CALLS {
+        sbi::get_spec_version();
+        if time::read64() > time_start + TIMER_TEST_TIMEOUT {
+            println!("!! Test-kernel: SBI test FAILED due to no forward progress with pending interrupts");
+            sbi::shutdown()
+        }
+    }
+    let sip = sip::read();
+    if !sip.stimer() || !sip.ssoft() {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to pending interrupts lost, stimer {}, ssoft {}",
+            sip.stimer(),
+            sip.ssoft()
+        );
+        sbi::shutdown()
+    }
+    sbi::set_timer(usize::MAX);
+    unsafe { core::arch::asm!("csrc sip, {}", in(reg) 1 << 1) }; // clear SSIP
+
+    // Reporting the deadman takes milliseconds on the UART, so a supervisor
+    // timer due one tick after the deadline has expired by the time the
+    // firmware rearms the comparator; it must be delivered, not masked
+    sbi::deadman_enable(DEADMAN_TEST_TIMEOUT as usize, 0);
+    let deadline = time::read64() + DEADMAN_TEST_TIMEOUT;
+    sbi::set_timer((deadline + 1) as usize);
+    while !sip::read().stimer() {
+        if time::read64() > deadline + TIMER_TEST_TIMEOUT {
+            println!("!! Test-kernel: SBI test FAILED due to timer masked after the deadman fired");
+            sbi::shutdown()
+        }
+    }
+    sbi::set_timer(usize::MAX);
+    sbi::deadman_enable(0, 0);
+    println!(
+        "<< Test-kernel: {} SBI calls completed with interrupts pending",
+        PROGRESS_TEST_CALLS
+    );
+}
+
+// stop on a stopped hart and suspend on a non-started hart cannot be tried,
+// both calls only ever act on the calling hart, which is started
+fn test_hsm_error_codes(hartid: usize) {
+    println!(">> Test-kernel: Testing HSM error codes for invalid transitions");
+    let cases = [
+        (
+            "start on started hart",
+            sbi::hart_start(hartid, entry as usize, 0),
+            sbi::SBI_ERR_ALREADY_AVAILABLE,
+        ),
+        (
+            "start on invalid hart",
+            sbi::hart_start(usize::MAX, entry as usize, 0),
+            sbi::SBI_ERR_INVALID_PARAM,
+        ),
+        (
+            "status of invalid hart",
+            sbi::hart_get_status(usize::MAX),
+            sbi::SBI_ERR_INVALID_PARAM,
+        ),
+        (
+            "suspend with reserved type",
+            sbi::hart_suspend(0x0000_0001, 0, 0),
+            sbi::SBI_ERR_INVALID_PARAM,
+        ),
+        (
+            "suspend with platform type",
+            sbi::hart_suspend(0x1000_0000, 0, 0),
+            sbi::SBI_ERR_NOT_SUPPORTED,
+        ),
+        (
+            "non-retentive suspend to invalid address",
+            sbi::hart_suspend(0x8000_0000, 0, 0),
+            sbi::SBI_ERR_INVALID_ADDRESS,
+        ),
+    ];
+    for (name, sbi_ret, expected) in cases {
+        if sbi_ret.error != expected {
+            println!(
+                "!! Test-kernel: SBI test FAILED due to {} returned {:?}",
+                name, sbi_ret
+            );
+            sbi::shutdown()
+        }
+    }
+    let sbi_ret = sbi::hart_get_status(hartid);
+    if sbi_ret.error != sbi::SBI_SUCCESS || sbi_ret.value != sbi::HART_STATUS_STARTED {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to own hart status {:?}",
+            sbi_ret
+        );
+        sbi::shutdown()
+    }
+    println!("<< Test-kernel: HSM invalid transitions returned spec error codes");
+}
+
+fn test_warm_reset() {
+    println!(
+        ">> Test-kernel: Testing warm reset of hart {}",
+        WARM_RESET_HART
+    );
+    let other_status_before = sbi::hart_get_status(1);
+    let sbi_ret = sbi::warm_reset_hart(
+        WARM_RESET_HART,
+        hart_2_warm_restart as usize,
+        WARM_RESET_PARAM,
+    );
+    if sbi_ret.error != sbi::SBI_SUCCESS {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to warm reset returned {:?}",
+            sbi_ret
+        );
+        sbi::shutdown()
+    }
+    let time_start = time::read64();
+    while !WARM_RESET_DONE.load(Ordering::SeqCst) {
+        if time::read64() > time_start + TIMER_TEST_TIMEOUT {
+            println!("!! Test-kernel: SBI test FAILED due to hart not restarted after warm reset");
+            sbi::shutdown()
+        }
+    }
+    if !WARM_RESET_CLEAN.load(Ordering::SeqCst) {
+        println!("!! Test-kernel: SBI test FAILED due to unclean state after warm reset");
+        sbi::shutdown()
+    }
+    let other_status_after = sbi::hart_get_status(1);
+    if other_status_after.value != other_status_before.value {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to hart 1 status changed from {:?} to {:?}",
+            other_status_before, other_status_after
+        );
+        sbi::shutdown()
+    }
+    println!(
+        "<< Test-kernel: Hart {} restarted cleanly, hart 1 unaffected",
+        WARM_RESET_HART
+    );
+}
+
+const STACK_RECURSION_DEPTH: usize = 64;
+// must match PER_HART_STACK_SIZE in the firmware
+const FIRMWARE_STACK_SIZE: usize = 16 * 1024;
+
+// every level keeps a frame alive and traps into the firmware, so the deepest
+// firmware call path is taken while the supervisor stack is deep as well
+#[inline(never)]
+fn recurse_with_sbi_calls(depth: usize) -> usize {
+    let frame = [depth; 8];
+    let ret = sbi::get_spec_version();
+    if depth == 0 {
+        return ret;
+    }
+    let sum = recurse_with_sbi_calls(depth - 1);
+    sum.wrapping_add(unsafe { core::ptr::read_volatile(&frame[depth % 8]) })
+}
+
+fn test_stack_watermark(hartid: usize) {
+    println!(">> Test-kernel: Testing firmware stack watermark");
+    let before = sbi::read_stack_watermark(hartid);
+    if before.error == sbi::SBI_ERR_NOT_SUPPORTED {
+        println!("<< Test-kernel: Stack watermark not enabled in this build, skipped");
+        return;
+    }
+    recurse_with_sbi_calls(STACK_RECURSION_DEPTH);
+    let after = sbi::read_stack_watermark(hartid);
+    if after.error != sbi::SBI_SUCCESS {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to reading stack watermark returned {:?}",
+            after
+        );
+        sbi::shutdown()
+    }
+    if after.value == 0 || after.value > FIRMWARE_STACK_SIZE || after.value < before.value {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to implausible stack watermark {} (was {})",
+            after.value, before.value
+        );
+        sbi::shutdown()
+    }
+    let invalid = sbi::read_stack_watermark(usize::MAX);
+    if invalid.error != sbi::SBI_ERR_INVALID_PARAM {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to invalid hart returned {:?}",
+            invalid
+        );
+        sbi::shutdown()
+    }
+    println!(
+        "<< Test-kernel: Firmware stack peak usage on hart {}: {} of {} bytes",
+        hartid, after.value, FIRMWARE_STACK_SIZE
+    );
+}
+
+// must match SBI_HEAP_SIZE plus the largest heap extension in the firmware
+const FIRMWARE_HEAP_MAX: usize = 64 * 1024 + 1024 * 1024;
+
+// the firmware allocates while it boots, so the peak is never zero; it only grows
+// while the supervisor keeps making calls, and the shutdown summary prints it.
+// The call fails once an allocation made before the heap extension has been
+// overwritten, so the extension must not overlap the boot heap
+fn test_heap_usage() {
+    println!(">> Test-kernel: Testing firmware heap peak usage");
+    let before = sbi::read_heap_usage();
+    if before.error == sbi::SBI_ERR_NOT_SUPPORTED {
+        println!("<< Test-kernel: Heap usage not supported, skipped");
+        return;
+    }
+    recurse_with_sbi_calls(STACK_RECURSION_DEPTH);
+    let after = sbi::read_heap_usage();
+    if before.error == sbi::SBI_ERR_FAILED || after.error == sbi::SBI_ERR_FAILED {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to boot heap allocation overwritten after heap extension"
+        );
+        sbi::shutdown()
+    }
+    if before.error != sbi::SBI_SUCCESS
+        || after.error != sbi::SBI_SUCCESS
+        || before.value == 0
+        || after.value < before.value
+        || after.value > FIRMWARE_HEAP_MAX
+    {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to implausible heap usage {:?} (was {:?})",
+            after, before
+        );
+        sbi::shutdown()
+    }
+    println!(
+        "<< Test-kernel: Firmware heap peak usage: {} bytes",
+        after.value
+    );
+}
+
+// U74 event class 0 with mask bit 9 set: integer load instruction retired
+const PMU_U74_INTEGER_LOAD_RETIRED: usize = 1 << 9;
+const PMU_TEST_LOADS: usize = 1000;
+
+fn test_pmu_counter() {
+    println!(">> Test-kernel: Testing PMU counter read");
+    let num_counters = sbi::pmu_num_counters();
+    if num_counters.error == sbi::SBI_ERR_NOT_SUPPORTED {
+        println!("<< Test-kernel: PMU extension not supported, skipped");
+        return;
+    }
+    let all_counters = (1 << num_counters.value) - 1;
+    let sbi_ret = sbi::pmu_counter_config_matching(
+        0,
+        all_counters,
+        sbi::PMU_CONFIG_FLAG_CLEAR_VALUE | sbi::PMU_CONFIG_FLAG_AUTO_START,
+        sbi::PMU_EVENT_RAW,
+        PMU_U74_INTEGER_LOAD_RETIRED,
+    );
+    if sbi_ret.error != sbi::SBI_SUCCESS {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to no counter for load events: {:?}",
+            sbi_ret
+        );
+        sbi::shutdown()
+    }
+    let counter = sbi_ret.value;
+    let data = [0usize; 4];
+    for i in 0..PMU_TEST_LOADS {
+        unsafe { core::ptr::read_volatile(&data[i % data.len()]) };
+    }
+    // a stopped and released counter keeps its value until configured again
+    let sbi_ret = sbi::pmu_counter_stop(counter, 1, sbi::PMU_STOP_FLAG_RESET);
+    if sbi_ret.error != sbi::SBI_SUCCESS {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to counter stop returned {:?}",
+            sbi_ret
+        );
+        sbi::shutdown()
+    }
+    let loads = sbi::pmu_counter_read(counter);
+    // the loop itself and the SBI calls add a few loads, but not many times more
+    if loads.error != sbi::SBI_SUCCESS
+        || loads.value < PMU_TEST_LOADS
+        || loads.value > PMU_TEST_LOADS * 16
+    {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to implausible load count {:?}",
+            loads
+        );
+        sbi::shutdown()
+    }
+    // without auto-start the counter is configured stopped, and only starts
+    // counting once counter_start is called
+    let sbi_ret = sbi::pmu_counter_config_matching(
+        0,
+        all_counters,
+        sbi::PMU_CONFIG_FLAG_CLEAR_VALUE,
+        sbi::PMU_EVENT_RAW,
+        PMU_U74_INTEGER_LOAD_RETIRED,
+    );
+    if sbi_ret.error != sbi::SBI_SUCCESS {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to no counter for load events without auto-start: {:?}",
+            sbi_ret
+        );
+        sbi::shutdown()
+    }
+    let stopped_counter = sbi_ret.value;
+    for i in 0..PMU_TEST_LOADS {
+        unsafe { core::ptr::read_volatile(&data[i % data.len()]) };
+    }
+    let stopped_loads = sbi::pmu_counter_read(stopped_counter);
+    let started = sbi::pmu_counter_start(stopped_counter, 1, 0);
+    sbi::pmu_counter_stop(stopped_counter, 1, sbi::PMU_STOP_FLAG_RESET);
+    if stopped_loads.error != sbi::SBI_SUCCESS
+        || stopped_loads.value != 0
+        || started.error != sbi::SBI_SUCCESS
+    {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to counter running without auto-start, read {:?}, start {:?}",
+            stopped_loads, started
+        );
+        sbi::shutdown()
+    }
+    let invalid = sbi::pmu_counter_read(num_counters.value);
+    if invalid.error != sbi::SBI_ERR_INVALID_PARAM {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to out of range counter read returned {:?}",
+            invalid
+        );
+        sbi::shutdown()
+    }
+    println!(
+        "<< Test-kernel: Counter {} counted {} loads for {} in the loop",
+        counter, loads.value, PMU_TEST_LOADS
+    );
+}
+
+// layout documented in the firmware's platform_info module
+const PLATFORM_INFO_SIGNATURE: &[u8; 8] = b"RSBIINFO";
+const PLATFORM_INFO_MIN_LENGTH: usize = 0x58;
+
+fn platform_info_str(bytes: &[u8]) -> &str {
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    core::str::from_utf8(&bytes[..len]).unwrap_or("<invalid>")
+}
+
+fn test_platform_info() {
+    println!(">> Test-kernel: Testing platform info structure");
+    let sbi_ret = sbi::get_platform_info();
+    if sbi_ret.error == sbi::SBI_ERR_NOT_SUPPORTED {
+        println!("<< Test-kernel: Platform info not supported, skipped");
+        return;
+    }
+    let base = sbi_ret.value as *const u8;
+    let header = unsafe { core::slice::from_raw_parts(base, 0x18) };
+    let field = |offset: usize| u32::from_le_bytes(header[offset..offset + 4].try_into().unwrap());
+    let (version, length) = (field(0x08), field(0x0C) as usize);
+    if &header[..8] != PLATFORM_INFO_SIGNATURE || version < 1 || length < PLATFORM_INFO_MIN_LENGTH {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to bad platform info header at {:#x}",
+            base as usize
+        );
+        sbi::shutdown()
+    }
+    let info = unsafe { core::slice::from_raw_parts(base, length) };
+    let (hart_count, hart_mask) = (field(0x10), field(0x14));
+    let board_name = platform_info_str(&info[0x18..0x38]);
+    let firmware_version = platform_info_str(&info[0x38..0x48]);
+    let plausible =
+        hart_count != 0 && hart_mask.count_ones() == hart_count && board_name.contains("Unmatched");
+    if !plausible {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to implausible platform info, board {}, {} harts ({:#x})",
+            board_name, hart_count, hart_mask
+        );
+        sbi::shutdown()
+    }
+    println!(
+        "<< Test-kernel: Board {}, firmware {}, {} harts",
+        board_name, firmware_version, hart_count
+    );
+}
+
+// indexed by the reason the firmware reports, as in its boot log
+const EXCLUSION_NAMES: [&str; 6] = [
+    "included",
+    "S7 core",
+    "disabled in device tree",
+    "missing in device tree",
+    "panicked",
+    "not initialized",
+];
+
+fn hart_excluded(hartid: usize) -> bool {
+    let sbi_ret = sbi::read_hart_exclusion(hartid);
+    sbi_ret.error == sbi::SBI_SUCCESS && sbi_ret.value != sbi::HART_INCLUDED
+}
+
+// Every hart outside the supervisor hart mask must carry the reason the
+// device tree gives for it; `cargo xtask image test-kernel --hart4-disabled`
+// packs a device tree where the S7 and a disabled hart are excluded together
+fn test_hart_exclusion(dtb_pa: usize) {
+    println!(">> Test-kernel: Testing hart exclusion reasons");
+    if sbi::read_hart_exclusion(0).error == sbi::SBI_ERR_NOT_SUPPORTED {
+        println!("<< Test-kernel: Hart exclusion reasons not supported, skipped");
+        return;
+    }
+    let info = sbi::get_platform_info();
+    if info.error != sbi::SBI_SUCCESS {
+        println!("<< Test-kernel: Platform info not supported, skipped");
+        return;
+    }
+    let hart_mask = unsafe { core::ptr::read_volatile((info.value + 0x14) as *const u32) } as usize;
+    let mut reasons_seen = 0usize;
+    for hartid in 0..MAX_HARTS {
+        let sbi_ret = sbi::read_hart_exclusion(hartid);
+        if sbi_ret.error != sbi::SBI_SUCCESS || sbi_ret.value >= EXCLUSION_NAMES.len() {
+            println!(
+                "!! Test-kernel: SBI test FAILED due to hart {} exclusion returned {:?}",
+                hartid, sbi_ret
+            );
+            sbi::shutdown()
+        }
+        let reason = sbi_ret.value;
+        let node: &[u8] = &[b'c', b'p', b'u', b'@', b'0' + hartid as u8];
+        let expected = if hart_mask & (1 << hartid) != 0 {
+            Some(sbi::HART_INCLUDED)
+        } else if hartid == 0 {
+            Some(sbi::HART_EXCLUDED_S7_CORE)
+        } else if node_property(dtb_pa, &[b"cpus", node], "reg").is_none() {
+            Some(sbi::HART_EXCLUDED_MISSING)
+        } else {
+            match node_property(dtb_pa, &[b"cpus", node], "status") {
+                Some(status) if status != b"okay\0" => Some(sbi::HART_EXCLUDED_DISABLED),
+                // excluded at run time, either reason will do
+                _ => None,
+            }
+        };
+        let plausible = match expected {
+            Some(expected) => reason == expected,
+            None => {
+                reason == sbi::HART_EXCLUDED_PANICKED
+                    || reason == sbi::HART_EXCLUDED_NOT_INITIALIZED
+            }
+        };
+        if !plausible {
+            println!(
+                "!! Test-kernel: SBI test FAILED due to hart {} reported {}, expected {}",
+                hartid,
+                EXCLUSION_NAMES[reason],
+                expected.map_or("a run-time reason", |expected| EXCLUSION_NAMES[expected])
+            );
+            sbi::shutdown()
+        }
+        if reason != sbi::HART_INCLUDED {
+            println!(
+                "<< Test-kernel: Hart {} excluded: {}",
+                hartid, EXCLUSION_NAMES[reason]
+            );
+            reasons_seen |= 1 << reason;
+        }
+    }
+    // `--hart4-disabled` must show up as its own reason even if the hart mask
+    // was wrong, and alongside the S7 unless hart 0 is the boot hart
+    let hart_4_disabled = matches!(
+        node_property(dtb_pa, &[b"cpus", b"cpu@4"], "status"),
+        Some(status) if status != b"okay\0"
+    );
+    let mut expected_reasons = 0usize;
+    if hart_mask & 1 == 0 {
+        expected_reasons |= 1 << sbi::HART_EXCLUDED_S7_CORE;
+    }
+    if hart_4_disabled {
+        expected_reasons |= 1 << sbi::HART_EXCLUDED_DISABLED;
+        let sbi_ret = sbi::read_hart_exclusion(4);
+        if sbi_ret.value != sbi::HART_EXCLUDED_DISABLED {
+            println!(
+                "!! Test-kernel: SBI test FAILED due to hart 4 disabled in device tree but reported {:?}",
+                sbi_ret
+            );
+            sbi::shutdown()
+        }
+    }
+    if expected_reasons.count_ones() < 2 {
+        println!(
+            "<< Test-kernel: Fewer than two exclusion reasons possible with this device tree and boot hart, reason set check skipped"
+        );
+    } else if reasons_seen & expected_reasons != expected_reasons {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to exclusion reasons {:#b}, expected at least {:#b}",
+            reasons_seen, expected_reasons
+        );
+        sbi::shutdown()
+    }
+    let invalid = sbi::read_hart_exclusion(MAX_HARTS);
+    if invalid.error != sbi::SBI_ERR_INVALID_PARAM {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to hart exclusion beyond the last hart returned {:?}",
+            invalid
+        );
+        sbi::shutdown()
+    }
+    println!(
+        "<< Test-kernel: {} distinct exclusion reason(s) match the device tree",
+        reasons_seen.count_ones()
+    );
+}
+
+// Must match the firmware's buffer, which always keeps a terminating 0
+const HART_SET_LINE_SIZE: usize = 256;
+const HART_SET_LINE_PREFIX: &str = "[rustsbi] boot hart ";
+const HART_SET_LINE_MASK: &str = "supervisor hart mask 0x";
+
+// The boot log line listing the harts, as the firmware printed it; with
+// `cargo xtask image test-kernel --hart4-disabled` it must name hart 4 as
+// disabled and leave it out of the mask, like the device tree says
+fn test_hart_set_line(dtb_pa: usize) {
+    println!(">> Test-kernel: Testing the boot log line of the hart set");
+    let sbi_ret = sbi::get_hart_set_line();
+    if sbi_ret.error == sbi::SBI_ERR_NOT_SUPPORTED {
+        println!("<< Test-kernel: Hart set line not supported, skipped");
+        return;
+    }
+    let line = platform_info_str(unsafe {
+        core::slice::from_raw_parts(sbi_ret.value as *const u8, HART_SET_LINE_SIZE)
+    });
+    let fail = |why: &str| -> ! {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to hart set line {}: {}",
+            why, line
+        );
+        sbi::shutdown()
+    };
+    let info = sbi::get_platform_info();
+    if info.error != sbi::SBI_SUCCESS {
+        println!("<< Test-kernel: Platform info not supported, skipped");
+        return;
+    }
+    let hart_mask = unsafe { core::ptr::read_volatile((info.value + 0x14) as *const u32) } as usize;
+    // "[rustsbi] boot hart B[, excluded: hart N (reason)[, hart N (reason)...]], supervisor hart mask 0xM"
+    let mut parts = match line.strip_prefix(HART_SET_LINE_PREFIX) {
+        Some(rest) => rest.split(", "),
+        None => fail("without the boot hart"),
+    };
+    let boot_hart = match parts.next().and_then(|hart| hart.parse::<usize>().ok()) {
+        Some(hart) if hart < MAX_HARTS => hart,
+        _ => fail("without the boot hart"),
+    };
+    if hart_mask & (1 << boot_hart) == 0 {
+        fail("with a boot hart outside the supervisor hart mask")
+    }
+    let mut printed_mask = None;
+    let mut listed = 0usize;
+    let mut listed_reasons = [""; MAX_HARTS];
+    for part in parts {
+        if let Some(mask) = part.strip_prefix(HART_SET_LINE_MASK) {
+            printed_mask = usize::from_str_radix(mask, 16).ok();
+            continue;
+        }
+        let part = part.strip_prefix("excluded: ").unwrap_or(part);
+        let (hartid, reason) = match part
+            .strip_prefix("hart ")
+            .and_then(|part| part.split_once(" ("))
+            .and_then(|(hartid, reason)| {
+                Some((hartid.parse::<usize>().ok()?, reason.strip_suffix(')')?))
+            }) {
+            Some((hartid, reason)) if hartid < MAX_HARTS => (hartid, reason),
+            _ => fail("with an unreadable entry"),
+        };
+        let reported = sbi::read_hart_exclusion(hartid);
+        if EXCLUSION_NAMES.get(reported.value) != Some(&reason) {
+            fail("disagreeing with the exclusion reason call")
+        }
+        listed |= 1 << hartid;
+        listed_reasons[hartid] = reason;
+    }
+    if printed_mask != Some(hart_mask) {
+        fail("with a mask other than the platform info")
+    }
+    if listed != ((1 << MAX_HARTS) - 1) & !hart_mask {
+        fail("not listing every hart outside the mask")
+    }
+    let disabled = matches!(
+        node_property(dtb_pa, &[b"cpus", b"cpu@4"], "status"),
+        Some(status) if status != b"okay\0"
+    );
+    if disabled && listed_reasons[4] != EXCLUSION_NAMES[sbi::HART_EXCLUDED_DISABLED] {
+        fail("not showing hart 4 disabled by the device tree")
+    }
+    println!(
+        "<< Test-kernel: Hart set line matches the mask {:#x}{}",
+        hart_mask,
+        if disabled { ", hart 4 disabled" } else { "" }
+    );
+}
+
+fn build_feature_enabled(name: &str) -> bool {
+    let features = sbi::read_build_config(sbi::BUILD_CONFIG_FEATURES);
+    let bit = sbi::BUILD_FEATURE_NAMES
+        .iter()
+        .position(|feature| *feature == name)
+        .unwrap();
+    features.error == sbi::SBI_SUCCESS && features.value & (1 << bit) != 0
+}
+
+// the hart the firmware's init-faults feature panics during init
+const INIT_FAULT_HART: usize = MAX_HARTS - 1;
+
+// with init-faults the last hart panics before it reports ready; the boot
+// hart must leave it behind and still enter the supervisor, which it has
+// done by the time this runs
+fn test_init_fault() {
+    println!(
+        ">> Test-kernel: Testing hart {} panicking during init",
+        INIT_FAULT_HART
+    );
+    if !build_feature_enabled("init-faults") {
+        println!("<< Test-kernel: Init faults not enabled, skipped");
+        return;
+    }
+    let sbi_ret = sbi::read_hart_exclusion(INIT_FAULT_HART);
+    if sbi_ret.error == sbi::SBI_SUCCESS && sbi_ret.value == sbi::HART_EXCLUDED_DISABLED {
+        println!("<< Test-kernel: Hart disabled in device tree before it could panic, skipped");
+        return;
+    }
+    if sbi_ret.error != sbi::SBI_SUCCESS || sbi_ret.value != sbi::HART_EXCLUDED_PANICKED {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to hart {} exclusion {:?}, expected panicked",
+            INIT_FAULT_HART, sbi_ret
+        );
+        sbi::shutdown()
+    }
+    let sbi_ret = sbi::hart_get_status(INIT_FAULT_HART);
+    if sbi_ret.error != sbi::SBI_SUCCESS || sbi_ret.value != sbi::HART_STATUS_STOPPED {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to panicked hart status {:?}",
+            sbi_ret
+        );
+        sbi::shutdown()
+    }
+    let sbi_ret = sbi::hart_start(INIT_FAULT_HART, hart_4_restarted as usize, 0);
+    if sbi_ret.error != sbi::SBI_ERR_FAILED {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to hart panicked in init started: {:?}",
+            sbi_ret
+        );
+        sbi::shutdown()
+    }
+    println!(
+        "<< Test-kernel: Hart {} panicked during init, the boot hart went on into the supervisor",
+        INIT_FAULT_HART
+    );
+}
+
+fn read_build_config(field: usize) -> usize {
+    let sbi_ret = sbi::read_build_config(field);
+    if sbi_ret.error != sbi::SBI_SUCCESS {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to build config field {} unreadable: {:?}",
+            field, sbi_ret
+        );
+        sbi::shutdown()
+    }
+    sbi_ret.value
+}
+
+fn test_build_config(hartid: usize) {
+    println!(">> Test-kernel: Testing firmware build config");
+    let probe = sbi::read_build_config(sbi::BUILD_CONFIG_FEATURES);
+    if probe.error == sbi::SBI_ERR_NOT_SUPPORTED {
+        println!("<< Test-kernel: Build config not supported, skipped");
+        return;
+    }
+    let features = read_build_config(sbi::BUILD_CONFIG_FEATURES);
+    let heap_size = read_build_config(sbi::BUILD_CONFIG_HEAP_SIZE);
+    let stack_size = read_build_config(sbi::BUILD_CONFIG_STACK_SIZE);
+    let dtb_size = read_build_config(sbi::BUILD_CONFIG_EMBEDDED_DTB_SIZE);
+    print!("<< Test-kernel: Firmware features:");
+    for (bit, name) in sbi::BUILD_FEATURE_NAMES.iter().enumerate() {
+        if features & (1 << bit) != 0 {
+            print!(" {}", name);
+        }
+    }
+    println!("");
+    println!(
+        "<< Test-kernel: Firmware heap {} bytes, stack {} bytes per hart, embedded device tree {} bytes",
+        heap_size, stack_size, dtb_size
+    );
+    if features >> sbi::BUILD_FEATURE_NAMES.len() != 0 {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to unknown feature bits {:#x}",
+            features
+        );
+        sbi::shutdown()
+    }
+    if heap_size == 0 || heap_size > FIRMWARE_HEAP_MAX || stack_size != FIRMWARE_STACK_SIZE {
+        println!("!! Test-kernel: SBI test FAILED due to implausible heap or stack size");
+        sbi::shutdown()
+    }
+    let invalid = sbi::read_build_config(usize::MAX);
+    if invalid.error != sbi::SBI_ERR_INVALID_PARAM {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to unknown build config field accepted: {:?}",
+            invalid
+        );
+        sbi::shutdown()
+    }
+    // Features with a vendor call must agree with whether the call is supported
+    let supported = |sbi_ret: sbi::SbiRet| sbi_ret.error != sbi::SBI_ERR_NOT_SUPPORTED;
+    let checks = [
+        (
+            "trap-profile",
+            supported(sbi::read_trap_profile(
+                sbi::TRAP_KIND_MACHINE_TIMER,
+                sbi::TRAP_PROFILE_COUNT,
+            )),
+        ),
+        (
+            "stack-watermark",
+            supported(sbi::read_stack_watermark(hartid)),
+        ),
+        ("memory-test", supported(sbi::read_memory_test_result())),
+        // An invalid action is rejected before anything panics
+        ("panic-injection", supported(sbi::inject_panic(usize::MAX))),
+        ("time-scale", supported(sbi::set_time_scale(1, 1))),
+    ];
+    for (name, call_supported) in checks {
+        let bit = sbi::BUILD_FEATURE_NAMES
+            .iter()
+            .position(|feature| *feature == name)
+            .unwrap();
+        let reported = features & (1 << bit) != 0;
+        if reported != call_supported {
+            println!(
+                "!! Test-kernel: SBI test FAILED due to feature {} reported {}, but its call is {}",
+                name,
+                if reported { "on" } else { "off" },
+                if call_supported {
+                    "supported"
+                } else {
+                    "not supported"
+                }
+            );
+            sbi::shutdown()
+        }
+    }
+    println!("<< Test-kernel: Build config matches the supported calls");
+}
+
+fn test_early_memory_test() {
+    println!(">> Test-kernel: Checking early boot memory test result");
+    let sbi_ret = sbi::read_memory_test_result();
+    if sbi_ret.error == sbi::SBI_ERR_NOT_SUPPORTED {
+        println!("<< Test-kernel: Memory test not enabled in this build, skipped");
+        return;
+    }
+    if sbi_ret.error != sbi::SBI_SUCCESS {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to memory test not run: {:?}",
+            sbi_ret
+        );
+        sbi::shutdown()
+    }
+    if sbi_ret.value != 0 {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to {} mismatched words in memory test",
+            sbi_ret.value
+        );
+        sbi::shutdown()
+    }
+    println!("<< Test-kernel: Early memory test passed");
+}
+
+// the firmware reads the sensor once at boot; any real board is well inside this range
+const TEMPERATURE_MIN: isize = -40_000;
+const TEMPERATURE_MAX: isize = 125_000;
+
+fn test_board_temperature() {
+    println!(">> Test-kernel: Reading board temperature sensor");
+    let sbi_ret = sbi::read_board_temperature(sbi::TEMPERATURE_CHANNEL_BOARD);
+    if sbi_ret.error == sbi::SBI_ERR_NOT_SUPPORTED {
+        println!("<< Test-kernel: No temperature sensor in this build or board, skipped");
+        return;
+    }
+    let sbi_ret = sbi::read_board_temperature(2);
+    if sbi_ret.error != sbi::SBI_ERR_INVALID_PARAM {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to invalid channel returned {:?}",
+            sbi_ret
+        );
+        sbi::shutdown()
+    }
+    for (name, channel) in [
+        ("board", sbi::TEMPERATURE_CHANNEL_BOARD),
+        ("FU740", sbi::TEMPERATURE_CHANNEL_SOC),
+    ] {
+        let sbi_ret = sbi::read_board_temperature(channel);
+        let value = sbi_ret.value as isize;
+        if sbi_ret.error != sbi::SBI_SUCCESS
+            || !(TEMPERATURE_MIN..=TEMPERATURE_MAX).contains(&value)
+        {
+            println!(
+                "!! Test-kernel: SBI test FAILED due to {} temperature {:?}",
+                name, sbi_ret
+            );
+            sbi::shutdown()
+        }
+        println!(
+            "<< Test-kernel: {} temperature {} millidegrees Celsius",
+            name, value
+        );
+    }
+}
+
+// hart 4 only stops itself in rust_main, it is free for a test that breaks it
+// SiFive UART0 transmit control register; bit 0 enables the transmitter
+const UART0_TXCTRL: usize = 0x1001_0008;
+const UART_TXEN: u32 = 1 << 0;
+// Enough to fill the 8-entry transmit FIFO and then some
+const UART_STUCK_FILLER: usize = 32;
+
+fn uart0_txctrl() -> u32 {
+    unsafe { core::ptr::read_volatile(UART0_TXCTRL as *const u32) }
+}
+
+fn test_stuck_uart() {
+    println!(">> Test-kernel: Testing console recovery from a stuck UART");
+    // With the transmitter disabled the FIFO never drains, which looks just
+    // like a wedged TX-full bit to the firmware
+    let txctrl = uart0_txctrl();
+    unsafe { core::ptr::write_volatile(UART0_TXCTRL as *mut u32, txctrl & !UART_TXEN) };
+    for _ in 0..UART_STUCK_FILLER {
+        sbi::console_putchar(b'.' as usize);
+    }
+    let recovered = uart0_txctrl() & UART_TXEN != 0;
+    if !recovered {
+        // Bring the console back ourselves so the failure can be reported
+        unsafe { core::ptr::write_volatile(UART0_TXCTRL as *mut u32, txctrl) };
+        println!("!! Test-kernel: SBI test FAILED due to UART transmitter not re-enabled");
+        sbi::shutdown()
+    }
+    println!("<< Test-kernel: Console recovered from a stuck UART");
+}
+
+const UART0_BASE: usize = 0x1001_0000;
+const UART1_BASE: usize = 0x1001_1000;
+const UART_TXCTRL_OFFSET: usize = 0x08;
+const UART_IP_OFFSET: usize = 0x14;
+// transmit watermark: with txcnt at 1, ip.txwm is set only while the FIFO is empty
+const UART_TXCNT_ONE: u32 = 1 << 16;
+const UART_TXCNT_MASK: u32 = 0b111 << 16;
+const UART_IP_TXWM: u32 = 1 << 0;
+const UART_MIRROR_FILLER: usize = 16;
+
+fn uart_reg(base: usize, offset: usize) -> *mut u32 {
+    (base + offset) as *mut u32
+}
+
+fn uart_tx_idle(base: usize) -> bool {
+    unsafe { core::ptr::read_volatile(uart_reg(base, UART_IP_OFFSET)) & UART_IP_TXWM != 0 }
+}
+
+// The FIFOs are write-only, but the transmit watermark shows whether a UART
+// has anything queued: right after a burst of console output both UARTs must
+// have bytes left to send with console-mirror, and only UART0 without it
+fn test_console_mirror() {
+    println!(">> Test-kernel: Testing console output on both UARTs");
+    let mirrored = build_feature_enabled("console-mirror");
+    let txctrl = [UART0_BASE, UART1_BASE]
+        .map(|base| unsafe { core::ptr::read_volatile(uart_reg(base, UART_TXCTRL_OFFSET)) });
+    for (base, txctrl) in [UART0_BASE, UART1_BASE].iter().zip(txctrl) {
+        let watermark = (txctrl & !UART_TXCNT_MASK) | UART_TXCNT_ONE;
+        unsafe { core::ptr::write_volatile(uart_reg(*base, UART_TXCTRL_OFFSET), watermark) };
+    }
+    for _ in 0..UART_MIRROR_FILLER {
+        sbi::console_putchar(b'.' as usize);
+    }
+    let busy = [!uart_tx_idle(UART0_BASE), !uart_tx_idle(UART1_BASE)];
+    let time_start = time::read64();
+    while !uart_tx_idle(UART0_BASE) && time::read64() < time_start + TIMER_TEST_TIMEOUT {}
+    for (base, txctrl) in [UART0_BASE, UART1_BASE].iter().zip(txctrl) {
+        unsafe { core::ptr::write_volatile(uart_reg(*base, UART_TXCTRL_OFFSET), txctrl) };
+    }
+    println!("");
+    if busy != [true, mirrored] {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to console output queued on uart0 {}, uart1 {}, console-mirror {}",
+            busy[0],
+            busy[1],
+            if mirrored { "on" } else { "off" }
+        );
+        sbi::shutdown()
+    }
+    println!(
+        "<< Test-kernel: Console output went to {}",
+        if mirrored {
+            "uart0 and uart1"
+        } else {
+            "uart0 only"
+        }
+    );
+}
+
+// Must match the firmware's default when /chosen has no rustsbi,uart-init-attempts
+const UART_DEFAULT_INIT_ATTEMPTS: usize = 3;
+
+// With uart-init-faults the firmware reports the first UART1 readback as not taken,
+// so a healthy UART1 needs exactly two attempts, or fails when fewer are allowed
+fn test_uart_init_retry(dtb_pa: usize) {
+    println!(">> Test-kernel: Testing UART1 setup retries");
+    let sbi_ret = sbi::read_uart_init_attempts();
+    if sbi_ret.error == sbi::SBI_ERR_NOT_SUPPORTED {
+        if build_feature_enabled("console-mirror") {
+            println!("!! Test-kernel: SBI test FAILED due to no record of the console-mirror UART1 setup");
+            sbi::shutdown()
+        }
+        println!("<< Test-kernel: UART1 not in use, skipped");
+        return;
+    }
+    let max_attempts = match chosen_property(dtb_pa, "rustsbi,uart-init-attempts") {
+        Some(cells) if cells.len() == 4 => u32::from_be_bytes(cells.try_into().unwrap()) as usize,
+        Some(cells) if cells.len() == 8 => u64::from_be_bytes(cells.try_into().unwrap()) as usize,
+        _ => 0,
+    };
+    let max_attempts = if max_attempts == 0 {
+        UART_DEFAULT_INIT_ATTEMPTS
+    } else {
+        max_attempts
+    };
+    let needed = if build_feature_enabled("uart-init-faults") {
+        2
+    } else {
+        1
+    };
+    let expected = if needed <= max_attempts {
+        (sbi::SBI_SUCCESS, needed)
+    } else {
+        (sbi::SBI_ERR_FAILED, max_attempts)
+    };
+    if (sbi_ret.error, sbi_ret.value) != expected {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to UART1 setup returned {:?}, expected {} attempt(s) of {}",
+            sbi_ret, expected.1, max_attempts
+        );
+        sbi::shutdown()
+    }
+    println!(
+        "<< Test-kernel: UART1 setup took {} attempt(s), {} allowed",
+        sbi_ret.value, max_attempts
+    );
+}
+
+const BROKEN_TRAP_HART: usize = 4;
+
+#[repr(C, align(4))]
+struct BrokenTrapHandler([u32; 4]);
+
+// two different exceptions at the handler entry, both fall back to the machine level:
+// all-zero words are defined to be illegal instructions, and `lw t0, 1(sp)` is a
+// misaligned load, which is not delegated and not emulated by the firmware
+static BROKEN_TRAP_HANDLERS: [(&str, BrokenTrapHandler); 2] = [
+    ("illegal instruction", BrokenTrapHandler([0; 4])),
+    ("misaligned load", BrokenTrapHandler([0x0011_2283, 0, 0, 0])),
+];
+static BROKEN_TRAP_ENTERED: AtomicBool = AtomicBool::new(false);
+static BROKEN_TRAP_RESTARTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn hart_4_broken_trap(_hart_id: usize, handler: usize) {
+    BROKEN_TRAP_ENTERED.store(true, Ordering::SeqCst);
+    unsafe { stvec::write(handler, TrapMode::Direct) };
+    // forwarded to stvec, where the first instruction of the handler traps again
+    unsafe { core::arch::asm!("csrw mcycle, x0") };
+    println!("!! Test-kernel: SBI test FAILED due to broken trap handler returned");
+    sbi::shutdown()
+}
+
+extern "C" fn hart_4_restarted(_hart_id: usize, _param: usize) {
+    BROKEN_TRAP_RESTARTED.store(true, Ordering::SeqCst);
+    let sbi_ret = sbi::hart_stop();
+    println!(
+        "!! Test-kernel: SBI test FAILED due to hart stop returned {:?}",
+        sbi_ret
+    );
+    sbi::shutdown()
+}
+
+// start hart 4 on its own schedule, it may not have stopped itself yet
+fn start_broken_trap_hart(entry: usize, param: usize) {
+    let time_start = time::read64();
+    loop {
+        let sbi_ret = sbi::hart_start(BROKEN_TRAP_HART, entry, param);
+        if sbi_ret.error == sbi::SBI_SUCCESS {
+            return;
+        }
+        if time::read64() > time_start + TIMER_TEST_TIMEOUT {
+            println!(
+                "!! Test-kernel: SBI test FAILED due to hart {} start returned {:?}",
+                BROKEN_TRAP_HART, sbi_ret
+            );
+            sbi::shutdown()
+        }
+    }
+}
+
+fn wait_until(flag: &AtomicBool, what: &str) {
+    let time_start = time::read64();
+    while !flag.load(Ordering::SeqCst) {
+        if time::read64() > time_start + TIMER_TEST_TIMEOUT {
+            println!("!! Test-kernel: SBI test FAILED due to {}", what);
+            sbi::shutdown()
+        }
+    }
+}
+
+// the firmware must tell the broken handler apart and stop the hart instead of
+// forwarding the same trap to it forever, whichever exception the entry raises;
+// the diagnostic is on the console
+fn test_broken_trap_handler() {
+    println!(
+        ">> Test-kernel: Testing broken stvec on hart {}",
+        BROKEN_TRAP_HART
+    );
+    if hart_excluded(BROKEN_TRAP_HART) {
+        println!("<< Test-kernel: Hart not in the supervisor, skipped");
+        return;
+    }
+    for (name, handler) in BROKEN_TRAP_HANDLERS.iter() {
+        BROKEN_TRAP_ENTERED.store(false, Ordering::SeqCst);
+        BROKEN_TRAP_RESTARTED.store(false, Ordering::SeqCst);
+        start_broken_trap_hart(hart_4_broken_trap as usize, handler.0.as_ptr() as usize);
+        wait_until(&BROKEN_TRAP_ENTERED, "hart not started with broken stvec");
+        let time_start = time::read64();
+        loop {
+            let sbi_ret = sbi::hart_get_status(BROKEN_TRAP_HART);
+            if sbi_ret.error == sbi::SBI_SUCCESS && sbi_ret.value == sbi::HART_STATUS_STOPPED {
+                break;
+            }
+            if time::read64() > time_start + TIMER_TEST_TIMEOUT {
+                println!(
+                    "!! Test-kernel: SBI test FAILED due to hart with {} at stvec not stopped: {:?}",
+                    name, sbi_ret
+                );
+                sbi::shutdown()
+            }
+        }
+        start_broken_trap_hart(hart_4_restarted as usize, 0);
+        wait_until(&BROKEN_TRAP_RESTARTED, "hart not restarted after stop");
+        println!(
+            "<< Test-kernel: Broken stvec ({}) stopped hart {}, restarted with hart_start",
+            name, BROKEN_TRAP_HART
+        );
+    }
+}
+
+// an action number the firmware does not know, to probe for panic injection
+const PANIC_ACTION_UNKNOWN: usize = 3;
+
+static PANIC_HART_ENTERED: AtomicBool = AtomicBool::new(false);
+static PANIC_CONSOLE_BUSY: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn hart_4_panic(_hart_id: usize, _param: usize) {
+    PANIC_HART_ENTERED.store(true, Ordering::SeqCst);
+    // panic only once the other hart has started writing to the console
+    while !PANIC_CONSOLE_BUSY.load(Ordering::SeqCst) {
+        core::hint::spin_loop()
+    }
+    let sbi_ret = sbi::inject_panic(sbi::PANIC_ACTION_HALT);
+    println!(
+        "!! Test-kernel: SBI test FAILED due to injected panic returned {:?}",
+        sbi_ret
+    );
+    sbi::shutdown()
+}
+
+const PANIC_RACE_MESSAGE: &[u8] = b"<< Test-kernel: Debug console write during panic\n";
+
+// Each write holds the firmware's console lock for as long as the UART takes
+// to send it, so the panic output has to get past a lock held by this hart
+fn write_during_panic() {
+    let sbi_ret = sbi::console_write(
+        PANIC_RACE_MESSAGE.len(),
+        PANIC_RACE_MESSAGE.as_ptr() as usize,
+        0,
+    );
+    if sbi_ret.error != sbi::SBI_SUCCESS || sbi_ret.value != PANIC_RACE_MESSAGE.len() {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to debug console write during panic returned {:?}",
+            sbi_ret
+        );
+        sbi::shutdown()
+    }
+}
+
+// a hart halted after a firmware panic is gone for good: it reads as stopped,
+// but hart_start refuses it; the other harts keep running. This hart keeps
+// writing to the debug console meanwhile, and neither side may get stuck on
+// the console lock
+fn test_panic_halt() {
+    println!(
+        ">> Test-kernel: Testing panic action halt on hart {}",
+        BROKEN_TRAP_HART
+    );
+    let sbi_ret = sbi::inject_panic(PANIC_ACTION_UNKNOWN);
+    if sbi_ret.error == sbi::SBI_ERR_NOT_SUPPORTED {
+        println!("<< Test-kernel: Panic injection not supported, skipped");
+        return;
+    }
+    if hart_excluded(BROKEN_TRAP_HART) {
+        println!("<< Test-kernel: Hart not in the supervisor, skipped");
+        return;
+    }
+    if sbi_ret.error != sbi::SBI_ERR_INVALID_PARAM {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to unknown panic action returned {:?}",
+            sbi_ret
+        );
+        sbi::shutdown()
+    }
+    start_broken_trap_hart(hart_4_panic as usize, 0);
+    wait_until(&PANIC_HART_ENTERED, "hart not started for panic injection");
+    let dbcn = sbi::probe_extension(sbi::EXTENSION_DBCN) != 0;
+    PANIC_CONSOLE_BUSY.store(true, Ordering::SeqCst);
+    let mut writes = 0;
+    let time_start = time::read64();
+    loop {
+        if dbcn {
+            write_during_panic();
+            writes += 1;
+        }
+        let sbi_ret = sbi::hart_start(BROKEN_TRAP_HART, hart_4_panic as usize, 0);
+        if sbi_ret.error == sbi::SBI_ERR_FAILED {
+            break;
+        }
+        if time::read64() > time_start + TIMER_TEST_TIMEOUT {
+            println!(
+                "!! Test-kernel: SBI test FAILED due to panicked hart start returned {:?}",
+                sbi_ret
+            );
+            sbi::shutdown()
+        }
+    }
+    let sbi_ret = sbi::hart_get_status(BROKEN_TRAP_HART);
+    if sbi_ret.error != sbi::SBI_SUCCESS || sbi_ret.value != sbi::HART_STATUS_STOPPED {
+        println!(
+            "!! Test-kernel: SBI test FAILED due to panicked hart status {:?}",
+            sbi_ret
+        );
+        sbi::shutdown()
+    }
+    if dbcn {
+        // the lock must be free again once the panicking hart has halted
+        write_during_panic();
+        println!(
+            "<< Test-kernel: {} debug console write(s) raced the panic, console still usable",
+            writes + 1
+        );
+    }
+    println!(
+        "<< Test-kernel: Panicked hart {} halted, cannot be started again",
+        BROKEN_TRAP_HART
+    );
+}
+
+fn record_interrupt(interrupt: usize) {
+    let idx = INTERRUPT_COUNT.fetch_add(1, Ordering::SeqCst);
+    if idx < INTERRUPT_ORDER.len() {
+        INTERRUPT_ORDER[idx].store(interrupt, Ordering::SeqCst);
+    }
+}
+
+static ILLEGAL_TRAP_COUNT: AtomicUsize = AtomicUsize::new(0);
+static TIMER_FIRED: AtomicBool = AtomicBool::new(false);
+static INTERRUPT_COUNT: AtomicUsize = AtomicUsize::new(0);
+static INTERRUPT_ORDER: [AtomicUsize; 2] = [AtomicUsize::new(0), AtomicUsize::new(0)];
+
+pub extern "C" fn rust_trap_exception() {
+    let cause = scause::read().cause();
+    if cause == Trap::Interrupt(Interrupt::SupervisorTimer) {
+        TIMER_CYCLE.store(read_cycle() as usize, Ordering::SeqCst);
+        sbi::set_timer(usize::MAX); // clear the pending timer
+        TIMER_FIRED.store(true, Ordering::SeqCst);
+        record_interrupt(INTERRUPT_TIMER);
+        return;
+    }
+    if cause == Trap::Interrupt(Interrupt::SupervisorSoft) {
+        unsafe { core::arch::asm!("csrc sip, {}", in(reg) 1 << 1) }; // clear SSIP
+        record_interrupt(INTERRUPT_SOFT);
+        return;
+    }
     println!("<< Test-kernel: Value of scause: {:?}", cause);
     if cause != Trap::Exception(Exception::IllegalInstruction) {
         println!("!! Test-kernel: Wrong cause associated to illegal instruction");
         sbi::shutdown()
     }
     println!("<< Test-kernel: Illegal exception delegate success");
+    ILLEGAL_TRAP_COUNT.fetch_add(1, Ordering::SeqCst);
     sepc::write(sepc::read().wrapping_add(4));
 }
 
@@ -171,6 +2376,16 @@ static mut BOOT_STACK: [u8; BOOT_STACK_SIZE] = [0; BOOT_STACK_SIZE];
 #[export_name = "_start"]
 unsafe extern "C" fn entry() -> ! {
     core::arch::asm!("
+    # 0. with the dtb-first convention a0 holds the device tree and a1 the
+    # hart id; swap them back and tell rust_main through a2
+    li      a2, 0
+    li      t0, {max_hart_id}
+    bleu    a0, t0, 2f
+    mv      t0, a0
+    mv      a0, a1
+    mv      a1, t0
+    li      a2, 1
+2:
     # 1. set sp
     # sp = bootstack + (hartid + 1) * 0x10000
     add     t0, a0, 1
@@ -183,6 +2398,7 @@ unsafe extern "C" fn entry() -> ! {
     addi    t0, t0, %pcrel_lo(1b)
     jr      t0
     ", 
+    max_hart_id = const MAX_HARTS - 1,
     boot_stack = sym BOOT_STACK,
     rust_main = sym rust_main,
     options(noreturn))
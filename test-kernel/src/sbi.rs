@@ -8,6 +8,9 @@ pub const EXTENSION_IPI: usize = 0x735049;
 pub const EXTENSION_RFENCE: usize = 0x52464E43;
 pub const EXTENSION_HSM: usize = 0x48534D;
 pub const EXTENSION_SRST: usize = 0x53525354;
+pub const EXTENSION_PMU: usize = 0x504D55;
+pub const EXTENSION_DBCN: usize = 0x4442434E;
+pub const EXTENSION_UNMATCHED: usize = 0x0A554E4D;
 
 const FUNCTION_BASE_GET_SPEC_VERSION: usize = 0x0;
 const FUNCTION_BASE_GET_SBI_IMPL_ID: usize = 0x1;
@@ -25,15 +28,15 @@ pub struct SbiRet {
     pub value: usize,
 }
 
-const SBI_SUCCESS: usize = 0;
-const SBI_ERR_FAILED: usize = usize::from_ne_bytes(isize::to_ne_bytes(-1));
-const SBI_ERR_NOT_SUPPORTED: usize = usize::from_ne_bytes(isize::to_ne_bytes(-2));
-const SBI_ERR_INVALID_PARAM: usize = usize::from_ne_bytes(isize::to_ne_bytes(-3));
-const SBI_ERR_DENIED: usize = usize::from_ne_bytes(isize::to_ne_bytes(-4));
-const SBI_ERR_INVALID_ADDRESS: usize = usize::from_ne_bytes(isize::to_ne_bytes(-5));
-const SBI_ERR_ALREADY_AVAILABLE: usize = usize::from_ne_bytes(isize::to_ne_bytes(-6));
-const SBI_ERR_ALREADY_STARTED: usize = usize::from_ne_bytes(isize::to_ne_bytes(-7));
-const SBI_ERR_ALREADY_STOPPED: usize = usize::from_ne_bytes(isize::to_ne_bytes(-8));
+pub const SBI_SUCCESS: usize = 0;
+pub const SBI_ERR_FAILED: usize = usize::from_ne_bytes(isize::to_ne_bytes(-1));
+pub const SBI_ERR_NOT_SUPPORTED: usize = usize::from_ne_bytes(isize::to_ne_bytes(-2));
+pub const SBI_ERR_INVALID_PARAM: usize = usize::from_ne_bytes(isize::to_ne_bytes(-3));
+pub const SBI_ERR_DENIED: usize = usize::from_ne_bytes(isize::to_ne_bytes(-4));
+pub const SBI_ERR_INVALID_ADDRESS: usize = usize::from_ne_bytes(isize::to_ne_bytes(-5));
+pub const SBI_ERR_ALREADY_AVAILABLE: usize = usize::from_ne_bytes(isize::to_ne_bytes(-6));
+pub const SBI_ERR_ALREADY_STARTED: usize = usize::from_ne_bytes(isize::to_ne_bytes(-7));
+pub const SBI_ERR_ALREADY_STOPPED: usize = usize::from_ne_bytes(isize::to_ne_bytes(-8));
 
 impl fmt::Debug for SbiRet {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -137,15 +140,15 @@ fn sbi_call_legacy(which: usize, arg0: usize, arg1: usize, arg2: usize) -> usize
     ret
 }
 
-const SBI_SET_TIMER: usize = 0;
-const SBI_CONSOLE_PUTCHAR: usize = 1;
-const SBI_CONSOLE_GETCHAR: usize = 2;
-const SBI_CLEAR_IPI: usize = 3;
-const SBI_SEND_IPI: usize = 4;
-const SBI_REMOTE_FENCE_I: usize = 5;
-const SBI_REMOTE_SFENCE_VMA: usize = 6;
-const SBI_REMOTE_SFENCE_VMA_ASID: usize = 7;
-const SBI_SHUTDOWN: usize = 8;
+pub const SBI_SET_TIMER: usize = 0;
+pub const SBI_CONSOLE_PUTCHAR: usize = 1;
+pub const SBI_CONSOLE_GETCHAR: usize = 2;
+pub const SBI_CLEAR_IPI: usize = 3;
+pub const SBI_SEND_IPI: usize = 4;
+pub const SBI_REMOTE_FENCE_I: usize = 5;
+pub const SBI_REMOTE_SFENCE_VMA: usize = 6;
+pub const SBI_REMOTE_SFENCE_VMA_ASID: usize = 7;
+pub const SBI_SHUTDOWN: usize = 8;
 
 pub fn console_putchar(c: usize) {
     sbi_call_legacy(SBI_CONSOLE_PUTCHAR, c, 0, 0);
@@ -159,6 +162,12 @@ pub fn set_timer(time: usize) {
     sbi_call_legacy(SBI_SET_TIMER, time, 0, 0);
 }
 
+const FUNCTION_TIMER_SET_TIMER: usize = 0x0;
+
+pub fn set_timer_checked(time: usize) -> SbiRet {
+    sbi_call_1(EXTENSION_TIMER, FUNCTION_TIMER_SET_TIMER, time)
+}
+
 const FUNCTION_IPI_SEND_IPI: usize = 0x0;
 
 pub fn send_ipi(hart_mask: usize, hart_mask_base: usize) -> SbiRet {
@@ -170,11 +179,32 @@ pub fn send_ipi(hart_mask: usize, hart_mask_base: usize) -> SbiRet {
     )
 }
 
+const FUNCTION_RFENCE_REMOTE_HFENCE_GVMA: usize = 0x4;
+
+pub fn remote_hfence_gvma(
+    hart_mask: usize,
+    hart_mask_base: usize,
+    start_addr: usize,
+    size: usize,
+) -> SbiRet {
+    sbi_call_4(
+        EXTENSION_RFENCE,
+        FUNCTION_RFENCE_REMOTE_HFENCE_GVMA,
+        hart_mask,
+        hart_mask_base,
+        start_addr,
+        size,
+    )
+}
+
 const FUNCTION_HSM_HART_START: usize = 0x0;
 const FUNCTION_HSM_HART_STOP: usize = 0x1;
 const FUNCTION_HSM_HART_GET_STATUS: usize = 0x2;
 const FUNCTION_HSM_HART_SUSPEND: usize = 0x3;
 
+pub const HART_STATUS_STARTED: usize = 0;
+pub const HART_STATUS_STOPPED: usize = 1;
+
 pub fn hart_start(hartid: usize, start_addr: usize, opaque: usize) -> SbiRet {
     sbi_call_3(
         EXTENSION_HSM,
@@ -185,8 +215,8 @@ pub fn hart_start(hartid: usize, start_addr: usize, opaque: usize) -> SbiRet {
     )
 }
 
-pub fn hart_stop(hartid: usize) -> SbiRet {
-    sbi_call_1(EXTENSION_HSM, FUNCTION_HSM_HART_STOP, hartid)
+pub fn hart_stop() -> SbiRet {
+    sbi_call_0(EXTENSION_HSM, FUNCTION_HSM_HART_STOP)
 }
 
 pub fn hart_get_status(hartid: usize) -> SbiRet {
@@ -203,6 +233,297 @@ pub fn hart_suspend(suspend_type: u32, resume_addr: usize, opaque: usize) -> Sbi
     )
 }
 
+const FUNCTION_PMU_NUM_COUNTERS: usize = 0x0;
+const FUNCTION_PMU_COUNTER_CONFIG_MATCHING: usize = 0x2;
+const FUNCTION_PMU_COUNTER_START: usize = 0x3;
+const FUNCTION_PMU_COUNTER_STOP: usize = 0x4;
+const FUNCTION_PMU_COUNTER_FW_READ: usize = 0x5;
+
+pub const PMU_CONFIG_FLAG_CLEAR_VALUE: usize = 1 << 1;
+pub const PMU_CONFIG_FLAG_AUTO_START: usize = 1 << 2;
+pub const PMU_STOP_FLAG_RESET: usize = 1 << 0;
+pub const PMU_EVENT_RAW: usize = 0x20000;
+
+pub fn pmu_num_counters() -> SbiRet {
+    sbi_call_0(EXTENSION_PMU, FUNCTION_PMU_NUM_COUNTERS)
+}
+
+pub fn pmu_counter_config_matching(
+    counter_idx_base: usize,
+    counter_idx_mask: usize,
+    config_flags: usize,
+    event_idx: usize,
+    event_data: usize,
+) -> SbiRet {
+    sbi_call_5(
+        EXTENSION_PMU,
+        FUNCTION_PMU_COUNTER_CONFIG_MATCHING,
+        counter_idx_base,
+        counter_idx_mask,
+        config_flags,
+        event_idx,
+        event_data,
+    )
+}
+
+pub fn pmu_counter_start(counter_idx_base: usize, counter_idx_mask: usize, flags: usize) -> SbiRet {
+    sbi_call_4(
+        EXTENSION_PMU,
+        FUNCTION_PMU_COUNTER_START,
+        counter_idx_base,
+        counter_idx_mask,
+        flags,
+        0,
+    )
+}
+
+pub fn pmu_counter_stop(counter_idx_base: usize, counter_idx_mask: usize, flags: usize) -> SbiRet {
+    sbi_call_3(
+        EXTENSION_PMU,
+        FUNCTION_PMU_COUNTER_STOP,
+        counter_idx_base,
+        counter_idx_mask,
+        flags,
+    )
+}
+
+pub fn pmu_counter_read(counter_idx: usize) -> SbiRet {
+    sbi_call_1(EXTENSION_PMU, FUNCTION_PMU_COUNTER_FW_READ, counter_idx)
+}
+
+const FUNCTION_DBCN_CONSOLE_WRITE: usize = 0x0;
+
+pub fn console_write(num_bytes: usize, base_lo: usize, base_hi: usize) -> SbiRet {
+    sbi_call_3(
+        EXTENSION_DBCN,
+        FUNCTION_DBCN_CONSOLE_WRITE,
+        num_bytes,
+        base_lo,
+        base_hi,
+    )
+}
+
+const FUNCTION_UNMATCHED_READ_SUPERVISOR_CSR: usize = 0x0;
+const FUNCTION_UNMATCHED_DEADMAN_ENABLE: usize = 0x1;
+const FUNCTION_UNMATCHED_DEADMAN_PET: usize = 0x2;
+const FUNCTION_UNMATCHED_REQUEST_MEMORY_ATTRIBUTE: usize = 0x3;
+
+pub const MEMORY_ATTRIBUTE_CACHEABLE: usize = 0;
+pub const MEMORY_ATTRIBUTE_NON_CACHEABLE: usize = 1;
+
+const FUNCTION_UNMATCHED_READ_TRAP_PROFILE: usize = 0x4;
+const FUNCTION_UNMATCHED_WARM_RESET_HART: usize = 0x5;
+const FUNCTION_UNMATCHED_READ_STACK_WATERMARK: usize = 0x6;
+const FUNCTION_UNMATCHED_SUBMIT_DEVICE_TREE: usize = 0x7;
+const FUNCTION_UNMATCHED_GET_PLATFORM_INFO: usize = 0x8;
+const FUNCTION_UNMATCHED_READ_MEMORY_TEST_RESULT: usize = 0x9;
+const FUNCTION_UNMATCHED_READ_BOARD_TEMPERATURE: usize = 0xA;
+const FUNCTION_UNMATCHED_INJECT_PANIC: usize = 0xB;
+const FUNCTION_UNMATCHED_READ_HEAP_USAGE: usize = 0xC;
+const FUNCTION_UNMATCHED_SET_TIME_SCALE: usize = 0xD;
+const FUNCTION_UNMATCHED_READ_BUILD_CONFIG: usize = 0xE;
+
+pub const BUILD_CONFIG_FEATURES: usize = 0;
+pub const BUILD_CONFIG_HEAP_SIZE: usize = 1;
+pub const BUILD_CONFIG_STACK_SIZE: usize = 2;
+pub const BUILD_CONFIG_EMBEDDED_DTB_SIZE: usize = 3;
+
+// Feature bits in the order of the firmware's Cargo.toml
+pub const BUILD_FEATURE_NAMES: [&str; 15] = [
+    "vectored-trap",
+    "console-mirror",
+    "trap-profile",
+    "dump-device-tree",
+    "stack-watermark",
+    "memory-test",
+    "board-sensors",
+    "terminal-probe",
+    "monitor",
+    "heap-extension",
+    "panic-injection",
+    "time-scale",
+    "ordered-harts",
+    "init-faults",
+    "uart-init-faults",
+];
+
+const FUNCTION_UNMATCHED_READ_HART_EXCLUSION: usize = 0xF;
+
+// Why the firmware kept a hart out of the supervisor at boot
+pub const HART_INCLUDED: usize = 0;
+pub const HART_EXCLUDED_S7_CORE: usize = 1;
+pub const HART_EXCLUDED_DISABLED: usize = 2;
+pub const HART_EXCLUDED_MISSING: usize = 3;
+pub const HART_EXCLUDED_PANICKED: usize = 4;
+pub const HART_EXCLUDED_NOT_INITIALIZED: usize = 5;
+
+const FUNCTION_UNMATCHED_READ_EXTENSION_USAGE: usize = 0x10;
+
+// Bits of the extension usage bitmap, in the firmware's tracking order
+pub const USAGE_LEGACY_SET_TIMER: usize = 1 << 0;
+pub const USAGE_LEGACY_CONSOLE_PUTCHAR: usize = 1 << 1;
+pub const USAGE_LEGACY_CLEAR_IPI: usize = 1 << 3;
+pub const USAGE_LEGACY_SHUTDOWN: usize = 1 << 8;
+pub const USAGE_BASE: usize = 1 << 9;
+pub const USAGE_TIMER: usize = 1 << 10;
+pub const USAGE_UNMATCHED: usize = 1 << 17;
+
+const FUNCTION_UNMATCHED_READ_UART_INIT_ATTEMPTS: usize = 0x11;
+const FUNCTION_UNMATCHED_GET_HART_SET_LINE: usize = 0x12;
+const FUNCTION_UNMATCHED_READ_COLD_BOOT_TICKS: usize = 0x13;
+
+pub const TEMPERATURE_CHANNEL_BOARD: usize = 0;
+pub const TEMPERATURE_CHANNEL_SOC: usize = 1;
+
+pub const PANIC_ACTION_HALT: usize = 0;
+pub const PANIC_ACTION_REBOOT: usize = 1;
+
+pub const TRAP_KIND_MACHINE_TIMER: usize = 2;
+pub const TRAP_PROFILE_COUNT: usize = 0;
+pub const TRAP_PROFILE_MIN: usize = 1;
+pub const TRAP_PROFILE_MAX: usize = 2;
+pub const TRAP_PROFILE_AVERAGE: usize = 3;
+
+pub const DEADMAN_FLAG_RESET_ON_EXPIRE: usize = 1 << 0;
+
+pub fn read_supervisor_csr(csr: usize) -> SbiRet {
+    sbi_call_1(
+        EXTENSION_UNMATCHED,
+        FUNCTION_UNMATCHED_READ_SUPERVISOR_CSR,
+        csr,
+    )
+}
+
+pub fn deadman_enable(timeout: usize, flags: usize) -> SbiRet {
+    sbi_call_2(
+        EXTENSION_UNMATCHED,
+        FUNCTION_UNMATCHED_DEADMAN_ENABLE,
+        timeout,
+        flags,
+    )
+}
+
+pub fn deadman_pet() -> SbiRet {
+    sbi_call_0(EXTENSION_UNMATCHED, FUNCTION_UNMATCHED_DEADMAN_PET)
+}
+
+pub fn request_memory_attribute(base: usize, size: usize, attribute: usize) -> SbiRet {
+    sbi_call_3(
+        EXTENSION_UNMATCHED,
+        FUNCTION_UNMATCHED_REQUEST_MEMORY_ATTRIBUTE,
+        base,
+        size,
+        attribute,
+    )
+}
+
+pub fn warm_reset_hart(hartid: usize, entry: usize, opaque: usize) -> SbiRet {
+    sbi_call_3(
+        EXTENSION_UNMATCHED,
+        FUNCTION_UNMATCHED_WARM_RESET_HART,
+        hartid,
+        entry,
+        opaque,
+    )
+}
+
+pub fn read_trap_profile(trap_kind: usize, field: usize) -> SbiRet {
+    sbi_call_2(
+        EXTENSION_UNMATCHED,
+        FUNCTION_UNMATCHED_READ_TRAP_PROFILE,
+        trap_kind,
+        field,
+    )
+}
+
+pub fn read_stack_watermark(hartid: usize) -> SbiRet {
+    sbi_call_1(
+        EXTENSION_UNMATCHED,
+        FUNCTION_UNMATCHED_READ_STACK_WATERMARK,
+        hartid,
+    )
+}
+
+pub fn submit_device_tree(base: usize, size: usize) -> SbiRet {
+    sbi_call_2(
+        EXTENSION_UNMATCHED,
+        FUNCTION_UNMATCHED_SUBMIT_DEVICE_TREE,
+        base,
+        size,
+    )
+}
+
+pub fn get_platform_info() -> SbiRet {
+    sbi_call_0(EXTENSION_UNMATCHED, FUNCTION_UNMATCHED_GET_PLATFORM_INFO)
+}
+
+pub fn read_memory_test_result() -> SbiRet {
+    sbi_call_0(
+        EXTENSION_UNMATCHED,
+        FUNCTION_UNMATCHED_READ_MEMORY_TEST_RESULT,
+    )
+}
+
+pub fn read_board_temperature(channel: usize) -> SbiRet {
+    sbi_call_1(
+        EXTENSION_UNMATCHED,
+        FUNCTION_UNMATCHED_READ_BOARD_TEMPERATURE,
+        channel,
+    )
+}
+
+pub fn inject_panic(action: usize) -> SbiRet {
+    sbi_call_1(EXTENSION_UNMATCHED, FUNCTION_UNMATCHED_INJECT_PANIC, action)
+}
+
+pub fn read_heap_usage() -> SbiRet {
+    sbi_call_0(EXTENSION_UNMATCHED, FUNCTION_UNMATCHED_READ_HEAP_USAGE)
+}
+
+pub fn set_time_scale(numerator: usize, denominator: usize) -> SbiRet {
+    sbi_call_2(
+        EXTENSION_UNMATCHED,
+        FUNCTION_UNMATCHED_SET_TIME_SCALE,
+        numerator,
+        denominator,
+    )
+}
+
+pub fn read_build_config(field: usize) -> SbiRet {
+    sbi_call_1(
+        EXTENSION_UNMATCHED,
+        FUNCTION_UNMATCHED_READ_BUILD_CONFIG,
+        field,
+    )
+}
+
+pub fn read_hart_exclusion(hartid: usize) -> SbiRet {
+    sbi_call_1(
+        EXTENSION_UNMATCHED,
+        FUNCTION_UNMATCHED_READ_HART_EXCLUSION,
+        hartid,
+    )
+}
+
+pub fn read_extension_usage() -> SbiRet {
+    sbi_call_0(EXTENSION_UNMATCHED, FUNCTION_UNMATCHED_READ_EXTENSION_USAGE)
+}
+
+pub fn get_hart_set_line() -> SbiRet {
+    sbi_call_0(EXTENSION_UNMATCHED, FUNCTION_UNMATCHED_GET_HART_SET_LINE)
+}
+
+pub fn read_uart_init_attempts() -> SbiRet {
+    sbi_call_0(
+        EXTENSION_UNMATCHED,
+        FUNCTION_UNMATCHED_READ_UART_INIT_ATTEMPTS,
+    )
+}
+
+pub fn read_cold_boot_ticks() -> SbiRet {
+    sbi_call_0(EXTENSION_UNMATCHED, FUNCTION_UNMATCHED_READ_COLD_BOOT_TICKS)
+}
+
 #[inline(always)]
 fn sbi_call_0(extension: usize, function: usize) -> SbiRet {
     let (error, value);
@@ -257,3 +578,46 @@ fn sbi_call_3(extension: usize, function: usize, arg0: usize, arg1: usize, arg2:
     };
     SbiRet { error, value }
 }
+
+#[inline(always)]
+fn sbi_call_4(
+    extension: usize,
+    function: usize,
+    arg0: usize,
+    arg1: usize,
+    arg2: usize,
+    arg3: usize,
+) -> SbiRet {
+    let (error, value);
+    unsafe {
+        asm!(
+            "ecall",
+            in("a0") arg0, in("a1") arg1, in("a2") arg2, in("a3") arg3,
+            in("a6") function, in("a7") extension,
+            lateout("a0") error, lateout("a1") value,
+        )
+    };
+    SbiRet { error, value }
+}
+
+#[inline(always)]
+fn sbi_call_5(
+    extension: usize,
+    function: usize,
+    arg0: usize,
+    arg1: usize,
+    arg2: usize,
+    arg3: usize,
+    arg4: usize,
+) -> SbiRet {
+    let (error, value);
+    unsafe {
+        asm!(
+            "ecall",
+            in("a0") arg0, in("a1") arg1, in("a2") arg2, in("a3") arg3, in("a4") arg4,
+            in("a6") function, in("a7") extension,
+            lateout("a0") error, lateout("a1") value,
+        )
+    };
+    SbiRet { error, value }
+}
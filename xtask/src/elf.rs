@@ -0,0 +1,53 @@
+// 从固件的ELF文件中读出链接脚本定义的符号，例如bss段的结束地址ebss。
+// 只支持riscv64目标生成的小端序ELF64
+use std::{fs, path::Path};
+
+const ELF_MAGIC: &[u8; 4] = b"\x7fELF";
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const SHT_SYMTAB: u32 = 2;
+
+/// 读出符号的地址，文件格式不对或者没有这个符号时返回错误说明
+pub fn symbol_address(path: &Path, name: &str) -> Result<u64, String> {
+    let blob = fs::read(path).map_err(|e| format!("read {}: {}", path.display(), e))?;
+    if blob.len() < 64 || &blob[..4] != ELF_MAGIC || blob[4] != ELFCLASS64 || blob[5] != ELFDATA2LSB
+    {
+        return Err(format!(
+            "{} is not a little-endian ELF64 file",
+            path.display()
+        ));
+    }
+    let truncated = || format!("{} is truncated", path.display());
+    let bytes = |offset: usize, len: usize| blob.get(offset..offset.checked_add(len)?);
+    let u16_at = |offset| Some(u16::from_le_bytes(bytes(offset, 2)?.try_into().ok()?) as usize);
+    let u32_at = |offset| Some(u32::from_le_bytes(bytes(offset, 4)?.try_into().ok()?) as usize);
+    let u64_at = |offset| Some(u64::from_le_bytes(bytes(offset, 8)?.try_into().ok()?));
+    let shoff = u64_at(0x28).ok_or_else(truncated)? as usize;
+    let shentsize = u16_at(0x3A).ok_or_else(truncated)?;
+    let shnum = u16_at(0x3C).ok_or_else(truncated)?;
+    // 节头中sh_offset在0x18，sh_size在0x20，sh_link在0x28，sh_entsize在0x38
+    for index in 0..shnum {
+        let header = shoff + index * shentsize;
+        if u32_at(header + 4).ok_or_else(truncated)? != SHT_SYMTAB as usize {
+            continue;
+        }
+        let offset = u64_at(header + 0x18).ok_or_else(truncated)? as usize;
+        let size = u64_at(header + 0x20).ok_or_else(truncated)? as usize;
+        let link = u32_at(header + 0x28).ok_or_else(truncated)?;
+        let entsize = u64_at(header + 0x38).ok_or_else(truncated)? as usize;
+        let strtab = u64_at(shoff + link * shentsize + 0x18).ok_or_else(truncated)? as usize;
+        if entsize == 0 {
+            return Err(format!("{} has a malformed symbol table", path.display()));
+        }
+        // 符号项中st_name在0，st_value在8
+        for symbol in (offset..offset + size).step_by(entsize) {
+            let name_offset = strtab + u32_at(symbol).ok_or_else(truncated)?;
+            let rest = blob.get(name_offset..).ok_or_else(truncated)?;
+            let end = rest.iter().position(|&b| b == 0).ok_or_else(truncated)?;
+            if &rest[..end] == name.as_bytes() {
+                return u64_at(symbol + 8).ok_or_else(truncated);
+            }
+        }
+    }
+    Err(format!("{} has no symbol {}", path.display(), name))
+}
@@ -0,0 +1,335 @@
+// mkimage生成的FIT镜像检查：FIT镜像本身就是一个设备树，这里解析它的结构，
+// 检查默认配置引用的子镜像都存在，而且加载和入口地址合理
+use std::{fmt, fs, path::Path};
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+
+// 固件的链接地址，见rustsbi-hifive-unmatched/src/u740.ld
+const FIRMWARE_ADDRESS: u64 = 0x8000_0000;
+// 固件支持的/chosen节点中rustsbi,boot-convention的取值，见rustsbi-hifive-unmatched/src/execute.rs
+const BOOT_CONVENTIONS: [&str; 2] = ["linux", "dtb-first"];
+
+#[derive(Debug)]
+struct Node {
+    name: String,
+    props: Vec<(String, Vec<u8>)>,
+    children: Vec<Node>,
+}
+
+impl Node {
+    fn prop(&self, name: &str) -> Option<&[u8]> {
+        self.props
+            .iter()
+            .find(|(prop, _)| prop == name)
+            .map(|(_, value)| &value[..])
+    }
+
+    fn prop_str(&self, name: &str) -> Option<&str> {
+        let value = self.prop(name)?;
+        let end = value.iter().position(|&b| b == 0).unwrap_or(value.len());
+        std::str::from_utf8(&value[..end]).ok()
+    }
+
+    // 字符串列表，例如loadables可以引用多个子镜像
+    fn prop_strs(&self, name: &str) -> Vec<&str> {
+        self.prop(name)
+            .map(|value| {
+                value
+                    .split(|&b| b == 0)
+                    .filter(|s| !s.is_empty())
+                    .filter_map(|s| std::str::from_utf8(s).ok())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    // 地址可能占一个或两个cell
+    fn prop_addr(&self, name: &str) -> Option<u64> {
+        let value = self.prop(name)?;
+        match value.len() {
+            4 => Some(u32::from_be_bytes(value.try_into().ok()?) as u64),
+            8 => Some(u64::from_be_bytes(value.try_into().ok()?)),
+            _ => None,
+        }
+    }
+
+    fn child(&self, name: &str) -> Option<&Node> {
+        self.children.iter().find(|child| child.name == name)
+    }
+}
+
+pub struct FitError(String);
+
+impl fmt::Display for FitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+macro_rules! fit_error {
+    ($($arg:tt)*) => {
+        FitError(format!($($arg)*))
+    };
+}
+
+/// 检查FIT镜像，成功时返回默认配置引用的子镜像的说明，每个子镜像一行。
+/// linked_end是固件链接出的结束地址，包括不在镜像中的bss段
+pub fn check_image(path: &Path, linked_end: u64) -> Result<Vec<String>, FitError> {
+    let blob = fs::read(path).map_err(|e| fit_error!("read {}: {}", path.display(), e))?;
+    let root = parse(&blob)?;
+    let images = root
+        .child("images")
+        .ok_or_else(|| fit_error!("no /images node"))?;
+    let configurations = root
+        .child("configurations")
+        .ok_or_else(|| fit_error!("no /configurations node"))?;
+    let default = configurations
+        .prop_str("default")
+        .ok_or_else(|| fit_error!("no default configuration"))?;
+    let config = configurations
+        .child(default)
+        .ok_or_else(|| fit_error!("default configuration '{}' does not exist", default))?;
+    let image = |name: &str| {
+        images.child(name).ok_or_else(|| {
+            fit_error!(
+                "configuration '{}' refers to missing image '{}'",
+                default,
+                name
+            )
+        })
+    };
+    let mut report = Vec::new();
+
+    // 固件必须加载到链接地址，并且从那里开始执行
+    let firmware_name = config
+        .prop_str("firmware")
+        .ok_or_else(|| fit_error!("configuration '{}' has no firmware", default))?;
+    let firmware = image(firmware_name)?;
+    let firmware_size = image_data(firmware)?.len() as u64;
+    if firmware_size == 0 {
+        return Err(fit_error!("firmware image '{}' is empty", firmware_name));
+    }
+    for prop in ["load", "entry"] {
+        match firmware.prop_addr(prop) {
+            Some(FIRMWARE_ADDRESS) => {}
+            Some(addr) => {
+                return Err(fit_error!(
+                    "firmware image '{}' {} is {:#x}, expected {:#x}",
+                    firmware_name,
+                    prop,
+                    addr,
+                    FIRMWARE_ADDRESS
+                ))
+            }
+            None => {
+                return Err(fit_error!(
+                    "firmware image '{}' has no {}",
+                    firmware_name,
+                    prop
+                ))
+            }
+        }
+    }
+    // 固件占用的内存到bss段的末尾为止，比镜像中的数据长
+    let firmware_end = (FIRMWARE_ADDRESS + firmware_size).max(linked_end);
+    report.push(format!(
+        "{}, resident up to {:#x}",
+        describe("firmware", firmware_name, firmware, firmware_size),
+        firmware_end
+    ));
+
+    // 内核和其它加载的镜像必须有加载地址，而且不能和固件重叠
+    let mut loaded: Vec<(&str, &str)> = Vec::new();
+    if let Some(kernel) = config.prop_str("kernel") {
+        loaded.push(("kernel", kernel));
+    }
+    for loadable in config.prop_strs("loadables") {
+        loaded.push(("loadable", loadable));
+    }
+    for (role, name) in loaded {
+        let node = image(name)?;
+        let size = image_data(node)?.len() as u64;
+        let load = node
+            .prop_addr("load")
+            .ok_or_else(|| fit_error!("{} image '{}' has no load address", role, name))?;
+        if load < firmware_end && FIRMWARE_ADDRESS < load + size {
+            return Err(fit_error!(
+                "{} image '{}' at {:#x} ~ {:#x} overlaps firmware {:#x} ~ {:#x}",
+                role,
+                name,
+                load,
+                load + size,
+                FIRMWARE_ADDRESS,
+                firmware_end
+            ));
+        }
+        if let Some(entry) = node.prop_addr("entry") {
+            if !(load..load + size).contains(&entry) {
+                return Err(fit_error!(
+                    "{} image '{}' entry {:#x} is outside its data {:#x} ~ {:#x}",
+                    role,
+                    name,
+                    entry,
+                    load,
+                    load + size
+                ));
+            }
+        }
+        report.push(describe(role, name, node, size));
+    }
+
+    // 设备树镜像本身也要是合法的设备树。SPL只把这个设备树交给固件，
+    // 因此进入内核时的约定写在它的/chosen节点中，这里检查固件能否识别
+    if let Some(fdt_name) = config.prop_str("fdt") {
+        let node = image(fdt_name)?;
+        let data = image_data(node)?;
+        if data.len() < 4 || u32::from_be_bytes(data[..4].try_into().unwrap()) != FDT_MAGIC {
+            return Err(fit_error!("fdt image '{}' is not a device tree", fdt_name));
+        }
+        let tree = parse(data).map_err(|e| fit_error!("fdt image '{}': {}", fdt_name, e))?;
+        let convention = tree
+            .child("chosen")
+            .and_then(|chosen| chosen.prop_str("rustsbi,boot-convention"))
+            .unwrap_or("linux");
+        if !BOOT_CONVENTIONS.contains(&convention) {
+            return Err(fit_error!(
+                "fdt image '{}' has unknown boot convention '{}', expected one of {:?}",
+                fdt_name,
+                convention,
+                BOOT_CONVENTIONS
+            ));
+        }
+        report.push(format!(
+            "{}, boot convention {}",
+            describe("fdt", fdt_name, node, data.len() as u64),
+            convention
+        ));
+    }
+    Ok(report)
+}
+
+fn image_data(node: &Node) -> Result<&[u8], FitError> {
+    node.prop("data").ok_or_else(|| {
+        // mkimage -E 会把数据放在设备树之外，这里只支持数据嵌入在镜像中的格式
+        fit_error!("image '{}' has no embedded data", node.name)
+    })
+}
+
+fn describe(role: &str, name: &str, node: &Node, size: u64) -> String {
+    let addr = |prop: &str| match node.prop_addr(prop) {
+        Some(addr) => format!("{:#x}", addr),
+        None => "-".to_string(),
+    };
+    format!(
+        "{:<8} {:<12} type {:<10} load {:<12} entry {:<12} {} bytes",
+        role,
+        name,
+        node.prop_str("type").unwrap_or("-"),
+        addr("load"),
+        addr("entry"),
+        size
+    )
+}
+
+fn parse(blob: &[u8]) -> Result<Node, FitError> {
+    let field = |index: usize| -> Result<usize, FitError> {
+        let bytes = blob
+            .get(index * 4..index * 4 + 4)
+            .ok_or_else(|| fit_error!("truncated device tree header"))?;
+        Ok(u32::from_be_bytes(bytes.try_into().unwrap()) as usize)
+    };
+    if field(0)? != FDT_MAGIC as usize {
+        return Err(fit_error!("not a FIT image, bad magic"));
+    }
+    if field(1)? > blob.len() {
+        return Err(fit_error!("truncated FIT image"));
+    }
+    let off_struct = field(2)?;
+    let off_strings = field(3)?;
+    let mut parser = Parser {
+        blob,
+        offset: off_struct,
+        off_strings,
+    };
+    if parser.token()? != FDT_BEGIN_NODE {
+        return Err(fit_error!("device tree does not start with a node"));
+    }
+    let root = parser.node()?;
+    if parser.token()? != FDT_END {
+        return Err(fit_error!("data after the root node"));
+    }
+    Ok(root)
+}
+
+struct Parser<'a> {
+    blob: &'a [u8],
+    offset: usize,
+    off_strings: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], FitError> {
+        let bytes = self
+            .blob
+            .get(self.offset..self.offset + len)
+            .ok_or_else(|| fit_error!("truncated structure block"))?;
+        // 每一项都对齐到4个字节
+        self.offset = (self.offset + len + 3) & !3;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, FitError> {
+        Ok(u32::from_be_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn token(&mut self) -> Result<u32, FitError> {
+        loop {
+            match self.u32()? {
+                FDT_NOP => continue,
+                token => return Ok(token),
+            }
+        }
+    }
+
+    fn cstr(&self, offset: usize) -> Result<String, FitError> {
+        let rest = self
+            .blob
+            .get(offset..)
+            .ok_or_else(|| fit_error!("string offset out of range"))?;
+        let end = rest
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or_else(|| fit_error!("unterminated string"))?;
+        Ok(String::from_utf8_lossy(&rest[..end]).into_owned())
+    }
+
+    // 调用时已经读过FDT_BEGIN_NODE
+    fn node(&mut self) -> Result<Node, FitError> {
+        let name = self.cstr(self.offset)?;
+        self.bytes(name.len() + 1)?;
+        let mut node = Node {
+            name,
+            props: Vec::new(),
+            children: Vec::new(),
+        };
+        loop {
+            match self.token()? {
+                FDT_PROP => {
+                    let len = self.u32()? as usize;
+                    let name_offset = self.u32()? as usize;
+                    let value = self.bytes(len)?.to_vec();
+                    let name = self.cstr(self.off_strings + name_offset)?;
+                    node.props.push((name, value));
+                }
+                FDT_BEGIN_NODE => node.children.push(self.node()?),
+                FDT_END_NODE => return Ok(node),
+                token => return Err(fit_error!("unexpected token {:#x}", token)),
+            }
+        }
+    }
+}
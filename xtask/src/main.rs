@@ -1,15 +1,20 @@
 use std::fmt;
 use std::{
-    env,
+    env, fs,
     path::{Path, PathBuf},
     process::{self, Command},
 };
 
 use clap::{clap_app, crate_authors, crate_description, crate_version};
 
+mod elf;
+mod fit;
+
 #[derive(Debug)]
 struct XtaskEnv {
     compile_mode: CompileMode,
+    features: Option<String>,
+    image_align: usize,
 }
 
 #[derive(Debug)]
@@ -27,7 +32,8 @@ impl fmt::Display for CompileMode {
     }
 }
 
-const DEFAULT_TARGET: &'static str = "riscv64imac-unknown-none-elf";
+const DEFAULT_TARGET: &str = "riscv64imac-unknown-none-elf";
+const DEFAULT_IMAGE_ALIGN: usize = 512; // SD卡的块大小
 
 fn main() {
     let matches = clap_app!(xtask =>
@@ -37,6 +43,8 @@ fn main() {
         (@subcommand make =>
             (about: "Build project")
             (@arg release: --release "Build artifacts in release mode, with optimizations")
+            (@arg features: --features +takes_value "Space or comma separated list of firmware features to activate")
+            (@arg align: --align +takes_value "Pad the firmware binary to a multiple of this many bytes (default 512)")
         )
         (@subcommand asm =>
             (about: "View asm code for project")
@@ -45,20 +53,36 @@ fn main() {
         (@subcommand image =>
             (about: "Build SD card partition image")
             (@arg PAYLOAD: "Set the build payload, may be 'test-kernel'")
+            (@arg dtb_first: --("dtb-first") "With the test kernel, pack a device tree selecting the dtb-first boot convention")
+            (@arg hart4_disabled: --("hart4-disabled") conflicts_with[dtb_first] "With the test kernel, pack a device tree disabling hart 4")
+            (@arg rescue: --rescue conflicts_with[dtb_first hart4_disabled] "With the test kernel, pack an unfetchable kernel entry and a rescue entry in the device tree")
             (@arg release: --release "Build artifacts in release mode, with optimizations")
+            (@arg features: --features +takes_value "Space or comma separated list of firmware features to activate")
+            (@arg align: --align +takes_value "Pad the firmware binary to a multiple of this many bytes (default 512)")
         )
         (@subcommand gdb =>
             (about: "Run GDB debugger")
         )
+        (@subcommand lint =>
+            (about: "Run clippy on firmware and test kernel for the target")
+            (@arg release: --release "Lint artifacts in release mode, with optimizations")
+            (@arg features: --features +takes_value "Space or comma separated list of firmware features to activate")
+        )
     )
     .get_matches();
     let mut xtask_env = XtaskEnv {
         compile_mode: CompileMode::Debug,
+        features: None,
+        image_align: DEFAULT_IMAGE_ALIGN,
     };
     if let Some(matches) = matches.subcommand_matches("make") {
         if matches.is_present("release") {
             xtask_env.compile_mode = CompileMode::Release;
         }
+        xtask_env.features = matches.value_of("features").map(str::to_string);
+        if let Some(align) = matches.value_of("align") {
+            xtask_env.image_align = parse_image_align(align);
+        }
         eprintln!("xtask make: mode: {:?}", xtask_env.compile_mode);
         xtask_build_sbi(&xtask_env);
         xtask_binary_sbi(&xtask_env);
@@ -73,13 +97,26 @@ fn main() {
         if matches.is_present("release") {
             xtask_env.compile_mode = CompileMode::Release;
         }
+        xtask_env.features = matches.value_of("features").map(str::to_string);
+        if let Some(align) = matches.value_of("align") {
+            xtask_env.image_align = parse_image_align(align);
+        }
         eprintln!("xtask image: mode: {:?}", xtask_env.compile_mode);
         xtask_build_sbi(&xtask_env);
         xtask_binary_sbi(&xtask_env);
         if matches.value_of("PAYLOAD") == Some("test-kernel") {
             xtask_build_test_kernel(&xtask_env);
             xtask_binary_test_kernel(&xtask_env);
-            xtask_sd_image_test_kernel(&xtask_env);
+            let variant = if matches.is_present("dtb_first") {
+                "-dtb-first"
+            } else if matches.is_present("hart4_disabled") {
+                "-hart4-disabled"
+            } else if matches.is_present("rescue") {
+                "-rescue"
+            } else {
+                ""
+            };
+            xtask_sd_image_test_kernel(&xtask_env, variant);
         } else {
             xtask_sd_image(&xtask_env);
         }
@@ -88,6 +125,19 @@ fn main() {
         xtask_build_sbi(&xtask_env);
         xtask_binary_sbi(&xtask_env);
         xtask_unmatched_gdb(&xtask_env);
+    } else if let Some(matches) = matches.subcommand_matches("lint") {
+        if matches.is_present("release") {
+            xtask_env.compile_mode = CompileMode::Release;
+        }
+        xtask_env.features = matches.value_of("features").map(str::to_string);
+        eprintln!("xtask lint: mode: {:?}", xtask_env.compile_mode);
+        // 两个包都检查完再退出，一次看到所有的问题
+        let sbi_ok = xtask_clippy(&xtask_env, "rustsbi-hifive-unmatched", true);
+        let test_kernel_ok = xtask_clippy(&xtask_env, "test-kernel", false);
+        if !(sbi_ok && test_kernel_ok) {
+            eprintln!("cargo clippy failed");
+            process::exit(1);
+        }
     } else {
         eprintln!("Use `cargo make` to build, `cargo xtask --help` for help")
     }
@@ -106,6 +156,9 @@ fn xtask_build_sbi(xtask_env: &XtaskEnv) {
     }
     command.args(&["--package", "rustsbi-hifive-unmatched"]);
     command.args(&["--target", DEFAULT_TARGET]);
+    if let Some(features) = &xtask_env.features {
+        command.args(&["--features", features]);
+    }
     let status = command.status().unwrap();
     if !status.success() {
         eprintln!("cargo build failed");
@@ -113,6 +166,28 @@ fn xtask_build_sbi(xtask_env: &XtaskEnv) {
     }
 }
 
+// 固件功能只传给固件包，测试内核没有可选功能
+fn xtask_clippy(xtask_env: &XtaskEnv, package: &str, with_features: bool) -> bool {
+    let cargo = env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    let mut command = Command::new(cargo);
+    command.current_dir(project_root().join(package));
+    command.arg("clippy");
+    match xtask_env.compile_mode {
+        CompileMode::Debug => {}
+        CompileMode::Release => {
+            command.arg("--release");
+        }
+    }
+    command.args(&["--package", package]);
+    command.args(&["--target", DEFAULT_TARGET]);
+    if with_features {
+        if let Some(features) = &xtask_env.features {
+            command.args(&["--features", features]);
+        }
+    }
+    command.status().unwrap().success()
+}
+
 fn xtask_binary_sbi(xtask_env: &XtaskEnv) {
     let objcopy = "rust-objcopy";
     let status = Command::new(objcopy)
@@ -128,6 +203,43 @@ fn xtask_binary_sbi(xtask_env: &XtaskEnv) {
         eprintln!("objcopy binary failed");
         process::exit(1);
     }
+    xtask_pad_sbi(xtask_env);
+}
+
+// 把固件二进制文件用0填充到块大小的整数倍
+fn xtask_pad_sbi(xtask_env: &XtaskEnv) {
+    let path = dist_dir(xtask_env).join("rustsbi-hifive-unmatched.bin");
+    let original = fs::read(&path).expect("read firmware binary");
+    let align = xtask_env.image_align;
+    let padded_len = (original.len() + align - 1) / align * align;
+    let mut padded = original.clone();
+    padded.resize(padded_len, 0);
+    fs::write(&path, &padded).expect("write padded firmware binary");
+    // 填充只能追加在末尾，原有的内容必须原样保留在原来的位置
+    let written = fs::read(&path).expect("read padded firmware binary");
+    if written.len() != padded_len || written[..original.len()] != original[..] {
+        eprintln!("padding corrupted firmware binary");
+        process::exit(1);
+    }
+    eprintln!(
+        "xtask: padded rustsbi-hifive-unmatched.bin from {} to {} bytes ({}-byte aligned)",
+        original.len(),
+        padded_len,
+        align
+    );
+}
+
+fn parse_image_align(align: &str) -> usize {
+    match align.parse::<usize>() {
+        Ok(align) if align > 0 => align,
+        _ => {
+            eprintln!(
+                "invalid alignment '{}', expected a positive number of bytes",
+                align
+            );
+            process::exit(1);
+        }
+    }
 }
 
 fn xtask_asm_sbi(xtask_env: &XtaskEnv) {
@@ -175,6 +287,35 @@ fn xtask_sd_image(xtask_env: &XtaskEnv) {
         eprintln!("mkimage failed with status {}", status);
         process::exit(status.code().unwrap_or(1));
     }
+    xtask_check_fit(
+        xtask_env,
+        &project_root().join("target/sd-card-partition-2.img"),
+    );
+}
+
+// .its写错时mkimage通常不会报错，生成以后检查一遍，避免烧录以后才发现无法启动。
+// 固件运行时还使用bin文件之后的bss段（栈、堆和设备树缓冲区），按链接出的ebss检查重叠
+fn xtask_check_fit(xtask_env: &XtaskEnv, path: &Path) {
+    let firmware_elf = dist_dir(xtask_env).join("rustsbi-hifive-unmatched");
+    let firmware_end = match elf::symbol_address(&firmware_elf, "ebss") {
+        Ok(ebss) => ebss,
+        Err(e) => {
+            eprintln!("find firmware end: {}", e);
+            process::exit(1);
+        }
+    };
+    match fit::check_image(path, firmware_end) {
+        Ok(report) => {
+            eprintln!("xtask: checked {}", path.display());
+            for line in report {
+                eprintln!("xtask:   {}", line);
+            }
+        }
+        Err(e) => {
+            eprintln!("invalid FIT image {}: {}", path.display(), e);
+            process::exit(1);
+        }
+    }
 }
 
 fn xtask_build_test_kernel(xtask_env: &XtaskEnv) {
@@ -214,14 +355,17 @@ fn xtask_binary_test_kernel(xtask_env: &XtaskEnv) {
     }
 }
 
-fn xtask_sd_image_test_kernel(xtask_env: &XtaskEnv) {
+// variant不为空时使用另一份.its，和默认的镜像有一处不同：
+// -dtb-first让固件按dtb-first的约定进入测试内核，-hart4-disabled关闭了第4个核，
+// -rescue的内核入口不可用，固件要从设备树中的备用入口进入测试内核
+fn xtask_sd_image_test_kernel(xtask_env: &XtaskEnv, variant: &str) {
     let status = find_mkimage()
         .expect("find mkimage tool")
         .current_dir(project_root())
         .arg("-f")
         .arg(&format!(
-            "test-kernel/sd-image-{}.its",
-            xtask_env.compile_mode
+            "test-kernel/sd-image-{}{}.its",
+            xtask_env.compile_mode, variant
         ))
         .arg("target/rustsbi-with-test-kernel.img")
         .status()
@@ -231,6 +375,10 @@ fn xtask_sd_image_test_kernel(xtask_env: &XtaskEnv) {
         eprintln!("mkimage failed with status {}", status);
         process::exit(status.code().unwrap_or(1));
     }
+    xtask_check_fit(
+        xtask_env,
+        &project_root().join("target/rustsbi-with-test-kernel.img"),
+    );
 }
 
 fn dist_dir(xtask_env: &XtaskEnv) -> PathBuf {
@@ -264,5 +412,5 @@ fn find_mkimage() -> std::io::Result<Command> {
             return Ok(cmd);
         }
     }
-    return Err(mkimage.unwrap_err());
+    Err(mkimage.unwrap_err())
 }